@@ -0,0 +1,36 @@
+// Measures how long it takes to clone a handle, both by copying the identity
+// of the original (`try_clone`) and by querying it again (`try_clone_fresh`).
+// The difference is the cost of the extra system call.
+//
+// Run with `cargo bench --bench clone`.
+
+use std::env;
+use std::io;
+use std::time::Instant;
+
+use same_file::Handle;
+
+const ITERS: u32 = 100_000;
+
+fn main() -> io::Result<()> {
+    let handle = Handle::from_path(env::current_dir()?.join("Cargo.toml"))?;
+    bench("try_clone", || handle.try_clone())?;
+    bench("try_clone_fresh", || handle.try_clone_fresh())?;
+    Ok(())
+}
+
+fn bench<F>(name: &str, mut clone: F) -> io::Result<()>
+where
+    F: FnMut() -> io::Result<Handle>,
+{
+    let start = Instant::now();
+    for _ in 0..ITERS {
+        let _handle = clone()?;
+    }
+    println!(
+        "{}: {:.2} ns/clone",
+        name,
+        start.elapsed().as_nanos() as f64 / f64::from(ITERS),
+    );
+    Ok(())
+}
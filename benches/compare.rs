@@ -0,0 +1,79 @@
+// Measures how long it takes to compare file keys, both for keys on the same
+// volume and for keys on different volumes. Comparing keys on different
+// volumes should be cheaper, since the index is never looked at.
+//
+// Run with `cargo bench --bench compare`.
+
+use std::collections::HashSet;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use same_file::{FileKey, Handle};
+
+const ITERS: u32 = 10_000_000;
+
+fn main() -> io::Result<()> {
+    let mut candidates = vec![env::temp_dir(), env::current_dir()?];
+    for path in &["/", "/dev", "/proc", "/run", "C:\\", "D:\\"] {
+        candidates.push(PathBuf::from(path));
+    }
+    let mut handles = vec![];
+    for path in candidates {
+        if let Ok(handle) = Handle::from_path(&path) {
+            handles.push((path, handle));
+        }
+    }
+
+    let (path, first) = &handles[0];
+    let here = env::current_dir()?.join("Cargo.toml");
+    let same_volume = Handle::from_path(&here)?;
+    if first.same_volume(&same_volume) {
+        bench("same volume", key(first), key(&same_volume));
+    }
+    match handles.iter().find(|(_, h)| !first.same_volume(h)) {
+        Some((other, h)) => {
+            println!("{} vs {}", path.display(), other.display());
+            bench("different volume", key(first), key(h));
+        }
+        None => println!("no candidates on different volumes were found"),
+    }
+    Ok(())
+}
+
+fn key(handle: &Handle) -> FileKey {
+    handle.key().expect("handle has a key")
+}
+
+fn bench(name: &str, k1: FileKey, k2: FileKey) {
+    let keys = [k1, k2];
+    let mut equal = 0u32;
+    let start = Instant::now();
+    for i in 0..ITERS {
+        let (a, b) = (&keys[(i & 1) as usize], &keys[1 - (i & 1) as usize]);
+        if a == b {
+            equal += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{}: {:.2} ns/comparison ({} equal)",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(ITERS),
+        equal,
+    );
+
+    let start = Instant::now();
+    let mut set = HashSet::new();
+    for _ in 0..ITERS / 10 {
+        set.insert(k1);
+        set.insert(k2);
+    }
+    println!(
+        "{}: {:.2} ns/insert ({} distinct)",
+        name,
+        start.elapsed().as_nanos() as f64 / f64::from(ITERS / 5),
+        set.len(),
+    );
+}
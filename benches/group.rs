@@ -0,0 +1,69 @@
+// Measures how long it takes to group the paths of a synthetic tree by file,
+// both sequentially (`group_same_files`) and in parallel
+// (`group_same_files_par`). A tenth of the files have an extra hard link, so
+// that some groups have more than one path.
+//
+// Run with `cargo bench --bench group --features rayon`.
+
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use same_file::{group_same_files, group_same_files_par};
+
+const DIRS: usize = 100;
+const FILES_PER_DIR: usize = 200;
+
+fn main() -> io::Result<()> {
+    let root =
+        env::temp_dir().join(format!("same-file-group-{}", process::id()));
+    let result = create(&root).and_then(|paths| run(&paths));
+    fs::remove_dir_all(&root)?;
+    result
+}
+
+fn create(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for d in 0..DIRS {
+        let dir = root.join(d.to_string());
+        fs::create_dir_all(&dir)?;
+        for f in 0..FILES_PER_DIR {
+            let file = dir.join(f.to_string());
+            File::create(&file)?;
+            paths.push(file.clone());
+            if f % 10 == 0 {
+                let link = dir.join(format!("{}.link", f));
+                fs::hard_link(&file, &link)?;
+                paths.push(link);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+fn run(paths: &[PathBuf]) -> io::Result<()> {
+    let start = Instant::now();
+    let sequential = group_same_files(paths)?;
+    let elapsed = start.elapsed();
+    println!(
+        "sequential: {:.2} ms ({} paths, {} groups)",
+        elapsed.as_secs_f64() * 1000.0,
+        paths.len(),
+        sequential.len(),
+    );
+
+    let start = Instant::now();
+    let parallel = group_same_files_par(paths)?;
+    let elapsed = start.elapsed();
+    println!(
+        "parallel: {:.2} ms ({} paths, {} groups)",
+        elapsed.as_secs_f64() * 1000.0,
+        paths.len(),
+        parallel.len(),
+    );
+    assert_eq!(sequential, parallel);
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use same_file::{FileKey, Handle};
+
+fn main() {
+    if let Err(err) = run() {
+        println!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> io::Result<()> {
+    // Run with `cargo run --example group_by_key -- <path>...` to see which
+    // of the given paths refer to the same file.
+    //
+    // Only keys are kept around, so no file stays open once it has been
+    // grouped. This assumes no file is deleted while this runs, since its
+    // key could otherwise be reused by another file.
+    let mut groups: HashMap<FileKey, Vec<PathBuf>> = HashMap::new();
+    for path in env::args_os().skip(1).map(PathBuf::from) {
+        let handle = Handle::from_path(&path)?;
+        if let Some(key) = handle.key() {
+            groups.entry(key).or_default().push(path);
+        }
+    }
+    for paths in groups.values() {
+        println!("{:?}", paths);
+    }
+    Ok(())
+}
@@ -0,0 +1,420 @@
+// Hermit is a unikernel. It isn't a Unix as far as `cfg(unix)` is concerned,
+// and so std doesn't provide `std::os::unix::fs::MetadataExt`, but it does
+// provide `fstat` through libc and its files have a device and an inode
+// number. So this is a thin version of the Unix implementation that calls
+// `fstat` directly.
+//
+// Hermit's file system support is limited, so a few things that the Unix
+// implementation does are not possible here:
+//
+// * There is no `O_NOFOLLOW` or `O_PATH`, so a file that cannot be opened for
+//   reading cannot be compared at all, and not following symbolic links is
+//   subject to races.
+// * There are no FIFOs or device nodes to take care with when opening.
+// * Generation numbers, file system IDs and kernel handles are not available.
+//
+// There are no snapshot handles either, so the only handles without a file
+// (and without an identity) are dangling ones.
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::mem;
+use std::os::hermit::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub(crate) type RawDescriptor = RawFd;
+
+// The device number of dangling handles. No actual file is ever on `NODEV`.
+const NODEV: u64 = u64::MAX;
+
+#[derive(Debug)]
+pub struct Handle {
+    file: Option<File>,
+    // If is_std is true, then we don't drop the corresponding File since it
+    // will close the handle.
+    is_std: bool,
+    key: Key,
+    // The modification time when the handle was constructed.
+    modified: Option<SystemTime>,
+    // The canonical path the handle was opened with, if any.
+    path: Option<PathBuf>,
+    kind: crate::FileKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialOrd)]
+pub struct Key {
+    dev: u64,
+    ino: u64,
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        if self.dev != other.dev {
+            return false;
+        }
+        self.ino == other.ino
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.dev.hash(state);
+        self.ino.hash(state);
+    }
+}
+
+impl Key {
+    fn from_file(file: &File) -> io::Result<Key> {
+        Ok(stat(file)?.key)
+    }
+
+    /// Returns false if this key doesn't identify a file, which is only the
+    /// case for dangling handles.
+    fn is_known(&self) -> bool {
+        self.dev != NODEV
+    }
+
+    pub fn parts(&self) -> (u64, u128) {
+        (self.dev, u128::from(self.ino))
+    }
+
+    /// The inverse of `parts`, which fails if the index doesn't fit in an
+    /// inode number.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(volume: u64, index: u128) -> Option<Key> {
+        u64::try_from(index).ok().map(|ino| Key { dev: volume, ino })
+    }
+}
+
+pub(crate) fn files_eq(file1: &File, file2: &File) -> io::Result<bool> {
+    Ok(Key::from_file(file1)? == Key::from_file(file2)?)
+}
+
+/// The parts of a file's metadata that a handle records.
+struct Stat {
+    key: Key,
+    kind: crate::FileKind,
+    modified: Option<SystemTime>,
+}
+
+/// Return the key, the type and the modification time of the given file.
+fn stat(file: &File) -> io::Result<Stat> {
+    let mut st: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(file.as_raw_fd(), &mut st) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let kind = match st.st_mode & libc::S_IFMT {
+        libc::S_IFDIR => crate::FileKind::Dir,
+        libc::S_IFREG => crate::FileKind::File,
+        _ => crate::FileKind::Other,
+    };
+    let mtime = st.st_mtim;
+    let modified = if mtime.tv_sec >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::new(
+            mtime.tv_sec as u64,
+            mtime.tv_nsec as u32,
+        ))
+    } else {
+        SystemTime::UNIX_EPOCH
+            .checked_sub(Duration::from_secs(mtime.tv_sec.unsigned_abs()))
+            .and_then(|t| {
+                t.checked_add(Duration::from_nanos(mtime.tv_nsec as u64))
+            })
+    };
+    Ok(Stat { key: Key { dev: st.st_dev, ino: st.st_ino }, kind, modified })
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if self.is_std {
+            // `file` is None if it was already given away, e.g., by
+            // `into_raw_fd`.
+            if let Some(file) = self.file.take() {
+                let _ = file.into_raw_fd();
+            }
+        }
+    }
+}
+
+impl Eq for Handle {}
+
+impl PartialEq for Handle {
+    fn eq(&self, other: &Handle) -> bool {
+        // Need this branch to satisfy `Eq` for dangling handles.
+        if !self.key.is_known() || !other.key.is_known() {
+            let equal = std::ptr::eq(self, other);
+            event!(
+                target: "same_file::compare",
+                tracing::Level::TRACE,
+                equal,
+                keyless = true,
+            );
+            return equal;
+        }
+        let equal = self.kind == other.kind && self.key == other.key;
+        event!(target: "same_file::compare", tracing::Level::TRACE, equal);
+        equal
+    }
+}
+
+impl AsRawFd for crate::Handle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_file().as_raw_fd()
+    }
+}
+
+impl AsRawFd for crate::RawDescriptor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Hash for Handle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+impl Handle {
+    pub fn from_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        Handle::from_path_detailed(p).map_err(io::Error::from)
+    }
+
+    pub fn from_path_detailed<P: AsRef<Path>>(
+        p: P,
+    ) -> Result<Handle, crate::Error> {
+        let file = File::open(p).map_err(crate::Error::open)?;
+        Handle::from_file(file).map_err(crate::Error::stat)
+    }
+
+    pub fn from_path_with<P: AsRef<Path>>(
+        p: P,
+        opts: &crate::HandleOptions,
+    ) -> io::Result<Handle> {
+        let p = p.as_ref();
+        // Without `O_NOFOLLOW`, this check is racy, but it's the best that
+        // can be done.
+        if !opts.follow_symlinks && fs::symlink_metadata(p)?.is_symlink() {
+            return Err(io::Error::from_raw_os_error(libc::ELOOP));
+        }
+        if opts.require_directory {
+            return Handle::from_dir_path(p);
+        }
+        Handle::from_path(p)
+    }
+
+    pub fn from_link<P: AsRef<Path>>(_p: P) -> io::Result<Handle> {
+        Err(crate::unsupported("symbolic link handles"))
+    }
+
+    pub fn from_dir_entry(entry: &fs::DirEntry) -> io::Result<Handle> {
+        Handle::from_path(entry.path())
+    }
+
+    pub fn from_dir_entry_snapshot(
+        entry: &fs::DirEntry,
+    ) -> io::Result<Handle> {
+        Handle::from_dir_entry(entry)
+    }
+
+    pub fn from_path_canonical<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        let p = fs::canonicalize(p)?;
+        let mut h = Handle::from_path(&p)?;
+        h.path = Some(p);
+        Ok(h)
+    }
+
+    pub fn canonical_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn from_path_retry<P: AsRef<Path>>(
+        p: P,
+        _attempts: usize,
+        _delay: Duration,
+    ) -> io::Result<Handle> {
+        Handle::from_path(p)
+    }
+
+    pub fn from_dir_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        let h = Handle::from_path(p)?;
+        if !h.as_file().metadata()?.is_dir() {
+            return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+        }
+        Ok(h)
+    }
+
+    pub fn from_file(file: File) -> io::Result<Handle> {
+        let Stat { key, kind, modified } = stat(&file)?;
+        event!(
+            target: "same_file::information",
+            tracing::Level::TRACE,
+            key = ?Some(key),
+        );
+        Ok(Handle {
+            file: Some(file),
+            is_std: false,
+            key,
+            modified,
+            path: None,
+            kind,
+        })
+    }
+
+    pub fn dangling() -> Handle {
+        Handle {
+            file: None,
+            is_std: false,
+            // The inode number is only there to spread dangling handles out
+            // when hashed.
+            key: Key { dev: NODEV, ino: crate::dangling_id() },
+            modified: None,
+            path: None,
+            kind: crate::FileKind::Other,
+        }
+    }
+
+    pub fn from_std(file: File) -> io::Result<Handle> {
+        Handle::from_file(file).map(|mut h| {
+            h.is_std = true;
+            h
+        })
+    }
+
+    pub fn stdin() -> io::Result<Handle> {
+        Handle::from_std(unsafe { File::from_raw_fd(0) })
+    }
+
+    pub fn stdout() -> io::Result<Handle> {
+        Handle::from_std(unsafe { File::from_raw_fd(1) })
+    }
+
+    pub fn stderr() -> io::Result<Handle> {
+        Handle::from_std(unsafe { File::from_raw_fd(2) })
+    }
+
+    pub fn raw(&self) -> RawFd {
+        self.file.as_ref().map_or(-1, |file| file.as_raw_fd())
+    }
+
+    pub fn as_file(&self) -> &File {
+        // This only panics for dangling handles, which is documented.
+        self.file.as_ref().expect("dangling handle has no file")
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut File {
+        // This only panics for dangling handles, which is documented.
+        self.file.as_mut().expect("dangling handle has no file")
+    }
+
+    pub fn into_file(mut self) -> Result<File, Handle> {
+        if self.is_std {
+            return Err(self);
+        }
+        match self.file.take() {
+            Some(file) => Ok(file),
+            None => Err(self),
+        }
+    }
+
+    pub fn as_borrowed_file(&self) -> Option<&File> {
+        if self.is_std {
+            self.file.as_ref()
+        } else {
+            None
+        }
+    }
+
+    pub fn reopen(&self, _options: &fs::OpenOptions) -> io::Result<File> {
+        Err(crate::unsupported("reopened files"))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Handle> {
+        if !self.key.is_known() {
+            return Ok(Handle::dangling());
+        }
+        Ok(Handle {
+            file: Some(self.as_file().try_clone()?),
+            is_std: false,
+            key: self.key,
+            modified: self.modified,
+            path: self.path.clone(),
+            kind: self.kind,
+        })
+    }
+
+    pub fn try_clone_fresh(&self) -> io::Result<Handle> {
+        if !self.key.is_known() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "dangling handle has no open file",
+            ));
+        }
+        let mut h = Handle::from_file(self.as_file().try_clone()?)?;
+        h.path = self.path.clone();
+        Ok(h)
+    }
+
+    pub fn modified_at_open(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        false
+    }
+
+    pub fn is_path_based(&self) -> bool {
+        false
+    }
+
+    pub fn kind(&self) -> crate::FileKind {
+        self.kind
+    }
+
+    pub fn key(&self) -> Option<Key> {
+        if self.key.is_known() {
+            Some(self.key)
+        } else {
+            None
+        }
+    }
+
+    /// Hermit doesn't report inode generation numbers.
+    pub fn generation(&self) -> Option<u64> {
+        None
+    }
+
+    pub fn link_id(&self) -> io::Result<u64> {
+        Err(crate::unsupported("link identifiers"))
+    }
+
+    pub fn same_as_file(&self, file: &File) -> io::Result<bool> {
+        Ok(self.key == Key::from_file(file)?)
+    }
+
+    pub fn weak_eq(&self, other: &Handle) -> bool {
+        // Every handle but a dangling one has a key on Hermit.
+        self.key.is_known() && self == other
+    }
+
+    pub fn same_volume(&self, other: &Handle) -> bool {
+        self.key.is_known()
+            && other.key.is_known()
+            && self.key.dev == other.key.dev
+    }
+
+    pub fn same_inode(&self, other: &Handle) -> bool {
+        self.key.is_known()
+            && other.key.is_known()
+            && self.key.ino == other.key.ino
+    }
+
+    pub fn dev(&self) -> u64 {
+        self.key.dev
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.key.ino
+    }
+}
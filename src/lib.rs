@@ -59,8 +59,69 @@ See [`examples/is_stderr.rs`] for a runnable example and compare the output of:
 - `cargo run --example is_stderr 2> examples/stderr` and
 - `cargo run --example is_stderr`.
 
+# Platform support
+
+This crate works on Windows, on Unix (including Redox) and on Hermit. On
+any other platform, such as `wasm32-unknown-unknown`, it still compiles and
+exposes the same API, but every attempt to create a [`Handle`] (and so
+every call to [`is_same_file`]) fails with an error whose kind is
+[`io::ErrorKind::Unsupported`]. This way, crates that only need it on
+real operating systems don't have to `cfg` it out themselves.
+
+On Apple's mobile platforms (iOS, tvOS, watchOS and visionOS), only public
+system calls and library functions are used, so apps using this crate pass
+App Store review. In an app sandbox, opening a file outside the app's
+container may be reported as a sandbox violation even when the file can be
+stat'd; see [`HandleOptions::stat_only`] for a way to avoid opening files
+at all.
+
+# System calls
+
+On Unix, files are opened and stat'd through std wherever it has what is
+needed. The remaining system calls (e.g., `statx`, `openat2` and `fcntl`)
+are made through [`libc`] by default, which is what the `backend-libc`
+feature enables. When the `backend-rustix` feature is enabled, they are
+made through [`rustix`] instead, with the same behavior.
+
+To drop the dependency on `libc` entirely, disable the default features
+and enable only `backend-rustix`. One of the two must be enabled on Unix.
+A few queries need system calls that rustix doesn't wrap, so without
+`backend-libc`:
+
+- `Handle::kernel_handle`, `Handle::kernel_handle_eq` and `KernelHandle`
+  don't exist on Linux.
+- `Handle::same_volume_uuid` and `Handle::shares_storage` don't exist on
+  Apple platforms, and [`Handle::link_id`] always fails there.
+- `Handle::overlay_eq` can't tell whether a file that overlayfs copied up
+  is the one in the lower layer, so it reports `None` for those.
+
+# Tracing
+
+When the `tracing` feature is enabled, this crate emits [`tracing`] events
+that make it possible to see how many handles are opened and compared. When
+it is disabled, the instrumentation is compiled out entirely. Each kind of
+event has its own target, so they can be filtered individually:
+
+- `same_file::open` (`DEBUG`): emitted by [`Handle::from_path`], with a
+  `path` field and either a `key` field or an `error` field.
+- `same_file::information` (`TRACE`): emitted whenever the identity of an
+  open file is queried, with a `key` field. If the file has no identity
+  (e.g., a Windows console), then `key` is `None`.
+- `same_file::compare` (`TRACE`): emitted whenever two handles are compared,
+  with an `equal` field. Comparisons that were decided without looking at
+  the identities of the files because one of them has none also have a
+  `keyless` field set to `true`.
+
 [`is_same_file`]: fn.is_same_file.html
 [`Handle`]: struct.Handle.html
+[`Handle::from_path`]: struct.Handle.html#method.from_path
+[`HandleOptions::stat_only`]: struct.HandleOptions.html#method.stat_only
+[`tracing`]: https://docs.rs/tracing
+[`libc`]: https://docs.rs/libc
+[`rustix`]: https://docs.rs/rustix
+[`Handle::kernel_handle`]: struct.Handle.html#method.kernel_handle
+[`Handle::fs_type`]: struct.Handle.html#method.fs_type
+[`io::ErrorKind::Unsupported`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Unsupported
 [`examples/is_stderr.rs`]: https://github.com/BurntSushi/same-file/blob/master/examples/is_same_file.rs
 
 */
@@ -71,21 +132,66 @@ See [`examples/is_stderr.rs`] for a runnable example and compare the output of:
 #[cfg(doctest)]
 doc_comment::doctest!("../README.md");
 
-use std::fs::File;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+// Emit a `tracing` event when the `tracing` feature is enabled, and nothing
+// at all otherwise. This must be defined before the modules that use it.
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($($tt:tt)*) => {
+        tracing::event!($($tt)*)
+    };
+}
 
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($($tt:tt)*) => {
+        ()
+    };
+}
+
+#[cfg(target_os = "hermit")]
+use crate::hermit as imp;
 #[cfg(any(target_os = "redox", unix))]
 use crate::unix as imp;
-#[cfg(not(any(target_os = "redox", unix, windows)))]
+#[cfg(not(any(
+    target_os = "redox",
+    target_os = "hermit",
+    unix,
+    windows
+)))]
 use unknown as imp;
 #[cfg(windows)]
 use win as imp;
 
+#[cfg(all(
+    any(target_os = "redox", unix),
+    not(any(feature = "backend-libc", feature = "backend-rustix"))
+))]
+compile_error!(
+    "same-file needs either the `backend-libc` or the `backend-rustix` \
+     feature on Unix"
+);
+
+#[cfg(target_os = "hermit")]
+mod hermit;
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "serde")]
+mod ser;
+/// Unix-specific extensions.
 #[cfg(any(target_os = "redox", unix))]
-mod unix;
-#[cfg(not(any(target_os = "redox", unix, windows)))]
+pub mod unix;
+#[cfg(not(any(target_os = "redox", target_os = "hermit", unix, windows)))]
 mod unknown;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
 #[cfg(windows)]
 mod win;
 
@@ -97,6 +203,20 @@ mod win;
 ///
 /// A handle consumes an open file resource as long as it exists.
 ///
+/// Handles are `Send` and `Sync` on every platform, so they can be moved to
+/// and shared between threads, e.g., by a parallel directory walker. This
+/// also holds for handles that borrow the process's stdio streams (see
+/// [`Handle::stdin`]), and for [`FileKey`], [`Error`] and the option types.
+/// Comparing and hashing handles only reads the identity that was recorded
+/// when they were constructed, so it needs no synchronization.
+///
+/// Equality doesn't depend on how a handle was obtained. Two handles of the
+/// same file compare equal, and hash identically, whether they were opened
+/// from a path (with or without [`HandleOptions`]), built from an open
+/// [`File`], cloned with [`try_clone`] or [`try_clone_fresh`], or built from
+/// a file that was [reopened]. On Unix, this includes snapshot handles (see
+/// [`is_snapshot`]).
+///
 /// Equality is determined by comparing inode numbers on Unix and a combination
 /// of identifier, volume serial, and file size on Windows. Note that it's
 /// possible for comparing two handles to produce a false positive on some
@@ -104,6 +224,66 @@ mod win;
 /// *don't* point to the same file. Check the [source] for specific
 /// implementation details.
 ///
+/// On QNX Neutrino, many paths are served by resource managers rather than
+/// file systems, and it's up to each resource manager to assign device and
+/// inode numbers. Two paths served by the same resource manager compare
+/// equal exactly when it reports the same numbers for both. Resource
+/// managers that don't assign inode numbers report zero for everything they
+/// serve, so such handles are treated as having no identity, just like a
+/// Windows console handle (see [`key`]).
+///
+/// On Fuchsia, there is no global file system: each process sees a
+/// namespace made up of directories served by different file system servers
+/// over channels. Inode numbers are only meaningful within a single server,
+/// and not every server reports a distinct device number, so two files in
+/// different parts of a namespace (e.g., `/data` and `/tmp`) can compare
+/// equal if they happen to have the same inode number. Comparisons within a
+/// single directory tree served by one server are reliable. Nodes whose
+/// server doesn't know their inode number are treated as having no
+/// identity. Descriptors that aren't backed by a file system node at all
+/// (e.g., sockets, pipes or raw channels wrapped by fdio) are emulated by the
+/// C library, whose `fstat` doesn't report a meaningful device number for
+/// them. So two such handles should only be trusted to compare equal if one
+/// was duplicated from the other.
+///
+/// On Emscripten, inode numbers are synthesized by the in-memory file system
+/// layer (MEMFS, NODEFS and friends). They are stable for the lifetime of the
+/// program, but every mounted file system numbers its files independently,
+/// so files on different mounts can compare equal. Zero is never a real
+/// inode number there, so such handles are treated as having no identity.
+/// Note that Emscripten's file systems don't support hard links.
+///
+/// On ESP-IDF, files are served by the VFS layer, and most of the file
+/// systems mounted through it (e.g., FAT and SPIFFS) have no inode numbers
+/// and report zero instead. Such handles are treated as having no identity,
+/// so two handles to the same file on those file systems never compare
+/// equal. None of them have symbolic links either, so
+/// [`HandleOptions::follow_symlinks`] has no effect there.
+///
+/// On Redox, every resource is provided by a scheme, and the device number of
+/// a file identifies the scheme that provides it. Each scheme numbers its
+/// own resources, so handles to resources of different schemes never compare
+/// equal, while the different spellings of a path to the same resource
+/// (e.g., with or without an explicit `file:` scheme) do.
+///
+/// On VxWorks, what an identity means depends on the file system. HRFS has
+/// real inode numbers, and the NFS client reports the file IDs of the
+/// server, both of which are reliable within a volume. dosFs has no inodes,
+/// so files on dosFs volumes that report an inode number of zero are treated
+/// as having no identity, and other inode numbers it reports are only as
+/// stable as the directory entries they are derived from.
+///
+/// [`key`]: #method.key
+/// [`Handle::stdin`]: #method.stdin
+/// [`FileKey`]: struct.FileKey.html
+/// [`Error`]: struct.Error.html
+/// [`HandleOptions`]: struct.HandleOptions.html
+/// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+/// [`try_clone`]: #method.try_clone
+/// [`try_clone_fresh`]: #method.try_clone_fresh
+/// [reopened]: #method.reopen
+/// [`is_snapshot`]: #method.is_snapshot
+/// [`HandleOptions::follow_symlinks`]: struct.HandleOptions.html#method.follow_symlinks
 /// [source]: https://github.com/BurntSushi/same-file/tree/master/src
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub struct Handle(imp::Handle);
@@ -114,13 +294,67 @@ impl Handle {
     /// Note that the underlying [`File`] is opened in read-only mode on all
     /// platforms.
     ///
+    /// On Unix, files are opened with `O_NONBLOCK | O_NOCTTY`, so this never
+    /// blocks on a named pipe or a device, and never acquires a controlling
+    /// terminal. Blocking mode is restored afterwards, except for FIFOs,
+    /// whose underlying [`File`] stays in non-blocking mode.
+    ///
+    /// Unix domain sockets can't be opened, but they still have an identity.
+    /// On Linux and Android, they are opened with `O_PATH`, and so are
+    /// devices that can't be opened because their driver isn't there (or
+    /// `/dev/tty` without a controlling terminal). The underlying [`File`] of
+    /// such a handle cannot be used for I/O. Elsewhere, the handle of such a
+    /// file is a snapshot of its identity, obtained with `stat`, that holds
+    /// no open file (see [`is_snapshot`]).
+    ///
+    /// On Windows, symbolic links, junctions and volume mount points are all
+    /// followed, so the identity of a handle is that of the file they
+    /// ultimately point to, on whichever volume it actually resides.
+    ///
+    /// On Windows, paths in the device namespace, such as `\\.\C:`,
+    /// `\\.\PhysicalDrive0` or `\\.\NUL`, are opened without asking for
+    /// read access, which would require administrator privileges for volumes
+    /// and disks. Devices that have no file index get a handle without an
+    /// identity (see [`key`]), like with [`from_file`].
+    ///
+    /// On Linux and Android, if the file cannot be opened for reading due to
+    /// insufficient permissions, then it is opened with `O_PATH` instead.
+    /// This only requires search permission on the directories leading to
+    /// the file, and is sufficient for determining its identity. The
+    /// underlying [`File`] of such a handle cannot be used for I/O.
+    ///
+    /// On Linux, Android, illumos and Solaris, paths that refer to one of
+    /// this process's own descriptors through procfs, such as
+    /// `/proc/self/fd/3`, `/dev/fd/3` or `/dev/stdin`, are not opened.
+    /// Instead, the descriptor is duplicated, so this works for descriptors
+    /// that can't be reopened through procfs (e.g., sockets, `O_PATH`
+    /// descriptors or deleted files). The underlying [`File`] of such a
+    /// handle shares its file offset with the original descriptor.
+    ///
+    /// On macOS, iOS, FreeBSD, DragonFly BSD, NetBSD and OpenBSD, the same
+    /// goes for `/dev/fd/3` and `/dev/stdin`, which is what opening them
+    /// would do anyway, except that opening them fails unless the descriptor
+    /// was opened for reading. So everywhere, a handle of such a path is a
+    /// handle of the descriptor, and compares equal to a handle constructed
+    /// from the descriptor directly (e.g., with [`stdin`] or
+    /// [`from_as_fd`]). In particular, a pipe reached this way has the
+    /// identity the platform reports for the descriptor, just like with
+    /// [`from_file`], and never causes an error.
+    ///
     /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`stdin`]: #method.stdin
+    /// [`from_as_fd`]: #method.from_as_fd
+    /// [`from_file`]: #method.from_file
+    /// [`key`]: #method.key
     ///
     /// # Errors
     /// This method will return an [`io::Error`] if the path cannot
     /// be opened, or the file's metadata cannot be obtained.
     /// The most common reasons for this are: the path does not
-    /// exist, or there were not enough permissions.
+    /// exist, or there were not enough permissions. If the path refers to
+    /// one of this process's own descriptors that isn't open, then the error
+    /// is `EBADF`.
     ///
     /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
     ///
@@ -143,75 +377,212 @@ impl Handle {
     /// # }
     /// ```
     pub fn from_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
-        imp::Handle::from_path(p).map(Handle)
+        let p = p.as_ref();
+        let result = imp::Handle::from_path(p).map(Handle);
+        match result {
+            Ok(ref _h) => event!(
+                target: "same_file::open",
+                tracing::Level::DEBUG,
+                path = %p.display(),
+                key = ?_h.key(),
+            ),
+            Err(ref _err) => event!(
+                target: "same_file::open",
+                tracing::Level::DEBUG,
+                path = %p.display(),
+                error = %_err,
+            ),
+        }
+        result
     }
 
-    /// Construct a handle from a file.
+    /// Construct a handle from a path, with an error that says what went
+    /// wrong.
+    ///
+    /// This is exactly like [`from_path`], except for the error type. An
+    /// [`Error`] records the path, tells failures to open the path apart
+    /// from failures to query the identity of the opened file (which are
+    /// rare), and classifies the failure with [`Error::kind`], so that, for
+    /// example, a file that vanished can be told apart from a process that
+    /// ran out of file descriptors (see [`Error::is_retryable`]).
+    ///
+    /// [`from_path`]: #method.from_path
+    /// [`Error::kind`]: struct.Error.html#method.kind
+    /// [`Error::is_retryable`]: struct.Error.html#method.is_retryable
     ///
     /// # Errors
-    /// This method will return an [`io::Error`] if the metadata for
-    /// the given [`File`] cannot be obtained.
+    /// This method will return an [`Error`] in the same circumstances as
+    /// [`from_path`] returns an [`io::Error`]. The [`io::Error`] that
+    /// [`from_path`] would return can be obtained with `io::Error::from`.
+    ///
+    /// [`Error`]: struct.Error.html
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use same_file::{ErrorKind, Handle};
+    ///
+    /// match Handle::from_path_detailed("./config") {
+    ///     Ok(handle) => println!("{:?}", handle.key()),
+    ///     Err(err) if err.kind() == ErrorKind::NotFound => {}
+    ///     Err(err) if err.is_retryable() => println!("try again: {}", err),
+    ///     Err(err) => println!("{}", err),
+    /// }
+    /// ```
+    pub fn from_path_detailed<P: AsRef<Path>>(p: P) -> Result<Handle, Error> {
+        let p = p.as_ref();
+        imp::Handle::from_path_detailed(p)
+            .map(Handle)
+            .map_err(|err| err.with_path(p))
+    }
+
+    /// Construct a handle from a directory entry, as returned by
+    /// [`fs::read_dir`].
+    ///
+    /// This opens the entry's path just like [`from_path`] does, so symbolic
+    /// links are followed. To avoid opening the file on Unix, use
+    /// [`from_dir_entry_snapshot`] instead.
+    ///
+    /// [`fs::read_dir`]: https://doc.rust-lang.org/std/fs/fn.read_dir.html
+    /// [`from_path`]: #method.from_path
+    /// [`from_dir_entry_snapshot`]: #method.from_dir_entry_snapshot
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file cannot be opened.
     ///
     /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
-    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
     ///
     /// # Examples
-    /// Check that two files are not in fact the same file:
     ///
     /// ```rust,no_run
     /// # use std::error::Error;
-    /// # use std::fs::File;
+    /// use std::collections::HashSet;
+    /// use std::fs;
     /// use same_file::Handle;
     ///
     /// # fn try_main() -> Result<(), Box<dyn Error>> {
-    /// let source = File::open("./source")?;
-    /// let target = File::open("./target")?;
-    ///
-    /// assert_ne!(
-    ///     Handle::from_file(source)?,
-    ///     Handle::from_file(target)?,
-    ///     "The files are the same."
-    /// );
-    /// #     Ok(())
+    /// let mut seen = HashSet::new();
+    /// for entry in fs::read_dir(".")? {
+    ///     let entry = entry?;
+    ///     if !seen.insert(Handle::from_dir_entry(&entry)?) {
+    ///         println!("{} was already seen", entry.path().display());
+    ///     }
+    /// }
+    /// # Ok(())
     /// # }
     /// #
     /// # fn main() {
     /// #     try_main().unwrap();
     /// # }
     /// ```
-    pub fn from_file(file: File) -> io::Result<Handle> {
-        imp::Handle::from_file(file).map(Handle)
+    pub fn from_dir_entry(entry: &fs::DirEntry) -> io::Result<Handle> {
+        imp::Handle::from_dir_entry(entry).map(Handle)
     }
 
-    /// Construct a handle from stdin.
+    /// Construct a handle from a directory entry, as returned by
+    /// [`fs::read_dir`], without opening it if possible.
+    ///
+    /// On Unix, this avoids opening the file. Instead, the identity of the
+    /// file is read with a single `fstatat` relative to the directory being
+    /// read, and the result is a snapshot handle (see [`is_snapshot`]). If
+    /// the entry is a symbolic link, then it is followed by opening it, just
+    /// like [`from_path`] does. Elsewhere, this is the same as
+    /// [`from_dir_entry`].
+    ///
+    /// Either way, the resulting handle compares equal to any other handle of
+    /// the same file. Note though that a snapshot handle doesn't keep the
+    /// file open, so it's possible for the file to be deleted and its
+    /// identity reused while the handle is alive. It also has no file to
+    /// hand out, so [`as_file`] panics.
+    ///
+    /// [`fs::read_dir`]: https://doc.rust-lang.org/std/fs/fn.read_dir.html
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`from_path`]: #method.from_path
+    /// [`from_dir_entry`]: #method.from_dir_entry
+    /// [`as_file`]: #method.as_file
     ///
     /// # Errors
-    /// This method will return an [`io::Error`] if stdin cannot
-    /// be opened due to any I/O-related reason.
+    /// This method will return an [`io::Error`] if the metadata of the entry
+    /// cannot be obtained, or if the file cannot be opened.
     ///
     /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// # use std::error::Error;
+    /// use std::collections::HashSet;
+    /// use std::fs;
     /// use same_file::Handle;
     ///
     /// # fn try_main() -> Result<(), Box<dyn Error>> {
-    /// let stdin = Handle::stdin()?;
-    /// let stdout = Handle::stdout()?;
-    /// let stderr = Handle::stderr()?;
-    ///
-    /// if stdin == stdout {
-    ///     println!("stdin == stdout");
-    /// }
-    /// if stdin == stderr {
-    ///     println!("stdin == stderr");
-    /// }
-    /// if stdout == stderr {
-    ///     println!("stdout == stderr");
+    /// let mut seen = HashSet::new();
+    /// for entry in fs::read_dir(".")? {
+    ///     let entry = entry?;
+    ///     if !seen.insert(Handle::from_dir_entry_snapshot(&entry)?) {
+    ///         println!("{} was already seen", entry.path().display());
+    ///     }
     /// }
+    /// # Ok(())
+    /// # }
     /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn from_dir_entry_snapshot(
+        entry: &fs::DirEntry,
+    ) -> io::Result<Handle> {
+        imp::Handle::from_dir_entry_snapshot(entry).map(Handle)
+    }
+
+    /// Construct a handle of a symbolic link itself, rather than of the file
+    /// it points to.
+    ///
+    /// Every other way of constructing a handle from a path follows symbolic
+    /// links (or refuses them, see [`HandleOptions::follow_symlinks`]). The
+    /// identity of a handle constructed with this method is that of the
+    /// link, so it compares equal to another handle of the same link (e.g.,
+    /// reached through a different path to the directory it is in, or
+    /// through a hard link to it), and unequal to a handle of its target
+    /// or of any other link to the same target. See [`is_same_link`].
+    ///
+    /// On Linux and Android, the link is opened with `O_PATH | O_NOFOLLOW`,
+    /// so the underlying [`File`] cannot be used for I/O. On other Unix
+    /// platforms, a link can't be opened at all, so the handle is a snapshot
+    /// of its identity, obtained with `lstat` (see [`is_snapshot`]).
+    ///
+    /// On Windows, the reparse point is opened with
+    /// `FILE_FLAG_OPEN_REPARSE_POINT`. Besides symbolic links, this accepts
+    /// junctions and volume mount points, which are also links to other
+    /// files, but not other kinds of reparse points (see
+    /// [`reparse_tag`]).
+    ///
+    /// [`HandleOptions::follow_symlinks`]: struct.HandleOptions.html#method.follow_symlinks
+    /// [`is_same_link`]: fn.is_same_link.html
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`reparse_tag`]: #method.reparse_tag
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the path cannot be
+    /// opened (or stat'd), or if it isn't a symbolic link, in which case the
+    /// error is `EINVAL` on Unix (just like for `readlink`) and
+    /// `ERROR_NOT_A_REPARSE_POINT` on Windows.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let link = Handle::from_link("/etc/alternatives/editor")?;
+    /// let target = Handle::from_path("/etc/alternatives/editor")?;
+    /// assert_ne!(link, target);
     /// #     Ok(())
     /// # }
     /// #
@@ -219,343 +590,7395 @@ impl Handle {
     /// #     try_main().unwrap();
     /// # }
     /// ```
+    pub fn from_link<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        imp::Handle::from_link(p).map(Handle)
+    }
+
+    /// Construct a handle from a path, after making the path canonical.
     ///
-    /// The output differs depending on the platform.
+    /// A relative path is resolved against the current directory, so if the
+    /// current directory changes, the same relative path may refer to a
+    /// different file. This resolves the path once with
+    /// [`fs::canonicalize`] before opening it, and keeps the canonical path
+    /// around (see [`canonical_path`]) so that the file can be found again
+    /// no matter how the current directory changes afterwards.
     ///
-    /// On Linux:
+    /// Note that resolving the path costs an extra system call (or more, on
+    /// some platforms) compared to [`from_path`].
     ///
-    /// ```text
-    /// $ ./example
-    /// stdin == stdout
-    /// stdin == stderr
-    /// stdout == stderr
-    /// $ ./example > result
-    /// $ cat result
-    /// stdin == stderr
-    /// $ ./example > result 2>&1
-    /// $ cat result
-    /// stdout == stderr
-    /// ```
+    /// [`fs::canonicalize`]: https://doc.rust-lang.org/std/fs/fn.canonicalize.html
+    /// [`canonical_path`]: #method.canonical_path
+    /// [`from_path`]: #method.from_path
     ///
-    /// Windows:
+    /// # Errors
+    /// This method will return an [`io::Error`] if the path cannot be
+    /// canonicalized, or in the same circumstances as [`from_path`].
     ///
-    /// ```text
-    /// > example
-    /// > example > result 2>&1
-    /// > type result
-    /// stdout == stderr
-    /// ```
-    pub fn stdin() -> io::Result<Handle> {
-        imp::Handle::stdin().map(Handle)
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn from_path_canonical<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        imp::Handle::from_path_canonical(p).map(Handle)
     }
 
-    /// Construct a handle from stdout.
+    /// Construct a handle from a path, falling back to an identity based on
+    /// the path's canonical form if the file can't be opened.
+    ///
+    /// This is like [`from_path`], except when opening the file fails with
+    /// `ERROR_ACCESS_DENIED`, which happens when the file may not be read
+    /// (since [`from_path`] opens it for reading), and in locked-down
+    /// environments. The path is then made canonical instead (which may
+    /// still be allowed, or failing that, by making the directory it is in
+    /// canonical), and the handle is a path-based handle (see
+    /// [`is_path_based`]) with that path as its identity (see
+    /// [`canonical_path`]).
+    ///
+    /// A path-based identity is weaker than a real one. A path-based handle
+    /// only compares equal to another path-based handle, when their
+    /// canonical paths are equal (ignoring the case of ASCII letters). 8.3
+    /// short names (e.g., `PROGRA~1`) are expanded to long names first, so a
+    /// short and a long path to the same file are still equal. But it
+    /// never compares equal to a handle that could open its file, and hard
+    /// links to the same file compare unequal. It holds no open file either,
+    /// so if the path is renamed or removed, the handle doesn't notice, and
+    /// [`as_file`] panics.
+    ///
+    /// Note that this only works on Windows. On Unix, see
+    /// [`HandleOptions::snapshot_fallback`] instead.
+    ///
+    /// [`from_path`]: #method.from_path
+    /// [`is_path_based`]: #method.is_path_based
+    /// [`canonical_path`]: #method.canonical_path
+    /// [`as_file`]: #method.as_file
+    /// [`HandleOptions::snapshot_fallback`]: struct.HandleOptions.html#method.snapshot_fallback
     ///
     /// # Errors
-    /// This method will return an [`io::Error`] if stdout cannot
-    /// be opened due to any I/O-related reason.
+    /// This method will return an [`io::Error`] in the same circumstances as
+    /// [`from_path`], except when access is denied and the path can be made
+    /// canonical.
     ///
     /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
     ///
     /// # Examples
-    /// See the example for [`stdin()`].
     ///
-    /// [`stdin()`]: #method.stdin
-    pub fn stdout() -> io::Result<Handle> {
-        imp::Handle::stdout().map(Handle)
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let h1 = Handle::from_path_or_canonical(r"C:\Windows\System32\config\SAM")?;
+    /// let h2 = Handle::from_path_or_canonical(r"C:\WINDOWS\system32\config\sam")?;
+    /// assert_eq!(h1, h2);
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(windows)]
+    pub fn from_path_or_canonical<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        imp::Handle::from_path_or_canonical(p).map(Handle)
     }
 
-    /// Construct a handle from stderr.
+    /// Return the canonical path this handle was opened with.
+    ///
+    /// This returns `None` unless the handle was constructed with
+    /// [`from_path_canonical`], or is a path-based handle (see
+    /// [`from_path_or_canonical`]). The path is not updated if the file is
+    /// later renamed or removed.
+    ///
+    /// [`from_path_canonical`]: #method.from_path_canonical
+    /// [`from_path_or_canonical`]: #method.from_path_or_canonical
+    pub fn canonical_path(&self) -> Option<&Path> {
+        self.0.canonical_path()
+    }
+
+    /// Construct a handle from a path relative to a directory, without ever
+    /// resolving to a file outside of that directory.
+    ///
+    /// The path is resolved with `openat2(2)` using `RESOLVE_BENEATH` and
+    /// `RESOLVE_NO_MAGICLINKS`. This means that absolute paths, `..`
+    /// components and symbolic links that would escape `dir`, as well as
+    /// magic links such as those in `/proc/self/fd`, are all rejected by the
+    /// kernel. This makes it safe to use with untrusted paths, as long as
+    /// `dir` itself is trusted.
+    ///
+    /// `dir` should be a directory handle, for example, one returned by
+    /// [`from_dir_path`]. The resulting handle compares equal to any other
+    /// handle of the same file.
+    ///
+    /// Note that this only works on Linux 5.6 or newer.
+    ///
+    /// [`from_dir_path`]: #method.from_dir_path
     ///
     /// # Errors
-    /// This method will return an [`io::Error`] if stderr cannot
-    /// be opened due to any I/O-related reason.
+    /// This method will return an [`io::Error`] if the path would escape
+    /// `dir` (`EXDEV` or `ELOOP`), if the path cannot be opened, or if the
+    /// kernel doesn't support `openat2` (`ENOSYS`). In the latter case, there
+    /// is no fallback to a less restrictive open.
     ///
     /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
     ///
     /// # Examples
-    /// See the example for [`stdin()`].
     ///
-    /// [`stdin()`]: #method.stdin
-    pub fn stderr() -> io::Result<Handle> {
-        imp::Handle::stderr().map(Handle)
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let root = Handle::from_dir_path("/srv/sandbox")?;
+    /// let h = Handle::from_path_beneath(&root, "user/supplied/path")?;
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn from_path_beneath<P: AsRef<Path>>(
+        dir: &Handle,
+        p: P,
+    ) -> io::Result<Handle> {
+        imp::Handle::from_path_beneath(&dir.0, p).map(Handle)
     }
 
-    /// Return a reference to the underlying file.
+    /// Construct a handle from a path, retrying the open if another process
+    /// temporarily prevents it.
+    ///
+    /// On Windows, programs like virus scanners and indexers can briefly
+    /// hold a file open without sharing it, which causes opening it to fail
+    /// with a sharing or lock violation. When that happens, this sleeps for
+    /// `delay` and tries again, up to `attempts` times in total. Any other
+    /// error is returned immediately.
+    ///
+    /// On other platforms, this is equivalent to [`from_path`], since
+    /// opening a file for reading is never prevented in that way.
+    ///
+    /// [`from_path`]: #method.from_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] for the same reasons as
+    /// [`from_path`]. If every attempt failed, then the error from the last
+    /// attempt is returned.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
     ///
     /// # Examples
-    /// Ensure that the target file is not the same as the source one,
-    /// and copy the data to it:
+    /// Try up to five times, waiting 50 milliseconds between attempts:
     ///
     /// ```rust,no_run
     /// # use std::error::Error;
-    /// use std::io::prelude::*;
-    /// use std::io::Write;
-    /// use std::fs::File;
+    /// use std::time::Duration;
     /// use same_file::Handle;
     ///
     /// # fn try_main() -> Result<(), Box<dyn Error>> {
-    /// let source = File::open("source")?;
-    /// let target = File::create("target")?;
-    ///
-    /// let source_handle = Handle::from_file(source)?;
-    /// let mut target_handle = Handle::from_file(target)?;
-    /// assert_ne!(source_handle, target_handle, "The files are the same.");
-    ///
-    /// let mut source = source_handle.as_file();
-    /// let target = target_handle.as_file_mut();
-    ///
-    /// let mut buffer = Vec::new();
-    /// // data copy is simplified for the purposes of the example
-    /// source.read_to_end(&mut buffer)?;
-    /// target.write_all(&buffer)?;
-    /// #
-    /// #    Ok(())
+    /// let delay = Duration::from_millis(50);
+    /// let source = Handle::from_path_retry("./source", 5, delay)?;
+    /// let target = Handle::from_path_retry("./target", 5, delay)?;
+    /// assert_ne!(source, target, "The files are the same.");
+    /// # Ok(())
     /// # }
     /// #
     /// # fn main() {
-    /// #    try_main().unwrap();
+    /// #     try_main().unwrap();
     /// # }
     /// ```
-    pub fn as_file(&self) -> &File {
-        self.0.as_file()
+    pub fn from_path_retry<P: AsRef<Path>>(
+        p: P,
+        attempts: usize,
+        delay: Duration,
+    ) -> io::Result<Handle> {
+        imp::Handle::from_path_retry(p, attempts, delay).map(Handle)
     }
 
-    /// Return a mutable reference to the underlying file.
+    /// Construct a handle from a path, giving up if opening it takes longer
+    /// than `timeout`.
+    ///
+    /// Opening a file on an unresponsive network share can block for a very
+    /// long time. This opens the path like [`from_path`], but does so on a
+    /// separate thread. If the open doesn't complete in time, then it is
+    /// cancelled (if the file system supports it) and abandoned.
+    ///
+    /// Note that an abandoned thread may remain blocked until the operating
+    /// system gives up on the share by itself. If the open eventually
+    /// succeeds, then the file is closed again.
+    ///
+    /// [`from_path`]: #method.from_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] for the same reasons as
+    /// [`from_path`], or if a thread could not be spawned. If the open did
+    /// not complete within `timeout`, then the error has kind
+    /// [`io::ErrorKind::TimedOut`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`io::ErrorKind::TimedOut`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.TimedOut
     ///
     /// # Examples
-    /// See the example for [`as_file()`].
+    /// Skip files that take longer than five seconds to open:
     ///
-    /// [`as_file()`]: #method.as_file
-    pub fn as_file_mut(&mut self) -> &mut File {
-        self.0.as_file_mut()
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::io;
+    /// use std::time::Duration;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let timeout = Duration::from_secs(5);
+    /// match Handle::from_path_timeout(r"\\server\share\file", timeout) {
+    ///     Ok(handle) => println!("{:?}", handle),
+    ///     Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+    ///         println!("skipping unresponsive file");
+    ///     }
+    ///     Err(err) => return Err(err.into()),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(windows)]
+    pub fn from_path_timeout<P: AsRef<Path>>(
+        p: P,
+        timeout: Duration,
+    ) -> io::Result<Handle> {
+        imp::Handle::from_path_timeout(p, timeout).map(Handle)
     }
 
-    /// Return the underlying device number of this handle.
+    /// Construct a handle from a path that must refer to a directory.
     ///
-    /// Note that this only works on unix platforms.
-    #[cfg(any(target_os = "redox", unix))]
-    pub fn dev(&self) -> u64 {
-        self.0.dev()
+    /// This is like [`from_path`], except it makes the intent to open a
+    /// directory explicit and fails if the path refers to anything else. On
+    /// Unix, the path is opened with `O_DIRECTORY`. On Windows, the path is
+    /// opened with `FILE_FLAG_BACKUP_SEMANTICS` and the directory attribute
+    /// is checked after opening. Symbolic links are followed.
+    ///
+    /// [`from_path`]: #method.from_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] for the same reasons as
+    /// [`from_path`], and additionally if the path does not refer to a
+    /// directory.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    /// Check whether a directory is the current working directory:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::env;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let cwd = Handle::from_dir_path(env::current_dir()?)?;
+    /// let dir = Handle::from_dir_path("./some/dir")?;
+    /// if cwd == dir {
+    ///     println!("./some/dir is the current directory");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn from_dir_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        imp::Handle::from_dir_path(p).map(Handle)
     }
 
-    /// Return the underlying inode number of this handle.
+    /// Construct a handle for the current working directory.
     ///
-    /// Note that this only works on unix platforms.
+    /// This opens `.` directly, rather than a path obtained from
+    /// [`env::current_dir`], so it works even if the current directory has
+    /// no path that can be expressed (e.g., because it was renamed or one
+    /// of its ancestors is inaccessible). It is equivalent to
+    /// [`from_dir_path`] with a path of `.`, which on Windows includes
+    /// opening with backup semantics.
+    ///
+    /// The handle refers to the directory that was current when it was
+    /// constructed. Changing the current directory later (e.g., with
+    /// [`env::set_current_dir`]) does not change which directory the handle
+    /// refers to, so call this again to observe the new one.
+    ///
+    /// [`env::current_dir`]: https://doc.rust-lang.org/std/env/fn.current_dir.html
+    /// [`env::set_current_dir`]: https://doc.rust-lang.org/std/env/fn.set_current_dir.html
+    /// [`from_dir_path`]: #method.from_dir_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the current directory
+    /// cannot be opened, for example, because it was removed.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let target = Handle::from_path("/some/dir")?;
+    /// if target == Handle::current_dir()? {
+    ///     println!("refusing to clean the current directory");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn current_dir() -> io::Result<Handle> {
+        Handle::from_dir_path(".")
+    }
+
+    /// Construct a placeholder handle that refers to no file at all.
+    ///
+    /// A dangling handle compares unequal to every other handle, including
+    /// other dangling handles (it is only equal to itself, as required by
+    /// [`Eq`]). It has no identity (see [`key`]), and different dangling
+    /// handles hash differently. This never touches the operating system,
+    /// so it can't fail, which makes it a cheap way to fill a slot in a data
+    /// structure without opening something like `/dev/null`.
+    ///
+    /// A dangling handle has no file, so [`as_file`] and [`as_file_mut`]
+    /// panic, [`into_file`] returns `None`, and methods that need a file
+    /// return an error. Cloning a dangling handle produces another dangling
+    /// handle.
+    ///
+    /// This works on every platform, including those where no other handle
+    /// can be constructed.
+    ///
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`key`]: #method.key
+    /// [`as_file`]: #method.as_file
+    /// [`as_file_mut`]: #method.as_file_mut
+    /// [`into_file`]: #method.into_file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use same_file::Handle;
+    ///
+    /// let slots: Vec<Handle> = (0..4).map(|_| Handle::dangling()).collect();
+    /// assert!(slots[0] != slots[1]);
+    /// assert!(slots[0].key().is_none());
+    /// ```
+    pub fn dangling() -> Handle {
+        Handle(imp::Handle::dangling())
+    }
+
+    /// Construct a handle to the platform's null device.
+    ///
+    /// This opens `/dev/null` on Unix and `NUL` on Windows for reading, so
+    /// the handle's file is valid and reads as empty. Unlike a
+    /// [dangling handle], it is a real open file, which makes it useful in
+    /// tests and as a placeholder that needs a file.
+    ///
+    /// Whether two null handles compare equal depends on how the operating
+    /// system identifies the null device. On Unix, `/dev/null` is a device
+    /// node with a device and inode number like any other file, so null
+    /// handles compare equal to each other (and to any other handle to
+    /// `/dev/null`). On Windows, `NUL` is not a file and has no identity
+    /// (see [`key`]), so a null handle is only equal to itself.
+    ///
+    /// [dangling handle]: #method.dangling
+    /// [`key`]: #method.key
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the null device cannot be
+    /// opened, e.g., in a chroot without `/dev`, or on platforms without a
+    /// null device.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::io::Read;
+    ///
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let mut null = Handle::null()?;
+    /// let mut buf = vec![];
+    /// null.as_file_mut().read_to_end(&mut buf)?;
+    /// assert!(buf.is_empty());
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn null() -> io::Result<Handle> {
+        #[cfg(windows)]
+        const NULL: &str = "NUL";
+        #[cfg(not(windows))]
+        const NULL: &str = "/dev/null";
+
+        Handle::from_file(File::open(NULL)?)
+    }
+
+    /// Construct a handle from a file.
+    ///
+    /// On Windows, if the file is not a file at all (e.g., the read end of
+    /// a pipe or a device), then the handle has no identity, just like a
+    /// stdio handle attached to a console (see [`key`]). Use
+    /// [`is_named_pipe`] to tell pipes apart.
+    ///
+    /// [`key`]: #method.key
+    /// [`is_named_pipe`]: #method.is_named_pipe
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the metadata for
+    /// the given [`File`] cannot be obtained.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    ///
+    /// # Examples
+    /// Check that two files are not in fact the same file:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// # use std::fs::File;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let source = File::open("./source")?;
+    /// let target = File::open("./target")?;
+    ///
+    /// assert_ne!(
+    ///     Handle::from_file(source)?,
+    ///     Handle::from_file(target)?,
+    ///     "The files are the same."
+    /// );
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn from_file(file: File) -> io::Result<Handle> {
+        imp::Handle::from_file(file).map(Handle)
+    }
+
+    /// Construct a handle from a [`cap_std::fs::File`].
+    ///
+    /// This lets tools that confine themselves to a directory capability
+    /// with [`cap-std`] use handles (e.g., for loop detection) without
+    /// opening anything by absolute path. The file is converted into a
+    /// [`File`] and used just like [`from_file`] uses it, so the handle
+    /// owns it.
+    ///
+    /// Note that the identity of the file is still OS-wide, not scoped to
+    /// any capability: a handle of a file opened through one directory
+    /// capability compares equal to a handle of the same file opened
+    /// through another capability, or by path.
+    ///
+    /// This requires the `cap-std` feature.
+    ///
+    /// [`cap_std::fs::File`]: https://docs.rs/cap-std/*/cap_std/fs/struct.File.html
+    /// [`cap-std`]: https://docs.rs/cap-std
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`from_file`]: #method.from_file
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] for the same reasons as
+    /// [`from_file`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use cap_std::{ambient_authority, fs::Dir};
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let dir = Dir::open_ambient_dir("./sandbox", ambient_authority())?;
+    /// let a = Handle::from_cap_file(dir.open("a")?)?;
+    /// let b = Handle::from_cap_file(dir.open("b")?)?;
+    /// println!("same file: {}", a == b);
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "cap-std")]
+    pub fn from_cap_file(file: cap_std::fs::File) -> io::Result<Handle> {
+        Handle::from_file(file.into_std())
+    }
+
+    /// Construct a handle from anything that has a file descriptor, such as
+    /// a [`ChildStdout`], a [`UnixStream`] or a [`File`].
+    ///
+    /// The descriptor is duplicated, and the handle keeps the duplicate, so
+    /// `t` is only borrowed for the duration of this call and is never
+    /// closed. The handle remains valid after `t` is dropped.
+    ///
+    /// Descriptors that don't refer to a file in a file system still have a
+    /// device and inode number on most platforms: on Linux, for example,
+    /// every pipe and socket has an inode on an internal file system. Two
+    /// handles of such descriptors compare equal exactly when they refer to
+    /// the same pipe or socket, which is the case for both ends of one pipe,
+    /// or for a descriptor and its duplicates. They never compare equal to a
+    /// handle of a file that was opened by path. Platforms that don't report
+    /// an identity for some descriptors produce handles without one (see
+    /// [`key`]).
+    ///
+    /// This is only available on Unix. See [`from_as_handle`] for Windows.
+    ///
+    /// [`ChildStdout`]: https://doc.rust-lang.org/std/process/struct.ChildStdout.html
+    /// [`UnixStream`]: https://doc.rust-lang.org/std/os/unix/net/struct.UnixStream.html
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`key`]: #method.key
+    /// [`from_as_handle`]: #method.from_as_handle
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the descriptor cannot be
+    /// duplicated, or if its metadata cannot be obtained.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    /// Check whether a child process writes to the same file as we do:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::process::{Command, Stdio};
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let child = Command::new("cat").stdout(Stdio::piped()).spawn()?;
+    /// let stdout = child.stdout.as_ref().unwrap();
+    /// if Handle::from_as_fd(stdout)? == Handle::stdout()? {
+    ///     println!("the child writes to our stdout");
+    /// }
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
     #[cfg(any(target_os = "redox", unix))]
-    pub fn ino(&self) -> u64 {
-        self.0.ino()
+    pub fn from_as_fd<T: std::os::unix::io::AsFd>(
+        t: &T,
+    ) -> io::Result<Handle> {
+        imp::Handle::from_as_fd(t).map(Handle)
+    }
+
+    /// Construct a handle from anything that has a Windows handle, such as
+    /// a [`ChildStdout`] or a [`File`].
+    ///
+    /// The handle is duplicated, and the result keeps the duplicate, so `t`
+    /// is only borrowed for the duration of this call and is never closed.
+    /// The result remains valid after `t` is dropped.
+    ///
+    /// Handles of things that aren't files, such as anonymous pipes or
+    /// devices, have no identity (see [`key`]), just like with
+    /// [`from_file`]. Such a handle only compares equal to itself, even when
+    /// two handles refer to the same pipe.
+    ///
+    /// This is only available on Windows. See [`from_as_fd`] for Unix.
+    ///
+    /// [`ChildStdout`]: https://doc.rust-lang.org/std/process/struct.ChildStdout.html
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`key`]: #method.key
+    /// [`from_file`]: #method.from_file
+    /// [`from_as_fd`]: #method.from_as_fd
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the handle cannot be
+    /// duplicated, or if the identity of a file cannot be obtained.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(windows)]
+    pub fn from_as_handle<T: std::os::windows::io::AsHandle>(
+        t: &T,
+    ) -> io::Result<Handle> {
+        imp::Handle::from_as_handle(t).map(Handle)
+    }
+
+    /// Construct a handle from a path, without blocking an async-std
+    /// executor.
+    ///
+    /// This is like [`from_path`], except the path is opened on async-std's
+    /// pool of threads for blocking operations, so that opening many files
+    /// on slow storage doesn't stall other tasks.
+    ///
+    /// Note that this is only available when the `async-std` feature is
+    /// enabled.
+    ///
+    /// [`from_path`]: #method.from_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] for the same reasons as
+    /// [`from_path`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    /// Check that two paths are not the same file:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # async fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let source = Handle::from_path_async("./source").await?;
+    /// let target = Handle::from_path_async("./target").await?;
+    /// assert_ne!(source, target, "The files are the same.");
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     async_std::task::block_on(try_main()).unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "async-std")]
+    pub async fn from_path_async<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        let p = p.as_ref().to_path_buf();
+        async_std::task::spawn_blocking(move || Handle::from_path(p)).await
+    }
+
+    /// Construct a handle from an async-std file, without blocking an
+    /// async-std executor.
+    ///
+    /// This is like [`from_file`], except the file's identity is queried on
+    /// async-std's pool of threads for blocking operations. The file is
+    /// flushed before it is converted.
+    ///
+    /// Note that this is only available when the `async-std` feature is
+    /// enabled, on Unix and Windows.
+    ///
+    /// [`from_file`]: #method.from_file
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the metadata for the
+    /// given file cannot be obtained.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Panics
+    /// This panics if an operation on the file is still in progress, e.g.,
+    /// because a read was started but never completed.
+    #[cfg(all(feature = "async-std", any(unix, windows)))]
+    pub async fn from_async_std_file(
+        mut file: async_std::fs::File,
+    ) -> io::Result<Handle> {
+        use async_std::io::WriteExt;
+
+        // Otherwise, the conversion below flushes any buffered writes in a
+        // blocking fashion.
+        file.flush().await?;
+        #[cfg(unix)]
+        let file = {
+            use std::os::unix::io::{FromRawFd, IntoRawFd};
+            unsafe { File::from_raw_fd(file.into_raw_fd()) }
+        };
+        #[cfg(windows)]
+        let file = {
+            use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+            unsafe { File::from_raw_handle(file.into_raw_handle()) }
+        };
+        async_std::task::spawn_blocking(move || Handle::from_file(file)).await
+    }
+
+    /// Construct a handle from stdin.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if stdin cannot
+    /// be opened due to any I/O-related reason.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let stdin = Handle::stdin()?;
+    /// let stdout = Handle::stdout()?;
+    /// let stderr = Handle::stderr()?;
+    ///
+    /// if stdin == stdout {
+    ///     println!("stdin == stdout");
+    /// }
+    /// if stdin == stderr {
+    ///     println!("stdin == stderr");
+    /// }
+    /// if stdout == stderr {
+    ///     println!("stdout == stderr");
+    /// }
+    /// #
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    ///
+    /// The output differs depending on the platform.
+    ///
+    /// On Linux:
+    ///
+    /// ```text
+    /// $ ./example
+    /// stdin == stdout
+    /// stdin == stderr
+    /// stdout == stderr
+    /// $ ./example > result
+    /// $ cat result
+    /// stdin == stderr
+    /// $ ./example > result 2>&1
+    /// $ cat result
+    /// stdout == stderr
+    /// ```
+    ///
+    /// Windows:
+    ///
+    /// ```text
+    /// > example
+    /// > example > result 2>&1
+    /// > type result
+    /// stdout == stderr
+    /// ```
+    pub fn stdin() -> io::Result<Handle> {
+        imp::Handle::stdin().map(Handle)
+    }
+
+    /// Construct a handle from stdout.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if stdout cannot
+    /// be opened due to any I/O-related reason.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    /// See the example for [`stdin()`].
+    ///
+    /// [`stdin()`]: #method.stdin
+    pub fn stdout() -> io::Result<Handle> {
+        imp::Handle::stdout().map(Handle)
+    }
+
+    /// Construct a handle from stderr.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if stderr cannot
+    /// be opened due to any I/O-related reason.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    /// See the example for [`stdin()`].
+    ///
+    /// [`stdin()`]: #method.stdin
+    pub fn stderr() -> io::Result<Handle> {
+        imp::Handle::stderr().map(Handle)
+    }
+
+    /// Return a reference to the underlying file.
+    ///
+    /// # Examples
+    /// Ensure that the target file is not the same as the source one,
+    /// and copy the data to it:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::io::prelude::*;
+    /// use std::io::Write;
+    /// use std::fs::File;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let source = File::open("source")?;
+    /// let target = File::create("target")?;
+    ///
+    /// let source_handle = Handle::from_file(source)?;
+    /// let mut target_handle = Handle::from_file(target)?;
+    /// assert_ne!(source_handle, target_handle, "The files are the same.");
+    ///
+    /// let mut source = source_handle.as_file();
+    /// let target = target_handle.as_file_mut();
+    ///
+    /// let mut buffer = Vec::new();
+    /// // data copy is simplified for the purposes of the example
+    /// source.read_to_end(&mut buffer)?;
+    /// target.write_all(&buffer)?;
+    /// #
+    /// #    Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #    try_main().unwrap();
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    /// This panics if the handle has no open file, which is the case for
+    /// snapshot handles (see [`is_snapshot`]), path-based handles (see
+    /// [`is_path_based`]) and [`dangling`] handles. Note that snapshots are
+    /// not only produced when asked for with [`HandleOptions`] or
+    /// [`from_dir_entry_snapshot`]. On Unix platforms other than Linux and
+    /// Android, [`from_link`] always returns one, and so does [`from_path`]
+    /// for a socket or a device that can't be opened. Check [`is_snapshot`]
+    /// first when in doubt.
+    ///
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`is_path_based`]: #method.is_path_based
+    /// [`dangling`]: #method.dangling
+    /// [`HandleOptions`]: struct.HandleOptions.html
+    /// [`from_dir_entry_snapshot`]: #method.from_dir_entry_snapshot
+    /// [`from_link`]: #method.from_link
+    /// [`from_path`]: #method.from_path
+    pub fn as_file(&self) -> &File {
+        self.0.as_file()
+    }
+
+    /// Return a mutable reference to the underlying file.
+    ///
+    /// # Examples
+    /// See the example for [`as_file()`].
+    ///
+    /// [`as_file()`]: #method.as_file
+    ///
+    /// # Panics
+    /// This panics if the handle has no open file, which is the case for
+    /// snapshot handles (see [`is_snapshot`]), path-based handles (see
+    /// [`is_path_based`]) and [`dangling`] handles. Note that snapshots are
+    /// not only produced when asked for with [`HandleOptions`] or
+    /// [`from_dir_entry_snapshot`]. On Unix platforms other than Linux and
+    /// Android, [`from_link`] always returns one, and so does [`from_path`]
+    /// for a socket or a device that can't be opened. Check [`is_snapshot`]
+    /// first when in doubt.
+    ///
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`is_path_based`]: #method.is_path_based
+    /// [`dangling`]: #method.dangling
+    /// [`HandleOptions`]: struct.HandleOptions.html
+    /// [`from_dir_entry_snapshot`]: #method.from_dir_entry_snapshot
+    /// [`from_link`]: #method.from_link
+    /// [`from_path`]: #method.from_path
+    pub fn as_file_mut(&mut self) -> &mut File {
+        self.0.as_file_mut()
+    }
+
+    /// Return the raw descriptor (on Unix) or handle (on Windows) of the
+    /// underlying file, without any `cfg` on the caller's part.
+    ///
+    /// This is meant for logging and comparing descriptors in code that
+    /// works on every platform. The returned value doesn't own anything: it
+    /// is only valid for as long as this handle is alive, and the handle
+    /// still closes it when dropped. To do something platform specific with
+    /// it, use its `AsRawFd` or `AsRawHandle` implementation.
+    ///
+    /// Unlike [`as_file`], this doesn't panic for handles without a file,
+    /// such as [dangling handles]. For those, it returns an invalid
+    /// descriptor: `-1` on Unix and a null handle on Windows.
+    ///
+    /// [`as_file`]: #method.as_file
+    /// [dangling handles]: #method.dangling
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let h = Handle::stdin()?;
+    /// println!("stdin is {:?}", h.raw());
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn raw(&self) -> RawDescriptor {
+        RawDescriptor(self.0.raw())
+    }
+
+    /// Consume this handle and return the underlying file, if it owns one.
+    ///
+    /// This returns `None` for handles constructed with [`stdin`],
+    /// [`stdout`] or [`stderr`], since those borrow the process's stdio
+    /// streams, and an owned [`File`] would close the stream when dropped.
+    /// It also returns `None` for snapshot handles (see [`is_snapshot`])
+    /// and dangling handles, which have no file. In each case, the handle is
+    /// dropped. Use the [`TryFrom`] implementation instead to get the handle
+    /// back.
+    ///
+    /// [`stdin`]: #method.stdin
+    /// [`stdout`]: #method.stdout
+    /// [`stderr`]: #method.stderr
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`TryFrom`]: #impl-TryFrom%3CHandle%3E-for-File
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    ///
+    /// # Examples
+    /// Check that a file isn't stdout before writing to it:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::io::Write;
+    /// use std::fs::OpenOptions;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let file = OpenOptions::new().append(true).open("./log")?;
+    /// let handle = Handle::from_file(file)?;
+    /// assert_ne!(handle, Handle::stdout()?);
+    /// let mut file = handle.into_file().unwrap();
+    /// writeln!(file, "hello")?;
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn into_file(self) -> Option<File> {
+        self.0.into_file().ok()
+    }
+
+    /// Return a reference to the underlying file if, and only if, this is a
+    /// handle of one of the process's standard streams.
+    ///
+    /// Handles constructed with [`stdin`], [`stdout`] or [`stderr`] borrow
+    /// the process's real stdio descriptor (or handle, on Windows) rather
+    /// than owning it, so dropping such a handle never closes the stream.
+    /// The [`File`] returned here is the borrowed stream itself, and can be
+    /// used to read from or write to it directly, bypassing the buffering
+    /// of [`std::io::stdin`] and friends. This returns `None` for every
+    /// other handle, which owns its file. [`as_file`] returns the file of
+    /// any kind of handle.
+    ///
+    /// The returned reference cannot outlive the handle, so the stream
+    /// cannot be closed through it by accident. Note though that
+    /// [`try_clone`] on a stdio handle produces an owned duplicate, which
+    /// is closed when dropped (without closing the stream itself).
+    ///
+    /// [`stdin`]: #method.stdin
+    /// [`stdout`]: #method.stdout
+    /// [`stderr`]: #method.stderr
+    /// [`as_file`]: #method.as_file
+    /// [`try_clone`]: #method.try_clone
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`std::io::stdin`]: https://doc.rust-lang.org/std/io/fn.stdin.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::io::Read;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let stdin = Handle::stdin()?;
+    /// let mut input = String::new();
+    /// stdin.as_borrowed_file().unwrap().read_to_string(&mut input)?;
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn as_borrowed_file(&self) -> Option<&File> {
+        self.0.as_borrowed_file()
+    }
+
+    /// Open the file of this handle again, with the given options.
+    ///
+    /// The new [`File`] is independent of this handle: it has its own file
+    /// position and access mode. The path the handle was opened with is not
+    /// used, so this works even if the file was renamed since. On Linux and
+    /// Android, the file is opened through `/proc/self/fd`, which works even
+    /// if the file was deleted. On macOS and other Apple platforms, and on
+    /// Windows, the current path of the file is looked up from the handle
+    /// (see [`path`] and [`final_path`]) and opened, and the result is
+    /// checked against this handle in case the path was replaced in the
+    /// meantime.
+    ///
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`path`]: #method.path
+    /// [`final_path`]: #method.final_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file could not be
+    /// opened with the given options. Handles that have no path to reopen,
+    /// such as snapshot handles, pipes on Windows and Apple platforms and
+    /// Windows console handles, always fail. On other platforms, this
+    /// always returns an error of kind [`io::ErrorKind::Unsupported`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`io::ErrorKind::Unsupported`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Unsupported
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::fs::OpenOptions;
+    /// use std::io::Write;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let h = Handle::from_path("./log")?;
+    /// let mut log = h.reopen(OpenOptions::new().append(true))?;
+    /// writeln!(log, "still the same file")?;
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn reopen(&self, options: &fs::OpenOptions) -> io::Result<File> {
+        self.0.reopen(options)
+    }
+
+    /// Create a new handle that refers to the same file as this one.
+    ///
+    /// The underlying file descriptor (or handle, on Windows) is duplicated,
+    /// so the new handle owns its own. Since a duplicate refers to the very
+    /// same open file, the identity of this handle is copied over as is,
+    /// without querying the file system again. Cloning a snapshot handle
+    /// produces another snapshot handle. Cloning a stdio handle produces a
+    /// handle that owns its duplicate, which is closed when it is dropped.
+    ///
+    /// See [`try_clone_fresh`] for a variant that queries the identity anew.
+    ///
+    /// [`try_clone_fresh`]: #method.try_clone_fresh
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file descriptor or
+    /// handle could not be duplicated.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn try_clone(&self) -> io::Result<Handle> {
+        self.0.try_clone().map(Handle)
+    }
+
+    /// Create a new handle that refers to the same file as this one, and
+    /// query its identity again.
+    ///
+    /// This is like [`try_clone`], except that the identity of the new handle
+    /// is obtained from the file system instead of being copied. This costs
+    /// an extra system call, but it means the new handle reflects the file's
+    /// current identity, which differs from the original's only if the
+    /// file's identity changed while it was open (e.g., due to an overlayfs
+    /// copy up). Comparing the two handles detects such a change.
+    ///
+    /// [`try_clone`]: #method.try_clone
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file descriptor or
+    /// handle could not be duplicated, or if the file's metadata cannot be
+    /// obtained. In particular, this fails for snapshot handles, which have
+    /// nothing to duplicate.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn try_clone_fresh(&self) -> io::Result<Handle> {
+        self.0.try_clone_fresh().map(Handle)
+    }
+
+    /// Returns true if this handle is a snapshot of a file's identity rather
+    /// than an open file.
+    ///
+    /// Snapshots are only ever produced on Unix: by [`HandleOptions::open`]
+    /// with [`HandleOptions::snapshot_fallback`] or
+    /// [`HandleOptions::stat_only`] enabled, by [`from_dir_entry_snapshot`],
+    /// by [`from_link`] on platforms other than Linux and Android, and for
+    /// files that can't be opened at all, such as Unix domain sockets on
+    /// those platforms (see [`from_path`]). A
+    /// snapshot holds no open file, so the identity it captured is not
+    /// pinned down: if the file is deleted, its identity may be reused by
+    /// another file. Methods that need an open file return an error for
+    /// snapshots, and [`as_file`] panics.
+    ///
+    /// [`HandleOptions::open`]: struct.HandleOptions.html#method.open
+    /// [`HandleOptions::snapshot_fallback`]: struct.HandleOptions.html#method.snapshot_fallback
+    /// [`HandleOptions::stat_only`]: struct.HandleOptions.html#method.stat_only
+    /// [`from_dir_entry_snapshot`]: #method.from_dir_entry_snapshot
+    /// [`from_link`]: #method.from_link
+    /// [`from_path`]: #method.from_path
+    /// [`as_file`]: #method.as_file
+    pub fn is_snapshot(&self) -> bool {
+        self.0.is_snapshot()
+    }
+
+    /// Returns true if this handle's identity is a canonical path rather
+    /// than the identity of an open file.
+    ///
+    /// Path-based handles are only ever produced on Windows, by
+    /// [`from_path_or_canonical`] when a file can't be opened. They only
+    /// compare equal to other path-based handles with the same canonical
+    /// path. See [`from_path_or_canonical`] for the weaker guarantee this
+    /// provides.
+    ///
+    /// [`from_path_or_canonical`]: #method.from_path_or_canonical
+    pub fn is_path_based(&self) -> bool {
+        self.0.is_path_based()
+    }
+
+    /// Return the type of this handle's file.
+    ///
+    /// The type is determined once, when the handle is constructed, so this
+    /// doesn't query the file system. Handles of different types never
+    /// compare equal, and comparing them returns early without looking at
+    /// their identities.
+    ///
+    /// On Windows, only directories and other files are told apart, and a
+    /// handle without an identity (see [`key`]) has a type of
+    /// [`FileKind::Other`].
+    ///
+    /// [`key`]: #method.key
+    /// [`FileKind::Other`]: enum.FileKind.html#variant.Other
+    pub fn kind(&self) -> FileKind {
+        self.0.kind()
+    }
+
+    /// Return the identity of this handle's file as a [`FileKey`].
+    ///
+    /// This returns `None` if the handle has no identity, for example, a
+    /// [`dangling`] handle, a Windows console handle, a file on QNX whose
+    /// resource manager doesn't assign inode numbers, or a node on Fuchsia,
+    /// Emscripten, ESP-IDF or a VxWorks dosFs volume whose inode number is
+    /// unknown. Such a handle never compares equal to any other handle.
+    ///
+    /// See the [`FileKey`] documentation for caveats about using a key after
+    /// its handle has been dropped.
+    ///
+    /// [`FileKey`]: struct.FileKey.html
+    /// [`dangling`]: #method.dangling
+    ///
+    /// # Examples
+    /// Group paths by the file they refer to:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::collections::HashMap;
+    /// use std::path::PathBuf;
+    /// use same_file::{FileKey, Handle};
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let mut groups: HashMap<FileKey, Vec<PathBuf>> = HashMap::new();
+    /// for path in &["./a", "./b", "./c"] {
+    ///     if let Some(key) = Handle::from_path(path)?.key() {
+    ///         groups.entry(key).or_default().push(PathBuf::from(path));
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn key(&self) -> Option<FileKey> {
+        self.0.key().map(|key| FileKey(key, self.0.generation()))
+    }
+
+    /// Return the identity of this handle's file as a pair of raw numbers.
+    ///
+    /// The first number identifies the volume the file is on, and the second
+    /// one identifies the file within that volume. On Unix, these are the
+    /// device and inode numbers. On Windows, these are the volume serial
+    /// number and the file index. The index is widened to 128 bits, which is
+    /// enough for file IDs on every file system (e.g., ReFS).
+    ///
+    /// Two handles with an identity compare equal exactly when their parts
+    /// are equal. This is meant for logging and diagnostics, where a
+    /// [`FileKey`] can't be serialized (unless the `serde` feature is
+    /// enabled). Like [`key`], this returns `None` if the handle has no
+    /// identity.
+    ///
+    /// [`FileKey`]: struct.FileKey.html
+    /// [`key`]: #method.key
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let handle = Handle::from_path("./foo")?;
+    /// if let Some((volume, index)) = handle.identity_parts() {
+    ///     println!("volume={:x} index={:x}", volume, index);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn identity_parts(&self) -> Option<(u64, u128)> {
+        self.0.key().map(|key| key.parts())
+    }
+
+    /// Return the identifier of the hard link through which this handle's
+    /// file was opened.
+    ///
+    /// A file with several hard links has a single identity, so handles
+    /// opened through any of its links compare equal. On APFS, each link
+    /// additionally has its own identifier, which this returns (it is the
+    /// `ATTR_CMNEXT_LINKID` attribute). For a file with a single link, the
+    /// link identifier is the same as its inode number. Use [`same_link`] to
+    /// compare two paths by link instead of by file.
+    ///
+    /// [`same_link`]: fn.same_link.html
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the link identifier could
+    /// not be queried. On platforms other than macOS, iOS, tvOS, watchOS
+    /// and visionOS, or without the `backend-libc` feature, this always
+    /// returns an error of kind [`io::ErrorKind::Unsupported`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`io::ErrorKind::Unsupported`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Unsupported
+    pub fn link_id(&self) -> io::Result<u64> {
+        self.0.link_id()
+    }
+
+    /// Returns true if this handle and the given file refer to the same file.
+    ///
+    /// This is like comparing this handle with one constructed by
+    /// [`from_file`], except the file is borrowed instead of consumed, so it
+    /// remains usable afterwards. Like such a comparison, this returns false
+    /// if either file has no identity.
+    ///
+    /// [`from_file`]: #method.from_file
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the metadata for the
+    /// given [`File`] cannot be obtained.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    ///
+    /// # Examples
+    /// Check whether a file handed to us is stdout:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::fs::File;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let file = File::open("./source")?;
+    /// if Handle::stdout()?.same_as_file(&file)? {
+    ///     println!("./source is stdout!");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn same_as_file(&self, file: &File) -> io::Result<bool> {
+        self.0.same_as_file(file)
+    }
+
+    /// Returns true if this handle is equal to any of the given handles.
+    ///
+    /// # Examples
+    /// Detect a symbolic link loop while walking a directory tree, given the
+    /// handles of the directories leading up to the current one:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let ancestors = vec![Handle::from_path(".")?, Handle::from_path("a")?];
+    /// if Handle::from_path("a/b")?.matches_any(&ancestors) {
+    ///     println!("a/b is a loop!");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn matches_any(&self, others: &[Handle]) -> bool {
+        others.iter().any(|other| self == other)
+    }
+
+    /// Returns true if this handle refers to the same file as any of the
+    /// given paths.
+    ///
+    /// The paths are opened one at a time, in order, and this stops at the
+    /// first one that matches. Paths after that are never opened.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if one of the paths that
+    /// had to be checked cannot be opened, for the same reasons as
+    /// [`from_path`]. Paths after it are not checked.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`from_path`]: #method.from_path
+    ///
+    /// # Examples
+    /// Check whether a file is one of a few well known files:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::path::PathBuf;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let known = vec![PathBuf::from("./source"), PathBuf::from("./target")];
+    /// if Handle::from_path("./file")?.matches_any_path(&known)? {
+    ///     println!("./file is well known!");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn matches_any_path(&self, paths: &[PathBuf]) -> io::Result<bool> {
+        for p in paths {
+            if *self == Handle::from_path(p)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Compare two handles, treating handles without an identity as equal
+    /// when they wrap the same raw handle or file descriptor.
+    ///
+    /// Handles that have an identity (see [`key`]) are compared exactly like
+    /// with `==`. Handles without one, such as a Windows console handle, are
+    /// compared by their raw handle (or file descriptor) value instead. For
+    /// example, two handles to the same console from [`stdout`] are equal
+    /// according to this method, but never according to `==`. A handle with
+    /// an identity never equals one without.
+    ///
+    /// This is deliberately *not* what `==` does, since the same raw value
+    /// doesn't always mean the same file. Once a handle or descriptor is
+    /// closed, its value can be reused for something unrelated, so this is
+    /// only meaningful while both handles are known to be open, and only for
+    /// values that nothing else in the process closes and reopens (like the
+    /// stdio streams).
+    ///
+    /// [`key`]: #method.key
+    /// [`stdout`]: #method.stdout
+    pub fn weak_eq(&self, other: &Handle) -> bool {
+        self.0.weak_eq(&other.0)
+    }
+
+    /// Returns true if both handles refer to files on the same volume.
+    ///
+    /// On Unix, this compares device numbers. On Windows, this compares
+    /// volume serial numbers. Handles without an identity (such as a
+    /// Windows console handle) are never on the same volume as anything.
+    pub fn same_volume(&self, other: &Handle) -> bool {
+        self.0.same_volume(&other.0)
+    }
+
+    /// Returns true if both handles have the same file index, ignoring which
+    /// volume they are on.
+    ///
+    /// On Unix, this compares inode numbers. On Windows, this compares file
+    /// indices. Handles without an identity (such as a Windows console
+    /// handle) never have the same index as anything.
+    ///
+    /// This is intentionally weaker than equality and is prone to false
+    /// positives: file indices are only unique within a single volume, so
+    /// two unrelated files on different volumes can easily share one. It is
+    /// meant as a first pass heuristic, for example, when matching files that
+    /// were moved between volumes. Pair it with [`same_volume`] when a
+    /// multi-stage match is needed.
+    ///
+    /// [`same_volume`]: #method.same_volume
+    pub fn same_inode(&self, other: &Handle) -> bool {
+        self.0.same_inode(&other.0)
+    }
+
+    /// Return the underlying device number of this handle.
+    ///
+    /// Note that this only works on unix platforms and Hermit.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix))]
+    pub fn dev(&self) -> u64 {
+        self.0.dev()
+    }
+
+    /// Return the device number that this handle's file stands for, if it
+    /// is a device node.
+    ///
+    /// This is the `st_rdev` field reported by `stat`, i.e., the major and
+    /// minor number of the device, as opposed to [`dev`], which is the
+    /// device containing the file. It is captured when the handle is
+    /// constructed, and is `None` for anything but character and block
+    /// devices. See [`same_device_node`] for comparing it.
+    ///
+    /// Note that this only works on unix platforms.
+    ///
+    /// [`dev`]: #method.dev
+    /// [`same_device_node`]: fn.same_device_node.html
+    #[cfg(any(target_os = "redox", unix))]
+    pub fn rdev(&self) -> Option<u64> {
+        self.0.rdev()
+    }
+
+    /// Return the underlying inode number of this handle.
+    ///
+    /// The full 64-bit inode number is returned on every target, including
+    /// 32-bit ones, regardless of whether they were built with large file
+    /// support. Inode numbers are never truncated, so files whose inode
+    /// numbers only differ above the low 32 bits never compare equal.
+    ///
+    /// Note that this only works on unix platforms and Hermit.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix))]
+    pub fn ino(&self) -> u64 {
+        self.0.ino()
+    }
+
+    /// Return the current path of the file this handle refers to.
+    ///
+    /// The path is retrieved from the open descriptor with
+    /// `fcntl(F_GETPATH)`, so if the file was renamed after the handle was
+    /// opened, then the new name is returned. This is useful in diagnostics,
+    /// where a path is much more actionable than a device and inode number.
+    ///
+    /// Note that this only works on Apple platforms.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the path could not be
+    /// determined. This is the case for handles that don't refer to a file
+    /// in the file system, such as stdio handles attached to a terminal or
+    /// a pipe.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    ))]
+    pub fn path(&self) -> io::Result<PathBuf> {
+        self.0.path()
+    }
+
+    /// Returns true if both handles refer to files on the same volume, as
+    /// identified by volume UUID.
+    ///
+    /// This is like [`same_volume`], except that volume UUIDs, unlike device
+    /// numbers, are stable across reboots. Volume UUIDs are only queried
+    /// when the device numbers of the handles differ.
+    ///
+    /// Since macOS Catalina, the read-only system volume and the writable
+    /// data volume are joined by firmlinks, so a path like `/Users/me/file`
+    /// and `/System/Volumes/Data/Users/me/file` refer to the same file on the
+    /// data volume. Handles constructed from either path compare equal, and
+    /// both report the data volume. However, the system volume and the data
+    /// volume are still two volumes with UUIDs of their own, even though
+    /// they form an APFS volume group. So a file under `/System` is not on
+    /// the same volume as one under `/System/Volumes/Data`.
+    ///
+    /// Note that this only works on Apple platforms, with the `backend-libc`
+    /// feature.
+    ///
+    /// [`same_volume`]: #method.same_volume
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the volume UUID of either
+    /// handle could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    ))]
+    pub fn same_volume_uuid(&self, other: &Handle) -> io::Result<bool> {
+        self.0.same_volume_uuid(&other.0)
+    }
+
+    /// Returns true if both handles' files appear to currently share storage.
+    ///
+    /// On APFS, a clone (as created by `clonefile(2)`, `cp -c` or duplicating
+    /// a file in Finder) is a new file with its own identity, so it doesn't
+    /// compare equal to the original. Until either of them is modified, their
+    /// contents are stored in the same blocks on disk, though.
+    ///
+    /// This is a heuristic: it maps the first byte of each file to its
+    /// location on the underlying device (with `fcntl(F_LOG2PHYS_EXT)`) and
+    /// compares those. It therefore reports false once the beginning of
+    /// either file has been rewritten, even if the rest is still shared, and
+    /// it always reports false for empty files. Handles to the same file
+    /// trivially share storage.
+    ///
+    /// Note that this only works on Apple platforms, with the `backend-libc`
+    /// feature.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the location of either
+    /// file could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    ))]
+    pub fn shares_storage(&self, other: &Handle) -> io::Result<bool> {
+        self.0.shares_storage(&other.0)
+    }
+
+    /// Return the inode generation number of this handle's file, if it is
+    /// known.
+    ///
+    /// File systems may reuse an inode number after the file it belonged to
+    /// is deleted. Some of them (such as ext4 and XFS) keep a generation
+    /// number for each inode that changes whenever that happens, so the
+    /// generation number can tell an old file apart from a new file that
+    /// happens to have the same inode number. This matters when an identity
+    /// is remembered after its handle is closed. Comparing two open handles
+    /// for equality never needs it (and never uses it), since neither file
+    /// can be deleted while its handle is open.
+    ///
+    /// The generation number is captured when the handle is constructed. On
+    /// Linux and Android, it is queried with the `FS_IOC_GETVERSION` ioctl.
+    /// If that fails (for example, because the file system doesn't support
+    /// it), this returns `None`. On FreeBSD, NetBSD and OpenBSD, it is the
+    /// `st_gen` field reported by `fstat`, which is only visible to the owner
+    /// of the file and to root. When it is hidden, this returns `None`. On
+    /// other platforms, this always returns `None`.
+    ///
+    /// Snapshot handles (see [`is_snapshot`]) have a generation number on
+    /// the BSDs, where it is part of the metadata they are made from, but not
+    /// on Linux and Android, where the ioctl needs an open file.
+    ///
+    /// The generation number is also part of the handle's [`key`], where
+    /// [`FileKey::eq_with_generation`] can use it to detect reuse of a
+    /// remembered inode number.
+    ///
+    /// Note that this only works on unix platforms.
+    ///
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`key`]: #method.key
+    /// [`FileKey::eq_with_generation`]: struct.FileKey.html#method.eq_with_generation
+    #[cfg(any(target_os = "redox", unix))]
+    pub fn generation(&self) -> Option<u64> {
+        self.0.generation()
+    }
+
+    /// Return the time this handle's file was last modified, as of when the
+    /// handle was constructed.
+    ///
+    /// This is a snapshot, not a live value: it is recorded along with the
+    /// identity of the file (from `st_mtime` on Unix and `ftLastWriteTime`
+    /// on Windows), and does not change when the file is modified later.
+    /// This makes it convenient for incremental tools that remember a file's
+    /// identity together with its modification time, since both are
+    /// obtained at once.
+    ///
+    /// This returns `None` for handles without an identity (see [`key`]),
+    /// and if the modification time isn't available.
+    ///
+    /// [`key`]: #method.key
+    pub fn modified_at_open(&self) -> Option<SystemTime> {
+        self.0.modified_at_open()
+    }
+
+    /// Returns true if the two handles are of the same file, and the file
+    /// had the same modification time when each of them was constructed.
+    ///
+    /// This is stricter than `==`: two handles of the same file compare
+    /// equal no matter when they were opened, but if the file was modified
+    /// in between, then this returns false. It is meant for telling whether
+    /// a file was left alone between two observations, e.g., when
+    /// deduplicating files whose contents were read in the meantime. It
+    /// relies on the modification times recorded by [`modified_at_open`],
+    /// so it returns false if either of them is unknown.
+    ///
+    /// Note that this is only meaningful when both handles were opened close
+    /// in time. Modification times have a limited granularity (as coarse as
+    /// two seconds on FAT), and can be set to anything by whoever may write
+    /// to the file, so a modification doesn't always change them.
+    ///
+    /// [`modified_at_open`]: #method.modified_at_open
+    pub fn eq_with_mtime(&self, other: &Handle) -> bool {
+        self == other
+            && self.modified_at_open().is_some()
+            && self.modified_at_open() == other.modified_at_open()
+    }
+
+    /// Returns true if this handle's file has no links left in the file
+    /// system, i.e., its link count is zero.
+    ///
+    /// This is the case for files that were deleted while the handle was
+    /// open, and for anonymous files that never had a name to begin with:
+    /// on Linux, those created by `memfd_create` and those opened with
+    /// `O_TMPFILE`. Such files still have a device and inode number that
+    /// are valid for as long as they are open (memfds live on an internal
+    /// tmpfs mount), so handles to them compare equal exactly when they
+    /// refer to the same anonymous file, for example, when one descriptor
+    /// was duplicated from the other or received over a Unix socket. Once an
+    /// `O_TMPFILE` file is given a name with `linkat`, it is no longer
+    /// unlinked, and a handle opened from that name compares equal to one
+    /// opened from the original descriptor.
+    ///
+    /// Note that an unlinked file has no path, so [`reopen`] only works for
+    /// it where it doesn't need one (on Linux and Android), and
+    /// [`canonical_path`] is stale if it was ever set.
+    ///
+    /// This queries the link count each time it is called.
+    ///
+    /// Note that this only works on unix platforms.
+    ///
+    /// [`reopen`]: #method.reopen
+    /// [`canonical_path`]: #method.canonical_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file's metadata could
+    /// not be queried, or if this is a snapshot handle.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "redox", unix))]
+    pub fn is_unlinked(&self) -> io::Result<bool> {
+        self.0.is_unlinked()
+    }
+
+    /// Return the identifier of the file system containing this handle's
+    /// file.
+    ///
+    /// This is the `f_fsid` field reported by `fstatvfs`. Unlike the device
+    /// number returned by [`dev`], which some file systems allocate
+    /// dynamically at mount time, the file system identifier is usually
+    /// derived from the file system itself (for example, from its UUID). It
+    /// is queried each time this method is called, except for snapshot
+    /// handles, which record it when they are constructed. It is not used
+    /// when comparing handles for equality.
+    ///
+    /// On btrfs, each subvolume reports its own device number, and the file
+    /// system identifier mixes in the subvolume as well. Since snapshots are
+    /// subvolumes, a file and its snapshotted copy share an inode number but
+    /// have different device numbers, so they never compare equal. Use
+    /// [`subvolume_id`] together with this method to group btrfs files by
+    /// subvolume in a way that is stable across reboots.
+    ///
+    /// Note that this only works on unix platforms. Some platforms report a
+    /// file system identifier of zero. VxWorks has no `fstatvfs`, so this
+    /// always fails there.
+    ///
+    /// [`dev`]: #method.dev
+    /// [`subvolume_id`]: #method.subvolume_id
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file system
+    /// information could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "redox", unix))]
+    pub fn fs_id(&self) -> io::Result<u64> {
+        self.0.fs_id()
+    }
+
+    /// Compare two handles using the file system identifier instead of the
+    /// device number.
+    ///
+    /// The device number of a network file system (like NFS) is typically
+    /// allocated anew each time it is mounted, so the same file observed
+    /// before and after a remount (for example, by an automounter) has a
+    /// different device number and compares unequal. The file system
+    /// identifier returned by [`fs_id`] is usually derived from the exported
+    /// file system instead, so it survives a remount.
+    ///
+    /// This returns true if the handles are equal, or if they have the same
+    /// inode number and the same file system identifier. This trades
+    /// strictness for stability: some file systems report a file system
+    /// identifier of zero, in which case this is no stronger than
+    /// [`same_inode`]. Prefer `==` unless you are comparing identities
+    /// across remounts.
+    ///
+    /// This only queries the file system identifiers when the handles are
+    /// unequal but have the same inode number. See
+    /// [`ComparisonOptions::fsid`] to compare this way only where it's
+    /// needed.
+    ///
+    /// Note that this only works on unix platforms.
+    ///
+    /// [`fs_id`]: #method.fs_id
+    /// [`same_inode`]: #method.same_inode
+    /// [`ComparisonOptions::fsid`]: struct.ComparisonOptions.html#method.fsid
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file system
+    /// identifier of either handle could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "redox", unix))]
+    pub fn fsid_eq(&self, other: &Handle) -> io::Result<bool> {
+        self.0.fsid_eq(&other.0)
+    }
+
+    /// Return the ID of the mount containing this handle's file.
+    ///
+    /// This is the `stx_mnt_id` field reported by `statx`, which is queried
+    /// each time this method is called. Mount IDs are the same as those in
+    /// `/proc/self/mountinfo`. They identify a mount rather than a file
+    /// system, so two bind mounts of the same file system have different
+    /// mount IDs. See [`is_same_mount`].
+    ///
+    /// This returns `None` if the kernel doesn't report mount IDs, which
+    /// requires Linux 5.8.
+    ///
+    /// Note that this only works on Linux.
+    ///
+    /// [`is_same_mount`]: fn.is_same_mount.html
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file's metadata could
+    /// not be queried, or if this is a snapshot handle.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(target_os = "linux")]
+    pub fn mount_id(&self) -> io::Result<Option<u64>> {
+        self.0.mount_id()
+    }
+
+    /// Return the btrfs subvolume identifier of this handle's file.
+    ///
+    /// This returns `None` when the file is not on a btrfs file system.
+    ///
+    /// Note that this only works on Linux.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file system type or
+    /// the subvolume could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(target_os = "linux")]
+    pub fn subvolume_id(&self) -> io::Result<Option<u64>> {
+        self.0.subvolume_id()
+    }
+
+    /// Returns true if this handle's file is on an overlayfs mount.
+    ///
+    /// This queries the file system type each time it is called.
+    ///
+    /// Note that this only works on Linux and Android.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file system type
+    /// could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn is_overlayfs(&self) -> io::Result<bool> {
+        self.0.is_overlayfs()
+    }
+
+    /// Return the magic number of the type of file system containing this
+    /// handle's file.
+    ///
+    /// This is the `f_type` field reported by `fstatfs`, which is queried
+    /// each time this method is called (except for snapshot handles, which
+    /// record it when they are constructed). It can be compared with the
+    /// `*_SUPER_MAGIC` constants in `libc` (e.g., `FUSE_SUPER_MAGIC`) after
+    /// casting them to `u64`, which lets callers decide how much to trust
+    /// the identities reported by particular file systems. See
+    /// [`ComparisonOptions::distrust_fuse`] for an example.
+    ///
+    /// Note that this only works on Linux and Android.
+    ///
+    /// [`ComparisonOptions::distrust_fuse`]: struct.ComparisonOptions.html#method.distrust_fuse
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file system type
+    /// could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn fs_magic(&self) -> io::Result<u64> {
+        self.0.fs_magic()
+    }
+
+    /// Return the name of the type of file system containing this handle's
+    /// file, such as `zfs`, `tmpfs` or `nfs`.
+    ///
+    /// This is the `st_fstype` field reported by `fstat`, which is queried
+    /// each time this method is called.
+    ///
+    /// On ZFS, every snapshot is a file system of its own, with its own
+    /// device number. So a file opened through its live path and the same
+    /// file opened through `.zfs/snapshot/<name>` never compare equal, which
+    /// is the right answer for backup tools, since the live file can change
+    /// while the snapshot can't. ZFS does preserve object numbers in
+    /// snapshots, though, so [`same_inode`] returns true for them. A file in
+    /// a snapshot is therefore the snapshotted version of a live file on the
+    /// same pool if both report `zfs` here and have the same inode number.
+    ///
+    /// Note that this only works on illumos and Solaris.
+    ///
+    /// [`same_inode`]: #method.same_inode
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file's metadata could
+    /// not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn fs_type(&self) -> io::Result<String> {
+        self.0.fs_type()
+    }
+
+    /// Compare two handles, taking into account that overlayfs can make the
+    /// same file appear with different identities.
+    ///
+    /// Depending on its configuration, overlayfs may report a different
+    /// device and inode number for a file when it is observed through the
+    /// merged mount than when it is observed through one of the underlying
+    /// layers. A file that is copied up to the upper layer (for example,
+    /// because it was opened for writing) may also change identity while it
+    /// is being observed.
+    ///
+    /// A file in the upper layer that was copied up records the file in the
+    /// lower layer that it originates from. So when one handle is for such a
+    /// file and the other handle is for its origin, this returns
+    /// `Some(true)`. (The origin is only visible through the upper layer
+    /// itself, and reading it usually requires `CAP_SYS_ADMIN`.)
+    ///
+    /// Otherwise, this returns `Some(true)` if the handles are equal and
+    /// `Some(false)` if they differ and neither is on overlayfs. If they
+    /// differ and at least one of them is on overlayfs, or the origin of a
+    /// copied up file could not be verified, then it isn't possible to tell
+    /// whether they are the same file, so this returns `None`. Without the
+    /// `backend-libc` feature, the origin can never be verified.
+    ///
+    /// Unlike `==`, this may read the overlayfs origin and query the file
+    /// system type of both handles, but only when they compare unequal.
+    ///
+    /// Note that this only works on Linux and Android.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file system type of
+    /// either handle could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn overlay_eq(&self, other: &Handle) -> io::Result<Option<bool>> {
+        self.0.overlay_eq(&other.0)
+    }
+
+    /// Return the kernel's persistent file handle for this handle's file.
+    ///
+    /// This is the handle returned by `name_to_handle_at(2)`, which is the
+    /// same one an NFS server hands out to its clients. Unlike an inode
+    /// number, it typically includes a generation number, so it remains
+    /// unique even after the file is deleted and its inode number reused.
+    /// This makes it a better identity to persist than a [`FileKey`].
+    ///
+    /// Note that this only works on Linux, with the `backend-libc` feature.
+    ///
+    /// [`FileKey`]: struct.FileKey.html
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the kernel handle could
+    /// not be obtained. Notably, file systems that cannot be exported (such
+    /// as procfs) fail with `EOPNOTSUPP`.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+    pub fn kernel_handle(&self) -> io::Result<KernelHandle> {
+        self.0.kernel_handle()
+    }
+
+    /// Compare two handles by their kernel file handles.
+    ///
+    /// Both handles must be on the same device, and have the same kernel
+    /// file handle, as returned by [`kernel_handle`]. If either file system
+    /// doesn't support kernel file handles, then this falls back to `==`.
+    ///
+    /// Note that this only works on Linux, with the `backend-libc` feature.
+    ///
+    /// [`kernel_handle`]: #method.kernel_handle
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if either kernel handle could
+    /// not be obtained for a reason other than lack of support.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+    pub fn kernel_handle_eq(&self, other: &Handle) -> io::Result<bool> {
+        self.0.kernel_handle_eq(&other.0)
+    }
+
+    /// Return the normalized path of the file this handle refers to.
+    ///
+    /// The path is retrieved from the open handle with
+    /// `GetFinalPathNameByHandleW`, so all symbolic links, junctions and
+    /// 8.3 short names are resolved, and it reflects any renames that
+    /// happened since the handle was opened. This is useful for reporting
+    /// what a chain of symbolic links actually points to.
+    ///
+    /// The `\\?\` prefix that Windows adds is removed when the path can be
+    /// represented without it, so that, for example, `C:\foo` is returned
+    /// instead of `\\?\C:\foo`. Use [`final_path_verbatim`] to keep it.
+    ///
+    /// Note that this only works on Windows.
+    ///
+    /// [`final_path_verbatim`]: #method.final_path_verbatim
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the path of the file
+    /// could not be queried, for example, for console handles and pipes.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(windows)]
+    pub fn final_path(&self) -> io::Result<PathBuf> {
+        self.0.final_path()
+    }
+
+    /// Return the normalized path of the file this handle refers to, with
+    /// its `\\?\` prefix intact.
+    ///
+    /// This is like [`final_path`], except the path is returned exactly as
+    /// Windows reports it.
+    ///
+    /// Note that this only works on Windows.
+    ///
+    /// [`final_path`]: #method.final_path
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] for the same reasons as
+    /// [`final_path`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(windows)]
+    pub fn final_path_verbatim(&self) -> io::Result<PathBuf> {
+        self.0.final_path_verbatim()
+    }
+
+    /// Return the path at which the volume containing this handle's file is
+    /// mounted.
+    ///
+    /// This is typically a drive root like `C:\`, but may also be a folder
+    /// on another volume when the volume is mounted there. If the volume
+    /// isn't mounted anywhere, then its volume GUID path (like
+    /// `\\?\Volume{...}\`) is returned instead.
+    ///
+    /// This is useful for grouping files by physical volume, since the
+    /// volume serial number used for equality isn't meant for humans.
+    ///
+    /// Note that this only works on Windows.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the path of the file or
+    /// its volume could not be queried. This requires the handle to refer to
+    /// an open file on a volume, so it fails for console handles, pipes and
+    /// some other pseudo-files.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(windows)]
+    pub fn volume_path(&self) -> io::Result<PathBuf> {
+        self.0.volume_path()
+    }
+
+    /// Return the NTFS object identifier of this handle's file, if it has
+    /// one.
+    ///
+    /// Object identifiers are assigned on demand (for example, by the
+    /// distributed link tracking service) and survive renames, which makes
+    /// them useful for tracking a file across moves. They are purely
+    /// informational and are not used when comparing handles for equality.
+    ///
+    /// This returns `None` when the file has not been assigned an object
+    /// identifier.
+    ///
+    /// Note that this only works on Windows, and only on volumes that
+    /// support object identifiers (such as NTFS).
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the object identifier
+    /// could not be queried, for example, because the volume does not
+    /// support object identifiers.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(windows)]
+    pub fn ntfs_object_id(&self) -> io::Result<Option<[u8; 16]>> {
+        self.0.ntfs_object_id()
+    }
+
+    /// Return the reparse tag of this handle's file, if it is a reparse
+    /// point.
+    ///
+    /// The tag says what kind of reparse point the file is, e.g.,
+    /// `IO_REPARSE_TAG_SYMLINK` for a symbolic link or
+    /// `IO_REPARSE_TAG_MOUNT_POINT` for a junction or volume mount point.
+    /// It is read with `FSCTL_GET_REPARSE_POINT` and returned as is. This
+    /// returns `None` if the file isn't a reparse point.
+    ///
+    /// Handles normally refer to the file that a reparse point leads to,
+    /// so this is only interesting for handles of files that were opened
+    /// with `FILE_FLAG_OPEN_REPARSE_POINT` (see [`from_file`]), and for
+    /// reparse points that aren't links, such as deduplicated files or
+    /// cloud placeholders (see [`HandleOptions::follow_symlinks`]).
+    ///
+    /// Note that this only works on Windows.
+    ///
+    /// [`from_file`]: #method.from_file
+    /// [`HandleOptions::follow_symlinks`]: struct.HandleOptions.html#method.follow_symlinks
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the reparse point could
+    /// not be read, for example, for console handles and pipes.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::fs::OpenOptions;
+    /// use std::os::windows::fs::OpenOptionsExt;
+    /// use same_file::Handle;
+    ///
+    /// // From winbase.h and winnt.h.
+    /// const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    /// const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+    /// const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let file = OpenOptions::new()
+    ///     .read(true)
+    ///     .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+    ///     .open(r"C:\Users\All Users")?;
+    /// let h = Handle::from_file(file)?;
+    /// if h.reparse_tag()? == Some(IO_REPARSE_TAG_MOUNT_POINT) {
+    ///     println!("a junction");
+    /// }
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(windows)]
+    pub fn reparse_tag(&self) -> io::Result<Option<u32>> {
+        self.0.reparse_tag()
+    }
+
+    /// Returns true if this handle refers to a pipe.
+    ///
+    /// On Windows, every pipe is a named pipe, including the anonymous
+    /// pipes created by `CreatePipe` and [`Stdio::piped`], which merely have
+    /// a name that nobody else knows. Pipes aren't files, so handles of
+    /// pipes have no identity (see [`key`]): a pipe handle only compares
+    /// equal to itself, and two handles of the same pipe, such as the server
+    /// and client ends of a named pipe, compare unequal. Neither the pipe's
+    /// name nor `GetNamedPipeHandleState` identify a pipe instance reliably
+    /// (names can be reused and many instances can share one), so this
+    /// crate doesn't try. Callers that need to treat pipes specially can
+    /// check this first.
+    ///
+    /// This is checked with `GetFileType`. Handles without an open file,
+    /// such as [`dangling`] handles, aren't pipes.
+    ///
+    /// Note that this only works on Windows.
+    ///
+    /// [`Stdio::piped`]: https://doc.rust-lang.org/std/process/struct.Stdio.html#method.piped
+    /// [`key`]: #method.key
+    /// [`dangling`]: #method.dangling
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the type of the file
+    /// could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> std::io::Result<()> {
+    /// let stdout = Handle::stdout()?;
+    /// if stdout.is_named_pipe()? {
+    ///     println!("stdout is piped, so it can't be compared");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(windows)]
+    pub fn is_named_pipe(&self) -> io::Result<bool> {
+        self.0.is_named_pipe()
+    }
+
+    /// Returns true if this handle's directory is case sensitive.
+    ///
+    /// Since Windows 10 (version 1803), individual directories can be marked
+    /// case sensitive, usually for interoperability with WSL. Within such a
+    /// directory, `foo` and `FOO` are different files. Tools that compare
+    /// paths as strings (rather than comparing handles) can use this to
+    /// decide whether names in a directory should be compared case
+    /// insensitively. The flag is queried each time this method is called.
+    ///
+    /// Note that this only works on Windows.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if this handle is not a
+    /// directory, or if the flag could not be queried, for example, because
+    /// the file system or the version of Windows doesn't support it.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    #[cfg(windows)]
+    pub fn is_case_sensitive_dir(&self) -> io::Result<bool> {
+        self.0.is_case_sensitive_dir()
+    }
+
+    /// Returns true if this handle's file is a sparse file.
+    ///
+    /// This checks the `FILE_ATTRIBUTE_SPARSE_FILE` attribute, which is
+    /// queried each time this method is called. A sparse file may or may not
+    /// actually have any unallocated ranges.
+    ///
+    /// Note that this only works on Windows. See [`allocated_size`] for a
+    /// rough equivalent on Unix.
+    ///
+    /// [`allocated_size`]: #method.allocated_size
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file's attributes
+    /// could not be queried, or if this handle has no open file to query
+    /// them from, i.e., if it is path-based (see [`is_path_based`]) or
+    /// dangling (see [`dangling`]).
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`is_path_based`]: #method.is_path_based
+    /// [`dangling`]: #method.dangling
+    #[cfg(windows)]
+    pub fn is_sparse(&self) -> io::Result<bool> {
+        self.0.is_sparse()
+    }
+
+    /// Return the number of bytes of storage allocated to this handle's
+    /// file.
+    ///
+    /// This is `st_blocks * 512` as reported by `fstat`, which is queried each
+    /// time this method is called. A snapshot handle (see [`is_snapshot`]) has
+    /// no file to query, so for those, it is what `stat` reported when the
+    /// snapshot was taken. Comparing it to the length of the file gives a hint
+    /// as to whether the file is sparse: a file that has less storage
+    /// allocated to it than its length probably has holes. This is only a
+    /// heuristic, though. Compression, deduplication and small files stored
+    /// inline with their metadata can all make a file take up less space than
+    /// its length without it being sparse, and preallocation can make a sparse
+    /// file take up more.
+    ///
+    /// Note that this only works on unix platforms. See [`is_sparse`] for
+    /// Windows.
+    ///
+    /// [`is_snapshot`]: #method.is_snapshot
+    /// [`is_sparse`]: #method.is_sparse
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the file's metadata
+    /// could not be queried.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    ///
+    /// # Examples
+    /// Check whether a file looks sparse:
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let handle = Handle::from_path("./disk.img")?;
+    /// let len = std::fs::metadata("./disk.img")?.len();
+    /// if handle.allocated_size()? < len {
+    ///     println!("./disk.img is probably sparse");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    #[cfg(any(target_os = "redox", unix))]
+    pub fn allocated_size(&self) -> io::Result<u64> {
+        self.0.allocated_size()
+    }
+}
+
+/// Construct a handle from a file, taking ownership of it.
+///
+/// This is equivalent to [`Handle::from_file`].
+///
+/// [`Handle::from_file`]: struct.Handle.html#method.from_file
+impl TryFrom<File> for Handle {
+    type Error = io::Error;
+
+    fn try_from(file: File) -> io::Result<Handle> {
+        Handle::from_file(file)
+    }
+}
+
+/// Construct a handle from a borrowed file.
+///
+/// The handle owns a duplicate of the file's descriptor (or handle, on
+/// Windows), so the original file remains usable and the handle can outlive
+/// it. The handle compares equal to any other handle of the same file.
+impl TryFrom<&File> for Handle {
+    type Error = io::Error;
+
+    fn try_from(file: &File) -> io::Result<Handle> {
+        Handle::from_file(file.try_clone()?)
+    }
+}
+
+/// Return the file owned by a handle.
+///
+/// This fails for handles constructed with [`Handle::stdin`],
+/// [`Handle::stdout`] or [`Handle::stderr`], which borrow the process's
+/// stdio streams, and for snapshot handles, which have no file. The error is
+/// the original handle. See [`Handle::into_file`].
+///
+/// [`Handle::stdin`]: struct.Handle.html#method.stdin
+/// [`Handle::stdout`]: struct.Handle.html#method.stdout
+/// [`Handle::stderr`]: struct.Handle.html#method.stderr
+/// [`Handle::into_file`]: struct.Handle.html#method.into_file
+impl TryFrom<Handle> for File {
+    type Error = Handle;
+
+    fn try_from(handle: Handle) -> Result<File, Handle> {
+        handle.0.into_file().map_err(Handle)
+    }
+}
+
+/// An error from constructing a [`Handle`] from a path.
+///
+/// This is returned by [`Handle::from_path_detailed`] and
+/// [`is_same_file_detailed`]. Besides the [`io::Error`] it wraps, it records
+/// the path that failed, whether opening the path or querying the identity
+/// of the opened file failed, and a classification of the failure (see
+/// [`ErrorKind`]).
+///
+/// It converts into the [`io::Error`] it wraps, so it can be propagated with
+/// `?` from functions that return an `io::Result`.
+///
+/// [`Handle`]: struct.Handle.html
+/// [`Handle::from_path_detailed`]: struct.Handle.html#method.from_path_detailed
+/// [`is_same_file_detailed`]: fn.is_same_file_detailed.html
+/// [`ErrorKind`]: enum.ErrorKind.html
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+#[derive(Debug)]
+pub struct Error {
+    opened: bool,
+    path: Option<PathBuf>,
+    err: io::Error,
+}
+
+impl Error {
+    /// An error from opening a path.
+    pub(crate) fn open(err: io::Error) -> Error {
+        Error { opened: false, path: None, err }
+    }
+
+    /// An error from querying the identity of a file that was opened.
+    ///
+    /// Nothing can be opened on unsupported platforms.
+    #[cfg_attr(
+        not(any(target_os = "redox", target_os = "hermit", unix, windows)),
+        allow(dead_code)
+    )]
+    pub(crate) fn stat(err: io::Error) -> Error {
+        Error { opened: true, path: None, err }
+    }
+
+    fn with_path(mut self, path: &Path) -> Error {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Return the underlying I/O error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.err
+    }
+
+    /// Return the path that could not be opened or queried, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns true if the path could not be opened, e.g., because it
+    /// doesn't exist or because there were not enough permissions to open
+    /// it.
+    pub fn is_open_error(&self) -> bool {
+        !self.opened
+    }
+
+    /// Returns true if the path was opened, but the identity of the file
+    /// could not be queried, which is rare.
+    pub fn is_stat_error(&self) -> bool {
+        self.opened
+    }
+
+    /// Return the classification of this error.
+    ///
+    /// On Unix, this is based on the `errno` value of the underlying I/O
+    /// error. Elsewhere, it is based on the error's [`io::ErrorKind`].
+    ///
+    /// [`io::ErrorKind`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::classify(&self.err)
+    }
+
+    /// Returns true if trying again later may succeed.
+    ///
+    /// This is the case for errors of kind [`ErrorKind::TooManyOpenFiles`]
+    /// and [`ErrorKind::Interrupted`].
+    ///
+    /// [`ErrorKind::TooManyOpenFiles`]: enum.ErrorKind.html#variant.TooManyOpenFiles
+    /// [`ErrorKind::Interrupted`]: enum.ErrorKind.html#variant.Interrupted
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind(),
+            ErrorKind::TooManyOpenFiles | ErrorKind::Interrupted
+        )
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.opened {
+            write!(f, "failed to query file identity")?;
+        } else {
+            write!(f, "failed to open file")?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, " {}", path.display())?;
+        }
+        write!(f, ": {}", self.err)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        err.err
+    }
+}
+
+/// The classification of an [`Error`].
+///
+/// This is returned by [`Error::kind`]. It groups the failures that callers
+/// typically handle differently: a file that vanished can be skipped, a
+/// permission problem may be worth a warning, and running out of file
+/// descriptors calls for backing off and trying again.
+///
+/// [`Error`]: struct.Error.html
+/// [`Error::kind`]: struct.Error.html#method.kind
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ErrorKind {
+    /// The file does not exist (`ENOENT` on Unix).
+    NotFound,
+    /// There were not enough permissions (`EACCES` or `EPERM` on Unix).
+    PermissionDenied,
+    /// The process or the system has too many open files (`EMFILE` or
+    /// `ENFILE` on Unix).
+    TooManyOpenFiles,
+    /// The operation was interrupted or would have blocked (`EINTR` or
+    /// `EAGAIN` on Unix).
+    Interrupted,
+    /// Anything else.
+    Other,
+}
+
+impl ErrorKind {
+    fn classify(err: &io::Error) -> ErrorKind {
+        #[cfg(unix)]
+        match err.raw_os_error() {
+            Some(imp::c::ENOENT) => return ErrorKind::NotFound,
+            Some(imp::c::EACCES) | Some(imp::c::EPERM) => {
+                return ErrorKind::PermissionDenied
+            }
+            Some(imp::c::EMFILE) | Some(imp::c::ENFILE) => {
+                return ErrorKind::TooManyOpenFiles
+            }
+            Some(imp::c::EINTR) | Some(imp::c::EAGAIN) => {
+                return ErrorKind::Interrupted
+            }
+            Some(_) => return ErrorKind::Other,
+            None => {}
+        }
+        #[cfg(windows)]
+        {
+            use windows_sys::Win32::Foundation::ERROR_TOO_MANY_OPEN_FILES;
+
+            if err.raw_os_error() == Some(ERROR_TOO_MANY_OPEN_FILES as i32) {
+                return ErrorKind::TooManyOpenFiles;
+            }
+        }
+        match err.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => {
+                ErrorKind::Interrupted
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// The type of the file a [`Handle`] refers to.
+///
+/// This is returned by [`Handle::kind`].
+///
+/// [`Handle`]: struct.Handle.html
+/// [`Handle::kind`]: struct.Handle.html#method.kind
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum FileKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// Anything else, e.g., a device, FIFO or socket.
+    Other,
+}
+
+/// The outcome of comparing two paths with [`compare_paths`].
+///
+/// [`compare_paths`]: fn.compare_paths.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Comparison {
+    /// Both paths exist and refer to the same file.
+    Same,
+    /// Both paths exist and refer to different files.
+    Different,
+    /// The first path does not exist. This is also the outcome if neither
+    /// path exists.
+    FirstMissing,
+    /// The first path exists, but the second one does not.
+    SecondMissing,
+}
+
+/// The identity of a file, detached from any open handle.
+///
+/// A key is a small value that can be copied, compared, hashed and ordered,
+/// which makes it suitable as a key in a `HashMap` or `BTreeMap`. It is
+/// obtained from a [`Handle`] with [`Handle::key`]. Two handles that compare
+/// equal have equal keys.
+///
+/// On Unix, a key consists of a device and inode number. On Windows, it
+/// consists of a volume serial number and a file index (which is 128 bits
+/// wide to accommodate ReFS). The contents are otherwise opaque.
+///
+/// A key may also carry the inode generation number of its file (see
+/// [`Handle::generation`]), which is not part of its identity: keys are
+/// compared, ordered and hashed by their device and inode numbers alone, so
+/// that the keys of equal handles are always equal, whether or not the
+/// generation number could be queried for each of them. Use
+/// [`eq_with_generation`] to take it into account as well.
+///
+/// # Caveats
+///
+/// A key is only trustworthy while some handle to its file remains open.
+/// File systems are free to reuse an inode number or file index once the
+/// file it belonged to is deleted, so a key that is kept around after its
+/// handle is dropped may later compare equal to the key of an unrelated file.
+/// Extracting keys and dropping handles trades this correctness guarantee for
+/// memory (and open file descriptors). See the [source] for more details.
+///
+/// # Serialization
+///
+/// When the `serde` feature is enabled, keys implement `Serialize` and
+/// `Deserialize`. A key is represented as a struct named `FileKey` with a
+/// `volume` field (a `u64`) and an `index` field (a `u128`), which are
+/// the numbers returned by [`Handle::identity_parts`], and a `generation`
+/// field (an `Option<u64>`), which is the number returned by
+/// [`generation`]. The `generation` field may be missing in
+/// self-describing formats such as JSON. This representation is the same
+/// on every platform and will not change. Deserializing a key whose index
+/// doesn't fit on the current platform (e.g., a 128-bit ReFS index on Unix)
+/// fails.
+///
+/// Persisted keys are advisory. By the time a key is read back, the file it
+/// was taken from may have been deleted and its identifier reused for an
+/// unrelated file (see above), so a matching key suggests, but doesn't
+/// prove, that a file is the same one as before. A differing key does prove
+/// that a path now names a different file. Where generation numbers are
+/// available, [`eq_with_generation`] closes most of this gap. On Linux, see
+/// [`Handle::kernel_handle`] for an identity that is better suited to being
+/// persisted.
+///
+/// [`Handle`]: struct.Handle.html
+/// [`Handle::key`]: struct.Handle.html#method.key
+/// [`Handle::identity_parts`]: struct.Handle.html#method.identity_parts
+/// [`Handle::kernel_handle`]: struct.Handle.html#method.kernel_handle
+/// [`Handle::generation`]: struct.Handle.html#method.generation
+/// [`eq_with_generation`]: #method.eq_with_generation
+/// [`generation`]: #method.generation
+/// [source]: https://github.com/BurntSushi/same-file/tree/master/src
+#[derive(Clone, Copy, Debug)]
+pub struct FileKey(imp::Key, Option<u64>);
+
+impl PartialEq for FileKey {
+    fn eq(&self, other: &FileKey) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for FileKey {}
+
+impl Hash for FileKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl PartialOrd for FileKey {
+    fn partial_cmp(&self, other: &FileKey) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FileKey {
+    fn cmp(&self, other: &FileKey) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl FileKey {
+    /// Return the inode generation number recorded in this key, if any.
+    ///
+    /// This is the [`Handle::generation`] of the handle the key was taken
+    /// from, or the generation number it was deserialized with. It is always
+    /// `None` on platforms without generation numbers, and for keys returned
+    /// by [`keys_for_uring`].
+    ///
+    /// [`Handle::generation`]: struct.Handle.html#method.generation
+    /// [`keys_for_uring`]: fn.keys_for_uring.html
+    pub fn generation(&self) -> Option<u64> {
+        self.1
+    }
+
+    /// Returns true if the two keys are equal, and don't have different
+    /// generation numbers.
+    ///
+    /// This is stricter than `==` when both keys have a generation number:
+    /// if a file was deleted and its inode number reused by a new file, the
+    /// keys of the two files are equal, but their generation numbers (on
+    /// file systems that have them) are not. This makes it the comparison
+    /// to use between a key that was remembered (e.g., persisted with the
+    /// `serde` feature) and the key of a file that is open now. If either
+    /// key has no generation number, this is the same as `==`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use same_file::{FileKey, Handle};
+    ///
+    /// # fn try_main() -> std::io::Result<()> {
+    /// # let remembered: FileKey = Handle::from_path("./index")?.key().unwrap();
+    /// if let Some(key) = Handle::from_path("./index")?.key() {
+    ///     if !key.eq_with_generation(&remembered) {
+    ///         println!("./index was replaced");
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    pub fn eq_with_generation(&self, other: &FileKey) -> bool {
+        match (self.1, other.1) {
+            (Some(gen1), Some(gen2)) if gen1 != gen2 => false,
+            _ => self == other,
+        }
+    }
+}
+
+/// The raw descriptor or handle of a [`Handle`]'s file.
+///
+/// This is returned by [`Handle::raw`]. It wraps a `RawFd` on Unix and a
+/// `RawHandle` on Windows, which can be obtained with the `AsRawFd` and
+/// `AsRawHandle` implementations. It can be printed with `Debug`, compared
+/// and hashed on every platform, and owns nothing.
+///
+/// [`Handle`]: struct.Handle.html
+/// [`Handle::raw`]: struct.Handle.html#method.raw
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct RawDescriptor(imp::RawDescriptor);
+
+/// A file handle, as returned by the Linux `name_to_handle_at(2)` system
+/// call.
+///
+/// This is obtained with [`Handle::kernel_handle`]. The handle itself is
+/// opaque and only meaningful to the file system that produced it, which in
+/// turn is identified by the mount ID. Note that the same file system may be
+/// mounted more than once (e.g., via bind mounts), each with a different
+/// mount ID.
+///
+/// [`Handle::kernel_handle`]: struct.Handle.html#method.kernel_handle
+#[cfg(all(target_os = "linux", feature = "backend-libc"))]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct KernelHandle {
+    mount_id: i32,
+    handle_type: i32,
+    bytes: Vec<u8>,
+}
+
+#[cfg(all(target_os = "linux", feature = "backend-libc"))]
+impl KernelHandle {
+    /// Return the ID of the mount the handle was obtained through.
+    pub fn mount_id(&self) -> i32 {
+        self.mount_id
+    }
+
+    /// Return the file system specific type of the handle.
+    pub fn handle_type(&self) -> i32 {
+        self.handle_type
+    }
+
+    /// Return the opaque bytes of the handle.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// A handle that is guaranteed to have an identity.
+///
+/// Some handles have no identity, for example, a Windows console handle.
+/// Such a handle never compares equal to any other handle, which is usually
+/// the right thing to do, but it can also mask bugs in a program that
+/// expects every file it sees to be a real file. Constructing a
+/// `StrictHandle` fails instead, so comparisons between strict handles are
+/// always based on real identity.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::StrictHandle;
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let stdout = StrictHandle::stdout()?;
+/// let stderr = StrictHandle::stderr()?;
+/// if stdout == stderr {
+///     println!("stdout and stderr are the same file");
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+#[derive(Debug, Eq, PartialEq, Hash)]
+pub struct StrictHandle(Handle);
+
+impl StrictHandle {
+    /// Construct a strict handle from a handle.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the given handle has no
+    /// identity, i.e., if [`Handle::key`] returns `None`.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`Handle::key`]: struct.Handle.html#method.key
+    pub fn new(handle: Handle) -> io::Result<StrictHandle> {
+        if handle.key().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "handle has no file identity",
+            ));
+        }
+        Ok(StrictHandle(handle))
+    }
+
+    /// Construct a strict handle from stdin.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if stdin cannot be opened,
+    /// or if it has no identity (e.g., a Windows console).
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn stdin() -> io::Result<StrictHandle> {
+        StrictHandle::new(Handle::stdin()?)
+    }
+
+    /// Construct a strict handle from stdout.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if stdout cannot be opened,
+    /// or if it has no identity (e.g., a Windows console).
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn stdout() -> io::Result<StrictHandle> {
+        StrictHandle::new(Handle::stdout()?)
+    }
+
+    /// Construct a strict handle from stderr.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if stderr cannot be opened,
+    /// or if it has no identity (e.g., a Windows console).
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn stderr() -> io::Result<StrictHandle> {
+        StrictHandle::new(Handle::stderr()?)
+    }
+
+    /// Return the identity of this handle's file.
+    ///
+    /// Unlike [`Handle::key`], this always succeeds.
+    ///
+    /// [`Handle::key`]: struct.Handle.html#method.key
+    pub fn key(&self) -> FileKey {
+        // unwrap() will not panic. Construction ensures there is a key.
+        self.0.key().unwrap()
+    }
+
+    /// Return a reference to the underlying handle.
+    pub fn as_handle(&self) -> &Handle {
+        &self.0
+    }
+
+    /// Consume this strict handle and return the underlying handle.
+    pub fn into_handle(self) -> Handle {
+        self.0
+    }
+}
+
+/// Options for constructing a [`Handle`] from a path.
+///
+/// This follows the builder style of [`OpenOptions`]: create a value with
+/// [`new`], set the options of interest, then call [`open`]. With no options
+/// set, [`open`] behaves exactly like [`Handle::from_path`].
+///
+/// [`Handle`]: struct.Handle.html
+/// [`Handle::from_path`]: struct.Handle.html#method.from_path
+/// [`OpenOptions`]: https://doc.rust-lang.org/std/fs/struct.OpenOptions.html
+/// [`new`]: #method.new
+/// [`open`]: #method.open
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::HandleOptions;
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let mut opts = HandleOptions::new();
+/// opts.snapshot_fallback(true);
+/// let h1 = opts.open("/data/data/com.example/files/config")?;
+/// let h2 = opts.open("./config")?;
+/// println!("same file: {}", h1 == h2);
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HandleOptions {
+    snapshot_fallback: bool,
+    stat_only: bool,
+    dont_sync: bool,
+    preserve_atime: bool,
+    require_directory: bool,
+    follow_symlinks: bool,
+    backup_semantics: bool,
+}
+
+impl Default for HandleOptions {
+    fn default() -> HandleOptions {
+        HandleOptions {
+            snapshot_fallback: false,
+            stat_only: false,
+            dont_sync: false,
+            preserve_atime: false,
+            require_directory: false,
+            follow_symlinks: true,
+            backup_semantics: true,
+        }
+    }
+}
+
+impl HandleOptions {
+    /// Create a new set of options, with the same behavior as
+    /// [`Handle::from_path`].
+    ///
+    /// [`Handle::from_path`]: struct.Handle.html#method.from_path
+    pub fn new() -> HandleOptions {
+        HandleOptions::default()
+    }
+
+    /// Fail unless the path refers to a directory.
+    ///
+    /// This is enforced while opening the file, using `O_DIRECTORY` on Unix
+    /// and by checking `FILE_ATTRIBUTE_DIRECTORY` on Windows, so there is
+    /// no window in which the path could be swapped for something else. The
+    /// error is `ENOTDIR` on Unix and `ERROR_DIRECTORY` on Windows, both of
+    /// which have a kind of `NotADirectory` on Rust versions that support it.
+    ///
+    /// This is disabled by default. See also [`Handle::from_dir_path`].
+    ///
+    /// [`Handle::from_dir_path`]: struct.Handle.html#method.from_dir_path
+    pub fn require_directory(&mut self, yes: bool) -> &mut HandleOptions {
+        self.require_directory = yes;
+        self
+    }
+
+    /// Follow a symbolic link in the last component of the path.
+    ///
+    /// When disabled, opening a path whose last component is a symbolic link
+    /// fails, rather than producing a handle of the file it points to. The
+    /// error is `ELOOP` on Unix and `ERROR_CANT_RESOLVE_FILENAME` on
+    /// Windows, both of which have a kind of `FilesystemLoop` on Rust
+    /// versions that support it. On Windows, junctions and volume mount
+    /// points count as symbolic links for this purpose. Symbolic links in
+    /// other components of the path are always followed.
+    ///
+    /// This is enabled by default.
+    pub fn follow_symlinks(&mut self, yes: bool) -> &mut HandleOptions {
+        self.follow_symlinks = yes;
+        self
+    }
+
+    /// Open the path with `FILE_FLAG_BACKUP_SEMANTICS`.
+    ///
+    /// This flag is what permits opening directories on Windows, which is
+    /// why [`Handle::from_path`] always uses it. But it also asks Windows to
+    /// honor the backup and restore privileges, if the caller holds them,
+    /// which bypass the usual access checks. Disabling it gives the most
+    /// restrictive open possible, for callers that know they only deal
+    /// with regular files. Without it, opening a directory fails with
+    /// `ERROR_ACCESS_DENIED` (which has a kind of `PermissionDenied`), so
+    /// combining this with [`require_directory`] always fails.
+    ///
+    /// This only has an effect on Windows. It is enabled by default.
+    ///
+    /// [`Handle::from_path`]: struct.Handle.html#method.from_path
+    /// [`require_directory`]: #method.require_directory
+    pub fn backup_semantics(&mut self, yes: bool) -> &mut HandleOptions {
+        self.backup_semantics = yes;
+        self
+    }
+
+    /// Fall back to a snapshot of the file's identity if it cannot be
+    /// opened due to insufficient permissions.
+    ///
+    /// Some files can be stat'd but not opened, for example, other apps'
+    /// files and scoped storage on Android, or files guarded by SELinux
+    /// policy. With this option enabled, such a file yields a handle that
+    /// holds no open file and only records the identity the file had when
+    /// it was stat'd. See [`Handle::is_snapshot`] for the weaker guarantee
+    /// this provides.
+    ///
+    /// This only has an effect on Unix. On Windows, a file's identity can be
+    /// obtained without any access rights to its contents.
+    ///
+    /// [`Handle::is_snapshot`]: struct.Handle.html#method.is_snapshot
+    pub fn snapshot_fallback(&mut self, yes: bool) -> &mut HandleOptions {
+        self.snapshot_fallback = yes;
+        self
+    }
+
+    /// Never open the file, and always produce a snapshot of its identity.
+    ///
+    /// This is like [`snapshot_fallback`], except that the file isn't even
+    /// attempted to be opened: its identity is read with `stat` (or `lstat`
+    /// if [`follow_symlinks`] is disabled) instead. This is useful where
+    /// opening a file has consequences that merely stat'ing it doesn't, for
+    /// example, in an iOS app extension, where opening a file outside the
+    /// app's container is reported as a sandbox violation even if access to
+    /// it was granted through a security-scoped bookmark. See
+    /// [`Handle::is_snapshot`] for the weaker guarantee this provides.
+    ///
+    /// Since there is no open file to check, [`require_directory`] and
+    /// [`follow_symlinks`] are enforced by looking at the file's metadata,
+    /// which is subject to races.
+    ///
+    /// This only has an effect on Unix. On Windows, a file has to be opened
+    /// to obtain its identity, but opening it for no access at all (as
+    /// [`Handle::from_path`] does) has no such consequences. It is disabled
+    /// by default.
+    ///
+    /// [`snapshot_fallback`]: #method.snapshot_fallback
+    /// [`follow_symlinks`]: #method.follow_symlinks
+    /// [`require_directory`]: #method.require_directory
+    /// [`Handle::is_snapshot`]: struct.Handle.html#method.is_snapshot
+    /// [`Handle::from_path`]: struct.Handle.html#method.from_path
+    pub fn stat_only(&mut self, yes: bool) -> &mut HandleOptions {
+        self.stat_only = yes;
+        self
+    }
+
+    /// Don't force network file systems to synchronize with their server
+    /// when determining a file's identity.
+    ///
+    /// Opening a file on a hard-mounted NFS export whose server has gone
+    /// away blocks until the server comes back, which may be never. With
+    /// this option enabled, the file is opened with `O_PATH | O_NONBLOCK`,
+    /// which only looks the path up, and its identity is queried with
+    /// `statx` and `AT_STATX_DONT_SYNC`, which lets the file system answer
+    /// from its cache. This doesn't rule out blocking (looking the path up
+    /// may still need the server), but it narrows the window considerably.
+    ///
+    /// The price is that the metadata may be slightly stale: the
+    /// modification time (see [`Handle::modified_at_open`]) in particular
+    /// may not reflect changes made on other machines. The identity of a
+    /// file never changes, so comparisons are unaffected. As with other
+    /// `O_PATH` handles, the underlying [`File`] can't be used for I/O.
+    ///
+    /// This only has an effect on Linux, and not together with
+    /// [`stat_only`]. On kernels without `statx` (before 4.11), the
+    /// identity is queried with a regular `fstat` instead. It is disabled
+    /// by default.
+    ///
+    /// [`Handle::modified_at_open`]: struct.Handle.html#method.modified_at_open
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`stat_only`]: #method.stat_only
+    pub fn dont_sync(&mut self, yes: bool) -> &mut HandleOptions {
+        self.dont_sync = yes;
+        self
+    }
+
+    /// Open the file with `O_NOATIME`, so that reading it through the
+    /// handle doesn't update its access time.
+    ///
+    /// Constructing a handle never updates the access time of a file by
+    /// itself, since determining its identity doesn't read it. But reading
+    /// the file through the handle's [`File`] does, which can make backup
+    /// tools think that the file changed. With this option enabled, the
+    /// file is opened with `O_NOATIME`, which prevents that. The kernel
+    /// only permits this for the owner of the file (and for privileged
+    /// processes), so for other files, this silently falls back to opening
+    /// them normally.
+    ///
+    /// Files that are opened with `O_PATH` (e.g., devices, or files that
+    /// cannot be read, see [`Handle::from_path`]) can't be read through the
+    /// handle anyway, and neither can [`stat_only`] or [`dont_sync`] handles,
+    /// so this makes no difference for them.
+    ///
+    /// This only has an effect on Linux and Android. It is disabled by
+    /// default.
+    ///
+    /// [`File`]: https://doc.rust-lang.org/std/fs/struct.File.html
+    /// [`Handle::from_path`]: struct.Handle.html#method.from_path
+    /// [`stat_only`]: #method.stat_only
+    /// [`dont_sync`]: #method.dont_sync
+    pub fn preserve_atime(&mut self, yes: bool) -> &mut HandleOptions {
+        self.preserve_atime = yes;
+        self
+    }
+
+    /// Construct a handle from a path using these options.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] in the same circumstances as
+    /// [`Handle::from_path`], except for those the options work around.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`Handle::from_path`]: struct.Handle.html#method.from_path
+    pub fn open<P: AsRef<Path>>(&self, p: P) -> io::Result<Handle> {
+        imp::Handle::from_path_with(p, self).map(Handle)
+    }
+
+    /// Returns true if the two file paths may correspond to the same file,
+    /// opening both of them using these options.
+    ///
+    /// This is like [`is_same_file`], with the same caveats.
+    ///
+    /// [`is_same_file`]: fn.is_same_file.html
+    pub fn is_same_file<P, Q>(&self, path1: P, path2: Q) -> io::Result<bool>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Ok(self.open(path1)? == self.open(path2)?)
+    }
+}
+
+/// Options for comparing two [`Handle`]s.
+///
+/// Some file systems report identities that can't be relied upon, in which
+/// case `==` may be confidently wrong. These options make it possible to get
+/// an indeterminate answer (`None`) instead. With no options set,
+/// [`compare`] agrees with `==`, except that files on NFS are compared by
+/// their file system identifier (see [`fsid`]).
+///
+/// [`Handle`]: struct.Handle.html
+/// [`compare`]: #method.compare
+/// [`fsid`]: #method.fsid
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::ComparisonOptions;
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let mut opts = ComparisonOptions::new();
+/// opts.distrust_fuse(true);
+/// match opts.is_same_file("/mnt/sshfs/a", "/mnt/sshfs/b")? {
+///     Some(same) => println!("same file: {}", same),
+///     None => println!("can't tell"),
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ComparisonOptions {
+    distrust_fuse: bool,
+    fsid: bool,
+}
+
+impl ComparisonOptions {
+    /// Create a new set of options, with which comparisons agree with `==`
+    /// (except on NFS).
+    pub fn new() -> ComparisonOptions {
+        ComparisonOptions::default()
+    }
+
+    /// Don't trust the identities of files on FUSE file systems.
+    ///
+    /// Some FUSE file systems (e.g., sshfs with some options, archive
+    /// mounts or older versions of s3fs) synthesize inode numbers that aren't
+    /// stable across lookups, so the same file can compare unequal and
+    /// different files can compare equal. With this option enabled,
+    /// comparing two handles of which at least one is on a FUSE file system
+    /// yields `None`. This costs an extra `fstatfs` call per handle for
+    /// every comparison. See [`Handle::fs_magic`] to build other policies.
+    ///
+    /// This only has an effect on Linux and Android. It is disabled by
+    /// default.
+    ///
+    /// [`Handle::fs_magic`]: struct.Handle.html#method.fs_magic
+    pub fn distrust_fuse(&mut self, yes: bool) -> &mut ComparisonOptions {
+        self.distrust_fuse = yes;
+        self
+    }
+
+    /// Compare handles using their file system identifiers instead of their
+    /// device numbers.
+    ///
+    /// The device number of a network file system is typically allocated
+    /// anew each time it is mounted, so the same file observed before and
+    /// after a remount compares unequal with `==`. With this option enabled,
+    /// comparisons are made with [`Handle::fsid_eq`] instead, which also
+    /// works for snapshot handles.
+    ///
+    /// On Linux and Android, this is done for files on NFS (as reported by
+    /// [`Handle::fs_magic`]) even if this option is disabled. The file
+    /// system type is only queried for unequal handles with the same inode
+    /// number.
+    ///
+    /// This only has an effect on unix platforms. It is disabled by default.
+    ///
+    /// [`Handle::fsid_eq`]: struct.Handle.html#method.fsid_eq
+    /// [`Handle::fs_magic`]: struct.Handle.html#method.fs_magic
+    pub fn fsid(&mut self, yes: bool) -> &mut ComparisonOptions {
+        self.fsid = yes;
+        self
+    }
+
+    /// Compare two handles using these options.
+    ///
+    /// This returns `Some(true)` or `Some(false)` if the handles can be
+    /// compared with confidence, in which case the answer is the same as
+    /// `==` (or [`Handle::fsid_eq`], see [`fsid`]), and `None` otherwise.
+    ///
+    /// [`Handle::fsid_eq`]: struct.Handle.html#method.fsid_eq
+    /// [`fsid`]: #method.fsid
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if the information needed
+    /// by the options could not be queried, e.g., the file system type of
+    /// either handle.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn compare(
+        &self,
+        h1: &Handle,
+        h2: &Handle,
+    ) -> io::Result<Option<bool>> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.distrust_fuse
+            && compare_fuse(h1, h2, Handle::fs_magic)?.is_none()
+        {
+            return Ok(None);
+        }
+        #[cfg(any(target_os = "redox", unix))]
+        if self.fsid || on_nfs(h1, h2)? {
+            return h1.fsid_eq(h2).map(Some);
+        }
+        Ok(Some(h1 == h2))
+    }
+
+    /// Compare the files at two paths using these options.
+    ///
+    /// This is like [`is_same_file`], except that the answer is `None` if
+    /// it can't be given with confidence. See [`compare`].
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if either path cannot be
+    /// opened, or in the same circumstances as [`compare`].
+    ///
+    /// [`is_same_file`]: fn.is_same_file.html
+    /// [`compare`]: #method.compare
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    pub fn is_same_file<P, Q>(
+        &self,
+        path1: P,
+        path2: Q,
+    ) -> io::Result<Option<bool>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.compare(&Handle::from_path(path1)?, &Handle::from_path(path2)?)
+    }
+}
+
+/// Compare two handles, unless either of them is on a FUSE file system.
+///
+/// The file system type is queried through `fs_magic`, so that tests can
+/// pretend that a handle is on FUSE.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn compare_fuse<F>(
+    h1: &Handle,
+    h2: &Handle,
+    fs_magic: F,
+) -> io::Result<Option<bool>>
+where
+    F: Fn(&Handle) -> io::Result<u64>,
+{
+    let fuse = imp::c::FUSE_SUPER_MAGIC as u64;
+    if fs_magic(h1)? == fuse || fs_magic(h2)? == fuse {
+        return Ok(None);
+    }
+    Ok(Some(h1 == h2))
+}
+
+/// Returns true if two unequal handles with the same inode number are on
+/// NFS, in which case they are better compared with `fsid_eq`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn on_nfs(h1: &Handle, h2: &Handle) -> io::Result<bool> {
+    if h1 == h2 || !h1.same_inode(h2) {
+        return Ok(false);
+    }
+    let nfs = imp::c::NFS_SUPER_MAGIC as u64;
+    Ok(h1.fs_magic()? == nfs || h2.fs_magic()? == nfs)
+}
+
+/// Elsewhere, there is no telling which file systems are NFS.
+#[cfg(all(
+    any(target_os = "redox", unix),
+    not(any(target_os = "linux", target_os = "android"))
+))]
+fn on_nfs(_: &Handle, _: &Handle) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// A bounded cache of the identities of paths.
+///
+/// Looking up the [`FileKey`] of a path the cache has seen before doesn't
+/// open the file again, which saves a system call or two for paths that are
+/// checked over and over, e.g., by a file watcher. When the cache is full,
+/// the least recently used path is dropped to make room for a new one.
+///
+/// Paths are cached exactly as given, without making them canonical, so
+/// `./foo` and `foo` are cached separately (but still have equal keys).
+/// Paths that can't be opened, or whose files have no identity, are never
+/// cached.
+///
+/// This requires the `lru` feature.
+///
+/// # Staleness
+///
+/// A cached key is only as fresh as the last time its path was opened. If
+/// the file at a path is replaced (e.g., by a rename or by deleting it and
+/// creating a new one), the cache keeps returning the key of the old file,
+/// and it doesn't notice if the path is removed either. On top of that,
+/// none of the files are held open, so the caveats in the [`FileKey`]
+/// documentation apply to every cached key. It is up to the caller to
+/// [`evict`] paths that it knows have changed, or to look them up with
+/// [`fresh`] when it can't afford a stale answer.
+///
+/// [`FileKey`]: struct.FileKey.html
+/// [`evict`]: #method.evict
+/// [`fresh`]: #method.fresh
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::IdentityCache;
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let mut cache = IdentityCache::new(1024);
+/// loop {
+///     // Only the first iteration opens the files.
+///     if cache.is_same("./config", "/etc/app/config")? {
+///         println!("editing the live config");
+///     }
+///     # break;
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+#[cfg(feature = "lru")]
+#[derive(Debug)]
+pub struct IdentityCache {
+    keys: lru::LruCache<PathBuf, FileKey>,
+}
+
+#[cfg(feature = "lru")]
+impl IdentityCache {
+    /// Create an empty cache that holds up to `capacity` paths.
+    ///
+    /// A capacity of zero is treated as a capacity of one.
+    pub fn new(capacity: usize) -> IdentityCache {
+        // unwrap() will not panic. The capacity is at least one.
+        let capacity = std::num::NonZeroUsize::new(capacity.max(1)).unwrap();
+        IdentityCache { keys: lru::LruCache::new(capacity) }
+    }
+
+    /// Return the identity of the file at the given path, opening it only
+    /// if the path isn't cached.
+    ///
+    /// See the [staleness] caveats.
+    ///
+    /// [staleness]: #staleness
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] in the same circumstances as
+    /// [`file_key`] if the path isn't cached.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`file_key`]: fn.file_key.html
+    pub fn key<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileKey> {
+        let path = path.as_ref();
+        match self.keys.get(path) {
+            Some(&key) => Ok(key),
+            None => self.fresh(path),
+        }
+    }
+
+    /// Return the identity of the file at the given path, opening it even
+    /// if the path is cached, and cache the result.
+    ///
+    /// If the path can't be opened anymore, it is evicted.
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] in the same circumstances as
+    /// [`file_key`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`file_key`]: fn.file_key.html
+    pub fn fresh<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileKey> {
+        let path = path.as_ref();
+        match file_key(path) {
+            Ok(key) => {
+                self.keys.put(path.to_path_buf(), key);
+                Ok(key)
+            }
+            Err(err) => {
+                self.keys.pop(path);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns true if the two paths refer to the same file, opening only
+    /// the paths that aren't cached.
+    ///
+    /// This is [`is_same_file`] with cached identities. See the
+    /// [staleness] caveats.
+    ///
+    /// [`is_same_file`]: fn.is_same_file.html
+    /// [staleness]: #staleness
+    ///
+    /// # Errors
+    /// This method will return an [`io::Error`] if either path isn't cached
+    /// and [`key`] fails for it.
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`key`]: #method.key
+    pub fn is_same<P, Q>(&mut self, path1: P, path2: Q) -> io::Result<bool>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Ok(self.key(path1)? == self.key(path2)?)
+    }
+
+    /// Remove the given path from the cache, so that it is opened again the
+    /// next time it is looked up.
+    ///
+    /// Returns true if the path was cached.
+    pub fn evict<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        self.keys.pop(path.as_ref()).is_some()
+    }
+
+    /// Remove every path from the cache.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Return the number of cached paths.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns true if no paths are cached.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Returns true if the two file paths may correspond to the same file.
+///
+/// Note that it's possible for this to produce a false positive on some
+/// platforms. Namely, this can return true even if the two file paths *don't*
+/// resolve to the same file.
+/// # Errors
+/// This function will return an [`io::Error`] if any of the two paths cannot
+/// be opened. The most common reasons for this are: the path does not exist,
+/// or there were not enough permissions.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::is_same_file;
+///
+/// assert!(is_same_file("./foo", "././foo").unwrap_or(false));
+/// ```
+pub fn is_same_file<P, Q>(path1: P, path2: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(Handle::from_path(path1)? == Handle::from_path(path2)?)
+}
+
+/// Returns true if the two file paths may correspond to the same file, with
+/// an error that says what went wrong.
+///
+/// This is exactly like [`is_same_file`], except for the error type, which
+/// is the same as for [`Handle::from_path_detailed`]. In particular, the
+/// error records which of the two paths failed.
+///
+/// [`is_same_file`]: fn.is_same_file.html
+/// [`Handle::from_path_detailed`]: struct.Handle.html#method.from_path_detailed
+///
+/// # Errors
+/// This function will return an [`Error`] if any of the two paths cannot be
+/// opened, in the same circumstances as [`is_same_file`] returns an
+/// [`io::Error`].
+///
+/// [`Error`]: struct.Error.html
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::{is_same_file_detailed, ErrorKind};
+///
+/// match is_same_file_detailed("./foo", "./bar") {
+///     Ok(same) => println!("same: {}", same),
+///     Err(err) if err.kind() == ErrorKind::NotFound => {
+///         println!("{} vanished", err.path().unwrap().display());
+///     }
+///     Err(err) => println!("{}", err),
+/// }
+/// ```
+pub fn is_same_file_detailed<P, Q>(path1: P, path2: Q) -> Result<bool, Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(Handle::from_path_detailed(path1)?
+        == Handle::from_path_detailed(path2)?)
+}
+
+/// Compares two paths that may not exist.
+///
+/// This is like [`is_same_file`], except that a path that does not exist is
+/// reported as [`Comparison::FirstMissing`] or
+/// [`Comparison::SecondMissing`] instead of as an error of kind `NotFound`.
+/// If neither path exists, then the result is `FirstMissing`.
+///
+/// [`is_same_file`]: fn.is_same_file.html
+/// [`Comparison::FirstMissing`]: enum.Comparison.html#variant.FirstMissing
+/// [`Comparison::SecondMissing`]: enum.Comparison.html#variant.SecondMissing
+///
+/// # Errors
+/// This function will return an [`io::Error`] if any of the two paths cannot
+/// be opened for any reason other than not existing, e.g., because there
+/// were not enough permissions.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::{compare_paths, Comparison};
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// match compare_paths("./output", "./input")? {
+///     Comparison::Same => println!("refusing to overwrite the input"),
+///     Comparison::SecondMissing => println!("no input"),
+///     _ => {}
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub fn compare_paths<P, Q>(path1: P, path2: Q) -> io::Result<Comparison>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fn open(p: &Path) -> io::Result<Option<Handle>> {
+        match Handle::from_path(p) {
+            Ok(h) => Ok(Some(h)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    let h1 = match open(path1.as_ref())? {
+        Some(h) => h,
+        None => return Ok(Comparison::FirstMissing),
+    };
+    let h2 = match open(path2.as_ref())? {
+        Some(h) => h,
+        None => return Ok(Comparison::SecondMissing),
+    };
+    Ok(if h1 == h2 { Comparison::Same } else { Comparison::Different })
+}
+
+/// Groups the given paths by the file they refer to.
+///
+/// Each path is opened and its identity queried (see [`Handle::key`]), so
+/// that two paths end up in the same group exactly when their handles
+/// compare equal. A path whose handle has no identity, e.g., a Windows
+/// console, is always in a group of its own. Groups are ordered by the
+/// position of their first path in `paths`, and the paths within a group are
+/// in the order they appear in `paths`.
+///
+/// Each handle is closed before the next path is opened, so if files are
+/// deleted while this runs, the caveats in the [`FileKey`] documentation
+/// apply. See [`group_same_files_par`] for a version that opens the paths
+/// in parallel.
+///
+/// [`Handle::key`]: struct.Handle.html#method.key
+/// [`FileKey`]: struct.FileKey.html
+/// [`group_same_files_par`]: fn.group_same_files_par.html
+///
+/// # Errors
+/// This function will return an [`io::Error`] as soon as one of the paths
+/// cannot be opened, for example, because it does not exist.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::group_same_files;
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let groups = group_same_files(&["./a", "./b", "./c"])?;
+/// for group in groups.iter().filter(|group| group.len() > 1) {
+///     println!("{:?} are all the same file", group);
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub fn group_same_files<P: AsRef<Path>>(
+    paths: &[P],
+) -> io::Result<Vec<Vec<PathBuf>>> {
+    let mut index: HashMap<FileKey, usize> = HashMap::new();
+    let mut groups: Vec<Vec<PathBuf>> = vec![];
+    for p in paths {
+        let p = p.as_ref();
+        let key = Handle::from_path(p)?.key();
+        match key.and_then(|key| index.get(&key).copied()) {
+            Some(i) => groups[i].push(p.to_path_buf()),
+            None => {
+                if let Some(key) = key {
+                    index.insert(key, groups.len());
+                }
+                groups.push(vec![p.to_path_buf()]);
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Groups the given paths by the file they refer to, in parallel.
+///
+/// This opens the paths and queries their identities on rayon's global
+/// thread pool, and returns exactly what [`group_same_files`] returns for
+/// the same paths: the same groups, in the same order. This pays off for
+/// large numbers of paths, where opening them one after another is the
+/// bottleneck.
+///
+/// This requires the `rayon` feature.
+///
+/// [`group_same_files`]: fn.group_same_files.html
+///
+/// # Errors
+/// This function will return an [`io::Error`] if any of the paths cannot be
+/// opened. If several of them cannot be opened, then which of their errors
+/// is returned is unspecified.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+#[cfg(feature = "rayon")]
+pub fn group_same_files_par<P: AsRef<Path> + Sync>(
+    paths: &[P],
+) -> io::Result<Vec<Vec<PathBuf>>> {
+    par::group(paths)
+}
+
+/// Returns true if the two open files are the same file.
+///
+/// This compares the identities of the files like comparing handles
+/// constructed with [`Handle::from_file`] would, except that both files are
+/// only borrowed. Neither of them is duplicated, closed or otherwise touched,
+/// so they remain usable afterwards. Since both files are open while they're
+/// compared, the result is reliable.
+///
+/// Files that have no identity, such as a Windows console or pipe, or a
+/// file on a Unix file system that doesn't report inode numbers, are never
+/// the same as anything, so this returns `false` for them rather than an
+/// error.
+///
+/// [`Handle::from_file`]: struct.Handle.html#method.from_file
+///
+/// # Errors
+/// This function will return an [`io::Error`] if the metadata of either
+/// file cannot be obtained.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::fs::{File, OpenOptions};
+/// use std::io::{self, Read, Write};
+///
+/// use same_file::files_eq;
+///
+/// # fn try_main() -> io::Result<()> {
+/// let mut input = File::open("./input")?;
+/// let mut output = OpenOptions::new().append(true).open("./output")?;
+/// if files_eq(&input, &output)? {
+///     return Err(io::Error::new(io::ErrorKind::Other, "in place"));
+/// }
+/// let mut buf = vec![];
+/// input.read_to_end(&mut buf)?;
+/// output.write_all(&buf)?;
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub fn files_eq(file1: &File, file2: &File) -> io::Result<bool> {
+    imp::files_eq(file1, file2)
+}
+
+/// Returns the identity of the file at the given path.
+///
+/// This opens the file, takes its key, and closes it again, which is the
+/// same as calling [`Handle::key`] on a fresh handle, so the result can be
+/// compared with keys of handles. It is convenient for storing identities
+/// in other data structures when no live [`Handle`] is needed.
+///
+/// Note that since the file is closed before this returns, the caveats in
+/// the [`FileKey`] documentation apply right away: once the file is
+/// deleted, its key may be reused by an unrelated file. If the file must
+/// not be mistaken for another one, keep a [`Handle`] open instead.
+///
+/// [`Handle::key`]: struct.Handle.html#method.key
+/// [`Handle`]: struct.Handle.html
+/// [`FileKey`]: struct.FileKey.html
+///
+/// # Errors
+/// This function will return an [`io::Error`] in the same circumstances as
+/// [`Handle::from_path`], or if the file has no identity (e.g., a device on
+/// Windows).
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+/// [`Handle::from_path`]: struct.Handle.html#method.from_path
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::collections::HashMap;
+/// use std::path::PathBuf;
+///
+/// use same_file::{file_key, FileKey};
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let mut seen: HashMap<FileKey, PathBuf> = HashMap::new();
+/// for path in &["./foo", "./bar"] {
+///     if let Some(first) = seen.insert(file_key(path)?, path.into()) {
+///         println!("{} is the same file as {}", path, first.display());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub fn file_key<P: AsRef<Path>>(path: P) -> io::Result<FileKey> {
+    Handle::from_path(path)?.key().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Other, "file has no identity")
+    })
+}
+
+/// Returns the identities of many files at once, using io_uring.
+///
+/// This submits a `statx` operation for each path to an io_uring instance,
+/// up to a bounded number at a time, which costs far fewer system calls than
+/// querying one path after another. If io_uring is unavailable or doesn't
+/// support `statx` (which requires Linux 5.6), then each path is queried
+/// sequentially instead. Either way, the results are in the same order as
+/// `paths`, and each key is identical to the one [`Handle::key`] would
+/// return for a handle of the same path.
+///
+/// Symbolic links are followed. Note that no file is opened, so the caveats
+/// in the [`FileKey`] documentation apply as soon as this returns.
+///
+/// This requires the `uring` feature, and only works on Linux.
+///
+/// [`Handle::key`]: struct.Handle.html#method.key
+/// [`FileKey`]: struct.FileKey.html
+///
+/// # Errors
+/// Each path that cannot be queried has an [`io::Error`] in its position,
+/// for example, because it does not exist.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub fn keys_for_uring<P: AsRef<Path>>(
+    paths: &[P],
+) -> Vec<io::Result<FileKey>> {
+    uring::keys(paths)
+        .into_iter()
+        .map(|r| r.map(|key| FileKey(key, None)))
+        .collect()
+}
+
+/// Returns true if the given path no longer refers to the file of the given
+/// handle.
+///
+/// This reopens `path` and compares the result with `old`, which is useful
+/// for detecting that a path was replaced (e.g., by renaming another file
+/// over it) since `old` was opened. Both handles remain open while they are
+/// compared, so the comparison cannot be fooled by the file system reusing
+/// the identity of a deleted file.
+///
+/// # Errors
+/// This function will return an [`io::Error`] if `path` cannot be opened. In
+/// particular, if the path was removed, then this returns an error of kind
+/// `NotFound` rather than `true`.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::path::Path;
+/// use same_file::{path_changed, Handle};
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let path = Path::new("./config.toml");
+/// let handle = Handle::from_path(path)?;
+/// // ... later ...
+/// if path_changed(&handle, path)? {
+///     println!("{} was replaced", path.display());
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub fn path_changed(old: &Handle, path: &Path) -> io::Result<bool> {
+    let new = Handle::from_path(path)?;
+    Ok(*old != new)
+}
+
+/// Returns true if the two file paths refer to the same hard link of the
+/// same file.
+///
+/// This is stricter than [`is_same_file`]: two different hard links to the
+/// same file are the same file, but not the same link. See
+/// [`Handle::link_id`] for details.
+///
+/// [`is_same_file`]: fn.is_same_file.html
+/// [`Handle::link_id`]: struct.Handle.html#method.link_id
+///
+/// # Errors
+/// This function will return an [`io::Error`] if any of the two paths cannot
+/// be opened, or if their link identifiers cannot be queried. On platforms
+/// without link identifiers, this always returns an error of kind
+/// [`io::ErrorKind::Unsupported`].
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+/// [`io::ErrorKind::Unsupported`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Unsupported
+pub fn same_link<P, Q>(path1: P, path2: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let h1 = Handle::from_path(path1)?;
+    let h2 = Handle::from_path(path2)?;
+    Ok(h1 == h2 && h1.link_id()? == h2.link_id()?)
+}
+
+/// Returns true if the two paths are the same symbolic link.
+///
+/// Unlike [`is_same_file`], this doesn't compare the files the links point
+/// to, but the links themselves, as constructed by [`Handle::from_link`].
+/// Two different links to the same file are different links, and a link is
+/// never the same as its target. (This is unrelated to [`same_link`], which
+/// is about hard links.)
+///
+/// [`is_same_file`]: fn.is_same_file.html
+/// [`Handle::from_link`]: struct.Handle.html#method.from_link
+/// [`same_link`]: fn.same_link.html
+///
+/// # Errors
+/// This function will return an [`io::Error`] if any of the two paths cannot
+/// be opened, or isn't a symbolic link (see [`Handle::from_link`]).
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::is_same_link;
+///
+/// assert!(is_same_link("/usr/bin/editor", "/bin/editor").unwrap_or(false));
+/// ```
+pub fn is_same_link<P, Q>(path1: P, path2: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(Handle::from_link(path1)? == Handle::from_link(path2)?)
+}
+
+/// Returns true if the two paths name the same directory entry, without
+/// following symbolic links.
+///
+/// This is [`is_same_file`] for a path whose last component may be a
+/// symbolic link: a symbolic link is compared as itself, using
+/// [`Handle::from_link`], and anything else is compared as by
+/// [`is_same_file`]. In particular:
+///
+/// * A symbolic link is never the same as its target, nor as another
+///   symbolic link to the same target.
+/// * A symbolic link is only the same as a path that resolves to the same
+///   link, such as a hard link to the link itself.
+/// * A dangling symbolic link can be compared like any other, without
+///   error.
+/// * Two paths that aren't symbolic links, like two hard links to the same
+///   file, are the same if [`is_same_file`] says they are.
+///
+/// Symbolic links in the directories leading up to the last component are
+/// still followed. On Windows, every name surrogate reparse point (symbolic
+/// links and junctions alike) is treated as a symbolic link.
+///
+/// [`is_same_file`]: fn.is_same_file.html
+/// [`Handle::from_link`]: struct.Handle.html#method.from_link
+///
+/// # Errors
+/// This function will return an [`io::Error`] if any of the two paths
+/// doesn't exist or cannot be opened.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::is_same_file_no_follow;
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// // `/etc/localtime` is a symbolic link to the zone file.
+/// assert!(!is_same_file_no_follow(
+///     "/etc/localtime",
+///     "/usr/share/zoneinfo/Etc/UTC",
+/// )?);
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub fn is_same_file_no_follow<P, Q>(path1: P, path2: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(no_follow(path1.as_ref())? == no_follow(path2.as_ref())?)
+}
+
+/// Open the directory entry at `p` itself, which is the symbolic link if it
+/// is one.
+fn no_follow(p: &Path) -> io::Result<Handle> {
+    if fs::symlink_metadata(p)?.file_type().is_symlink() {
+        Handle::from_link(p)
+    } else {
+        Handle::from_path(p)
+    }
+}
+
+/// Returns true if the two handles are device nodes for the same device.
+///
+/// Two different device nodes (e.g., `/dev/null` and a copy of it made with
+/// `mknod` in a chroot) are different files, so they never compare equal as
+/// handles. But if they have the same major and minor number (see
+/// [`Handle::rdev`]), then opening either of them opens the same device,
+/// which is what this checks. It is purely additive to the usual
+/// comparison: two handles of the same device node are also for the same
+/// device.
+///
+/// Note that this only works on unix platforms.
+///
+/// [`Handle::rdev`]: struct.Handle.html#method.rdev
+///
+/// # Errors
+/// This function will return an [`io::Error`] of kind
+/// [`io::ErrorKind::InvalidInput`] unless both handles are character or
+/// block devices.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+/// [`io::ErrorKind::InvalidInput`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidInput
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use same_file::{same_device_node, Handle};
+///
+/// # fn try_main() -> std::io::Result<()> {
+/// let tty = Handle::from_path("/dev/tty0")?;
+/// let jailed = Handle::from_path("/srv/jail/dev/tty0")?;
+/// if same_device_node(&tty, &jailed)? {
+///     println!("the jail has access to the console");
+/// }
+/// # Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+#[cfg(any(target_os = "redox", unix))]
+pub fn same_device_node(h1: &Handle, h2: &Handle) -> io::Result<bool> {
+    match (h1.rdev(), h2.rdev()) {
+        (Some(rdev1), Some(rdev2)) => {
+            Ok(h1.kind() == h2.kind() && rdev1 == rdev2)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "handle is not a device node",
+        )),
+    }
+}
+
+/// Returns true if the two file paths are on the same mount.
+///
+/// This is not the same question as whether they are on the same device:
+/// bind mounts of the same file system share a device number but are
+/// different mounts, while btrfs subvolumes have different device numbers
+/// but may be on the same mount. Renaming a file atomically (or reflinking
+/// it) only works within a single mount.
+///
+/// Both paths are opened, and their mount IDs are compared (see
+/// [`Handle::mount_id`]). On kernels that don't report mount IDs (before
+/// 5.8), the mount of each path is instead looked up in
+/// `/proc/self/mountinfo`, by finding the longest mount point that contains
+/// the path once it has been made canonical.
+///
+/// Note that this only works on Linux.
+///
+/// [`Handle::mount_id`]: struct.Handle.html#method.mount_id
+///
+/// # Errors
+/// This function will return an [`io::Error`] if any of the two paths cannot
+/// be opened, or if their mounts cannot be determined.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+#[cfg(target_os = "linux")]
+pub fn is_same_mount<P, Q>(path1: P, path2: Q) -> io::Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    imp::is_same_mount(path1.as_ref(), path2.as_ref())
+}
+
+/// Return a number that is different for every dangling handle, so that
+/// they don't all hash the same.
+fn dangling_id() -> u64 {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed) as u64
+}
+
+/// Build the error returned when a feature isn't supported on the current
+/// platform.
+#[allow(dead_code)]
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} are not supported on this platform", what),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::error;
+    use std::fs::{self, File};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::result;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::is_same_file;
+
+    type Result<T> = result::Result<T, Box<dyn error::Error + Send + Sync>>;
+
+    /// Create an error from a format!-like syntax.
+    macro_rules! err {
+        ($($tt:tt)*) => {
+            Box::<dyn error::Error + Send + Sync>::from(format!($($tt)*))
+        }
+    }
+
+    /// A simple wrapper for creating a temporary directory that is
+    /// automatically deleted when it's dropped.
+    ///
+    /// We use this in lieu of tempfile because tempfile brings in too many
+    /// dependencies.
+    #[derive(Debug)]
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).unwrap();
+        }
+    }
+
+    impl TempDir {
+        /// Create a new empty temporary directory under the system's
+        /// configured temporary directory.
+        fn new() -> Result<TempDir> {
+            #![allow(deprecated)]
+
+            use std::sync::atomic::{
+                AtomicUsize, Ordering, ATOMIC_USIZE_INIT,
+            };
+
+            static TRIES: usize = 100;
+            static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+            let tmpdir = env::temp_dir();
+            for _ in 0..TRIES {
+                let count = COUNTER.fetch_add(1, Ordering::SeqCst);
+                let path = tmpdir.join("rust-walkdir").join(count.to_string());
+                if path.is_dir() {
+                    continue;
+                }
+                fs::create_dir_all(&path).map_err(|e| {
+                    err!("failed to create {}: {}", path.display(), e)
+                })?;
+                return Ok(TempDir(path));
+            }
+            Err(err!("failed to create temp dir after {} tries", TRIES))
+        }
+
+        /// Return the underlying path to this temporary directory.
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    // Tests that depend on the current directory must hold this lock (see
+    // `lock_cwd`), since some tests change it and tests run in parallel.
+    static CWD_LOCKED: AtomicBool = AtomicBool::new(false);
+
+    struct CwdLock;
+
+    impl Drop for CwdLock {
+        fn drop(&mut self) {
+            CWD_LOCKED.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn lock_cwd() -> CwdLock {
+        while CWD_LOCKED
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            std::thread::yield_now();
+        }
+        CwdLock
+    }
+
+    fn tmpdir() -> TempDir {
+        TempDir::new().unwrap()
+    }
+
+    #[cfg(unix)]
+    pub fn soft_link_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        src: P,
+        dst: Q,
+    ) -> io::Result<()> {
+        use std::os::unix::fs::symlink;
+        symlink(src, dst)
+    }
+
+    #[cfg(unix)]
+    pub fn soft_link_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        src: P,
+        dst: Q,
+    ) -> io::Result<()> {
+        soft_link_dir(src, dst)
+    }
+
+    #[cfg(windows)]
+    pub fn soft_link_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        src: P,
+        dst: Q,
+    ) -> io::Result<()> {
+        use std::os::windows::fs::symlink_dir;
+        symlink_dir(src, dst)
+    }
+
+    #[cfg(windows)]
+    pub fn soft_link_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        src: P,
+        dst: Q,
+    ) -> io::Result<()> {
+        use std::os::windows::fs::symlink_file;
+        symlink_file(src, dst)
+    }
+
+    #[cfg(windows)]
+    pub fn junction<P: AsRef<Path>, Q: AsRef<Path>>(
+        src: P,
+        dst: Q,
+    ) -> io::Result<()> {
+        use std::process::Command;
+
+        let status = Command::new("cmd")
+            .arg("/C")
+            .arg("mklink")
+            .arg("/J")
+            .arg(dst.as_ref())
+            .arg(src.as_ref())
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("mklink /J failed: {}", status),
+            ));
+        }
+        Ok(())
+    }
+
+    // These tests are rather uninteresting. The really interesting tests
+    // would stress the edge cases. On Unix, this might be comparing two files
+    // on different mount points with the same inode number. On Windows, this
+    // might be comparing two files whose file indices are the same on file
+    // systems where such things aren't guaranteed to be unique.
+    //
+    // Alas, I don't know how to create those environmental conditions. ---AG
+
+    #[test]
+    fn same_file_trivial() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("a")).unwrap());
+    }
+
+    #[test]
+    fn same_dir_trivial() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("a")).unwrap());
+    }
+
+    #[test]
+    fn not_same_file_trivial() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        assert!(!is_same_file(dir.join("a"), dir.join("b")).unwrap());
+    }
+
+    #[test]
+    fn not_same_dir_trivial() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("b")).unwrap();
+        assert!(!is_same_file(dir.join("a"), dir.join("b")).unwrap());
+    }
+
+    #[test]
+    fn same_file_hard() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
+    }
+
+    // Symbolic links can only be created on Unix and Windows.
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn same_file_soft() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
+    }
+
+    #[test]
+    fn path_changed_rename() {
+        use super::{path_changed, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        assert!(!path_changed(&a, &dir.join("a")).unwrap());
+
+        fs::rename(dir.join("b"), dir.join("a")).unwrap();
+        assert!(path_changed(&a, &dir.join("a")).unwrap());
+    }
+
+    #[cfg(all(target_os = "macos", feature = "backend-libc"))]
+    #[test]
+    fn same_link_hard() {
+        use super::{same_link, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+        assert_eq!(a, alink);
+        assert_ne!(a.link_id().unwrap(), alink.link_id().unwrap());
+        assert!(same_link(dir.join("a"), dir.join("a")).unwrap());
+        assert!(!same_link(dir.join("a"), dir.join("alink")).unwrap());
+    }
+
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    )))]
+    #[test]
+    fn same_link_unsupported() {
+        use super::same_link;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let err = same_link(dir.join("a"), dir.join("a")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rdev_device_nodes() {
+        use super::{same_device_node, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let null = Handle::from_path("/dev/null").unwrap();
+        let zero = Handle::from_path("/dev/zero").unwrap();
+        assert_eq!(null.rdev(), Some(libc::makedev(1, 3)));
+        assert_eq!(zero.rdev(), Some(libc::makedev(1, 5)));
+        assert_eq!(a.rdev(), None);
+
+        assert!(same_device_node(&null, &null).unwrap());
+        let again = Handle::from_path("/dev/null").unwrap();
+        assert!(same_device_node(&null, &again).unwrap());
+        assert!(!same_device_node(&null, &zero).unwrap());
+        let err = same_device_node(&null, &a).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    // Symbolic links can only be created on Unix and Windows.
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn same_dir_soft() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        soft_link_dir(dir.join("a"), dir.join("alink")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
+    }
+
+    #[test]
+    fn dir_path_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        assert_eq!(
+            Handle::from_dir_path(dir.join("a")).unwrap(),
+            Handle::from_path(dir.join("a")).unwrap(),
+        );
+    }
+
+    #[test]
+    fn dir_path_not_dir() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        assert!(Handle::from_dir_path(dir.join("a")).is_err());
+    }
+
+    // Elsewhere, there are no keys to compare.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix, windows))]
+    #[test]
+    fn same_key() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a.key().unwrap(), alink.key().unwrap());
+        assert_ne!(a.key().unwrap(), b.key().unwrap());
+    }
+
+    #[cfg(feature = "lru")]
+    #[test]
+    fn identity_cache() {
+        use super::{file_key, IdentityCache};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        for name in &["a", "b", "c"] {
+            File::create(dir.join(name)).unwrap();
+        }
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = file_key(dir.join("a")).unwrap();
+
+        let mut cache = IdentityCache::new(2);
+        assert!(cache.is_empty());
+        assert!(cache.is_same(dir.join("a"), dir.join("alink")).unwrap());
+        assert_eq!(cache.len(), 2);
+        // Hits don't open the file, so they survive its removal.
+        fs::remove_file(dir.join("alink")).unwrap();
+        assert_eq!(cache.key(dir.join("alink")).unwrap(), a);
+        assert!(cache.fresh(dir.join("alink")).is_err());
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.evict(dir.join("alink")));
+
+        // "a" is the least recently used path, so it goes first.
+        assert!(!cache.is_same(dir.join("b"), dir.join("c")).unwrap());
+        assert_eq!(cache.len(), 2);
+        fs::remove_file(dir.join("a")).unwrap();
+        fs::remove_file(dir.join("b")).unwrap();
+        assert!(cache.key(dir.join("a")).is_err());
+        assert!(cache.key(dir.join("b")).is_ok());
+
+        assert!(cache.evict(dir.join("b")));
+        assert!(cache.key(dir.join("b")).is_err());
+        assert!(cache.key(dir.join("c")).is_ok());
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn files_eq() {
+        use std::io::Write;
+
+        use super::files_eq;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut a = File::create(dir.join("a")).unwrap();
+        let b = File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a2 = File::open(dir.join("a")).unwrap();
+        let alink = File::open(dir.join("alink")).unwrap();
+        assert!(files_eq(&a, &a).unwrap());
+        assert!(files_eq(&a, &a2).unwrap());
+        assert!(files_eq(&a, &alink).unwrap());
+        assert!(!files_eq(&a, &b).unwrap());
+        // Both files are still usable.
+        a.write_all(b"still open").unwrap();
+        assert_eq!(alink.metadata().unwrap().len(), 10);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn files_eq_pipe() {
+        use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+        use std::process::{Command, Stdio};
+
+        use super::files_eq;
+
+        let mut child = Command::new("cmd")
+            .arg("/C")
+            .arg("echo")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let pipe = unsafe { File::from_raw_handle(stdout.into_raw_handle()) };
+        let tdir = tmpdir();
+        let a = File::create(tdir.path().join("a")).unwrap();
+        assert!(!files_eq(&pipe, &a).unwrap());
+        if super::Handle::from_file(pipe.try_clone().unwrap())
+            .unwrap()
+            .key()
+            .is_none()
+        {
+            assert!(!files_eq(&pipe, &pipe).unwrap());
+        }
+        child.wait().unwrap();
+    }
+
+    // Elsewhere, there are no keys to compare.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix, windows))]
+    #[test]
+    fn file_key() {
+        use super::{file_key, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = file_key(dir.join("a")).unwrap();
+        assert_eq!(
+            a,
+            Handle::from_path(dir.join("a")).unwrap().key().unwrap()
+        );
+        assert_ne!(a, file_key(dir.join("b")).unwrap());
+        assert!(file_key(dir.join("c")).is_err());
+    }
+
+    // Elsewhere, there are no keys to serialize.
+    #[cfg(all(
+        feature = "serde",
+        any(target_os = "redox", target_os = "hermit", unix, windows)
+    ))]
+    #[test]
+    fn file_key_serde() {
+        use super::{FileKey, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        let key = h.key().unwrap();
+        let json = serde_json::to_string(&key).unwrap();
+        let from_json = serde_json::from_str::<FileKey>(&json).unwrap();
+        assert_eq!(from_json, key);
+        assert!(from_json.eq_with_generation(&key));
+        let bytes = postcard::to_allocvec(&key).unwrap();
+        let from_bytes = postcard::from_bytes::<FileKey>(&bytes).unwrap();
+        assert_eq!(from_bytes, key);
+        #[cfg(any(target_os = "redox", unix))]
+        {
+            assert_eq!(key.generation(), h.generation());
+            assert_eq!(from_json.generation(), h.generation());
+            assert_eq!(from_bytes.generation(), h.generation());
+        }
+
+        // These pin the format, which must not change.
+        let key: FileKey = serde_json::from_str(
+            r#"{"volume":1,"index":5,"generation":null}"#,
+        )
+        .unwrap();
+        assert_eq!(key.0.parts(), (1, 5));
+        assert_eq!(key.generation(), None);
+        assert_eq!(
+            serde_json::to_string(&key).unwrap(),
+            r#"{"volume":1,"index":5,"generation":null}"#
+        );
+        assert_eq!(postcard::to_allocvec(&key).unwrap(), [1, 5, 0]);
+        assert_eq!(postcard::from_bytes::<FileKey>(&[1, 5, 0]).unwrap(), key);
+        // The generation number may be left out of self-describing formats.
+        let short: FileKey =
+            serde_json::from_str(r#"{"volume":1,"index":5}"#).unwrap();
+        assert_eq!(short, key);
+        assert_eq!(short.generation(), None);
+
+        // The generation number survives a round trip, and tells reused
+        // inode numbers apart.
+        let json = r#"{"volume":1,"index":5,"generation":7}"#;
+        let gen7: FileKey = serde_json::from_str(json).unwrap();
+        assert_eq!(gen7.generation(), Some(7));
+        assert_eq!(serde_json::to_string(&gen7).unwrap(), json);
+        let bytes = postcard::to_allocvec(&gen7).unwrap();
+        assert_eq!(bytes, [1, 5, 1, 7]);
+        let gen7_again = postcard::from_bytes::<FileKey>(&bytes).unwrap();
+        assert_eq!(gen7_again.generation(), Some(7));
+        assert!(gen7.eq_with_generation(&gen7_again));
+        let gen8: FileKey =
+            serde_json::from_str(r#"{"volume":1,"index":5,"generation":8}"#)
+                .unwrap();
+        assert_eq!(gen7, gen8);
+        assert!(!gen7.eq_with_generation(&gen8));
+        assert!(gen7.eq_with_generation(&key));
+        assert!(key.eq_with_generation(&gen8));
+
+        let wide = r#"{"volume":1,"index":18446744073709551616}"#;
+        assert_eq!(
+            serde_json::from_str::<FileKey>(wide).is_ok(),
+            cfg!(windows)
+        );
+    }
+
+    #[test]
+    fn same_as_file_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let afile = File::open(dir.join("a")).unwrap();
+        let bfile = File::open(dir.join("b")).unwrap();
+        assert!(a.same_as_file(&afile).unwrap());
+        assert!(!a.same_as_file(&bfile).unwrap());
+        // The file is still ours to use.
+        assert_eq!(afile.metadata().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn matches_any() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        File::create(dir.join("c")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        let c = Handle::from_path(dir.join("c")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+
+        assert!(a.matches_any(&[b.try_clone().unwrap(), alink]));
+        assert!(!a.matches_any(&[b, c]));
+        assert!(!a.matches_any(&[]));
+
+        let paths = [dir.join("b"), dir.join("alink"), dir.join("nope")];
+        assert!(a.matches_any_path(&paths).unwrap());
+        assert!(a.matches_any_path(&paths[..2]).unwrap());
+        assert!(a.matches_any_path(&paths[1..]).unwrap());
+        assert!(!a.matches_any_path(&paths[..1]).unwrap());
+        assert!(!a.matches_any_path(&[]).unwrap());
+        // The missing path is only opened when nothing before it matches.
+        assert!(a
+            .matches_any_path(&[dir.join("c"), dir.join("nope")])
+            .is_err());
+    }
+
+    #[test]
+    fn compare_paths() {
+        use super::{compare_paths, Comparison};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+
+        let (a, b, alink, nope) = (
+            dir.join("a"),
+            dir.join("b"),
+            dir.join("alink"),
+            dir.join("nope"),
+        );
+        assert_eq!(compare_paths(&a, &alink).unwrap(), Comparison::Same);
+        assert_eq!(compare_paths(&a, &b).unwrap(), Comparison::Different);
+        assert_eq!(
+            compare_paths(&nope, &a).unwrap(),
+            Comparison::FirstMissing
+        );
+        assert_eq!(
+            compare_paths(&a, &nope).unwrap(),
+            Comparison::SecondMissing
+        );
+        assert_eq!(
+            compare_paths(&nope, &nope).unwrap(),
+            Comparison::FirstMissing
+        );
+    }
+
+    #[test]
+    fn group_same_files() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        fs::hard_link(dir.join("b"), dir.join("blink")).unwrap();
+
+        let paths = [
+            dir.join("b"),
+            dir.join("a"),
+            dir.join("blink"),
+            dir.join("alink"),
+            dir.join("b"),
+        ];
+        let expected = vec![
+            vec![dir.join("b"), dir.join("blink"), dir.join("b")],
+            vec![dir.join("a"), dir.join("alink")],
+        ];
+        assert_eq!(super::group_same_files(&paths).unwrap(), expected);
+        #[cfg(feature = "rayon")]
+        assert_eq!(super::group_same_files_par(&paths).unwrap(), expected);
+
+        assert!(super::group_same_files::<&Path>(&[]).unwrap().is_empty());
+        assert!(super::group_same_files(&[dir.join("nope")]).is_err());
+        #[cfg(feature = "rayon")]
+        assert!(super::group_same_files_par(&[dir.join("nope")]).is_err());
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn same_path_async() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        async_std::task::block_on(async {
+            let a = Handle::from_path_async(dir.join("a")).await.unwrap();
+            let alink =
+                Handle::from_path_async(dir.join("alink")).await.unwrap();
+            let b = Handle::from_path_async(dir.join("b")).await.unwrap();
+            assert_eq!(a, alink);
+            assert_ne!(a, b);
+            assert!(Handle::from_path_async(dir.join("c")).await.is_err());
+
+            let file =
+                async_std::fs::File::open(dir.join("alink")).await.unwrap();
+            assert_eq!(Handle::from_async_std_file(file).await.unwrap(), a);
+        });
+    }
+
+    #[cfg(any(target_os = "redox", unix))]
+    #[test]
+    fn allocated_size_sparse() {
+        use super::{Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let file = File::create(dir.join("a")).unwrap();
+        file.set_len(16 * 1024 * 1024).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        assert!(h.allocated_size().unwrap() < 16 * 1024 * 1024);
+
+        let mut opts = HandleOptions::new();
+        opts.stat_only(true);
+        let snapshot = opts.open(dir.join("a")).unwrap();
+        assert!(snapshot.is_snapshot());
+        assert_eq!(
+            snapshot.allocated_size().unwrap(),
+            h.allocated_size().unwrap()
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn case_sensitive_dir_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let d = Handle::from_dir_path(dir).unwrap();
+        // Directories are case insensitive unless marked otherwise.
+        assert!(!d.is_case_sensitive_dir().unwrap());
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        assert!(a.is_case_sensitive_dir().is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn is_sparse_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        assert!(!h.is_sparse().unwrap());
+        assert!(Handle::dangling().is_sparse().is_err());
+    }
+
+    #[test]
+    fn weak_eq_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert!(a.weak_eq(&alink));
+        assert!(!a.weak_eq(&b));
+
+        if let (Ok(out1), Ok(out2)) = (Handle::stdout(), Handle::stdout()) {
+            assert!(out1.weak_eq(&out2));
+            assert_eq!(out1 == out2, out1.key().is_some());
+        }
+    }
+
+    #[test]
+    fn comparison_options() {
+        use super::{ComparisonOptions, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+
+        let mut opts = ComparisonOptions::new();
+        assert_eq!(opts.compare(&a, &a).unwrap(), Some(true));
+        assert_eq!(opts.compare(&a, &b).unwrap(), Some(false));
+        opts.distrust_fuse(true);
+        // The temporary directory could be on FUSE, in which case nothing can
+        // be said for sure.
+        let on_fuse = opts.compare(&a, &a).unwrap().is_none();
+        assert_eq!(
+            opts.is_same_file(dir.join("a"), dir.join("b")).unwrap(),
+            if on_fuse { None } else { Some(false) }
+        );
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn compare_fuse() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        let fuse = libc::FUSE_SUPER_MAGIC as u64;
+        let ext4 = libc::EXT4_SUPER_MAGIC as u64;
+
+        let all_fuse = |_: &Handle| Ok(fuse);
+        assert_eq!(super::compare_fuse(&a, &a, all_fuse).unwrap(), None);
+        assert_eq!(super::compare_fuse(&a, &b, all_fuse).unwrap(), None);
+        let b_fuse = |h: &Handle| Ok(if *h == b { fuse } else { ext4 });
+        assert_eq!(super::compare_fuse(&a, &a, b_fuse).unwrap(), Some(true));
+        assert_eq!(super::compare_fuse(&a, &b, b_fuse).unwrap(), None);
+        let no_fuse = |_: &Handle| Ok(ext4);
+        assert_eq!(super::compare_fuse(&a, &b, no_fuse).unwrap(), Some(false));
+        let broken =
+            |_: &Handle| Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        assert!(super::compare_fuse(&a, &b, broken).is_err());
+
+        assert_eq!(a.fs_magic().unwrap(), b.fs_magic().unwrap());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn from_file_pipe() {
+        use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+        use std::process::{Command, Stdio};
+
+        use super::Handle;
+
+        let mut child = Command::new("cmd")
+            .arg("/C")
+            .arg("echo")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let file = unsafe { File::from_raw_handle(stdout.into_raw_handle()) };
+        let pipe = Handle::from_file(file).unwrap();
+        assert_eq!(pipe, pipe);
+        if pipe.key().is_none() {
+            assert_ne!(pipe, Handle::stdout().unwrap());
+        }
+        child.wait().unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn from_path_device() {
+        use super::Handle;
+
+        let nul = Handle::from_path(r"\\.\NUL").unwrap();
+        assert_eq!(nul, nul);
+        if nul.key().is_none() {
+            assert_ne!(nul, Handle::from_path(r"\\.\NUL").unwrap());
+        }
+        assert!(Handle::from_path("//./NUL").is_ok());
+
+        // Opening a volume may still be refused, e.g., in a sandbox.
+        let drive = env::var("SystemDrive").unwrap_or_else(|_| "C:".into());
+        let volume = match Handle::from_path(format!(r"\\.\{}", drive)) {
+            Ok(volume) => volume,
+            Err(_) => return,
+        };
+        let again = Handle::from_path(format!(r"\\.\{}", drive)).unwrap();
+        assert_eq!(volume.key().is_some(), volume == again);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn named_pipe() {
+        use std::os::windows::ffi::OsStrExt;
+        use std::os::windows::io::FromRawHandle;
+        use std::ptr;
+
+        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+        use windows_sys::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+        use windows_sys::Win32::System::Pipes::{
+            CreateNamedPipeW, CreatePipe,
+        };
+
+        use super::Handle;
+
+        let mut read = ptr::null_mut();
+        let mut write = ptr::null_mut();
+        assert_ne!(
+            unsafe { CreatePipe(&mut read, &mut write, ptr::null(), 0) },
+            0
+        );
+        let read = Handle::from_file(unsafe { File::from_raw_handle(read) });
+        let write = Handle::from_file(unsafe { File::from_raw_handle(write) });
+        let (read, write) = (read.unwrap(), write.unwrap());
+        assert!(read.is_named_pipe().unwrap());
+        assert!(write.is_named_pipe().unwrap());
+        assert_eq!(read, read);
+        if read.key().is_none() {
+            assert_ne!(read, write);
+        }
+
+        let name = format!(r"\\.\pipe\same-file-test-{}", std::process::id());
+        let wide: Vec<u16> =
+            std::ffi::OsStr::new(&name).encode_wide().chain(Some(0)).collect();
+        let server = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                PIPE_ACCESS_DUPLEX,
+                0,
+                1,
+                512,
+                512,
+                0,
+                ptr::null(),
+            )
+        };
+        assert_ne!(server, INVALID_HANDLE_VALUE);
+        let server =
+            Handle::from_file(unsafe { File::from_raw_handle(server) })
+                .unwrap();
+        let client =
+            fs::OpenOptions::new().read(true).write(true).open(&name).unwrap();
+        let client = Handle::from_file(client).unwrap();
+        assert!(server.is_named_pipe().unwrap());
+        assert!(client.is_named_pipe().unwrap());
+        assert_eq!(server, server);
+        if server.key().is_none() {
+            assert_ne!(server, client);
+        }
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+        File::create(dir.join("a")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        assert!(!a.is_named_pipe().unwrap());
+        assert!(!Handle::dangling().is_named_pipe().unwrap());
+    }
+
+    #[cfg(any(target_os = "redox", unix))]
+    #[test]
+    fn from_as_fd() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut file = File::create(dir.join("log")).unwrap();
+        let log = Handle::from_path(dir.join("log")).unwrap();
+        assert_eq!(Handle::from_as_fd(&file).unwrap(), log);
+        // The file is only borrowed, so it's still usable.
+        file.write_all(b"still open").unwrap();
+
+        let mut child =
+            Command::new("cat").stdout(Stdio::piped()).spawn().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let pipe = Handle::from_as_fd(&stdout).unwrap();
+        assert_eq!(pipe, Handle::from_as_fd(&stdout).unwrap());
+        assert_ne!(pipe, log);
+        drop(stdout);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn from_as_handle() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut file = File::create(dir.join("log")).unwrap();
+        let log = Handle::from_path(dir.join("log")).unwrap();
+        assert_eq!(Handle::from_as_handle(&file).unwrap(), log);
+        // The file is only borrowed, so it's still usable.
+        file.write_all(b"still open").unwrap();
+
+        let mut child = Command::new("cmd")
+            .arg("/C")
+            .arg("echo")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let pipe = Handle::from_as_handle(&stdout).unwrap();
+        assert!(pipe.key().is_none());
+        assert_ne!(pipe, log);
+        drop(stdout);
+        child.wait().unwrap();
+    }
+
+    #[cfg(feature = "cap-std")]
+    #[test]
+    fn from_cap_file() {
+        use cap_std::{ambient_authority, fs::Dir};
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let cap = Dir::open_ambient_dir(dir, ambient_authority()).unwrap();
+        let a = Handle::from_cap_file(cap.open("a").unwrap()).unwrap();
+        assert_eq!(a, Handle::from_path(dir.join("a")).unwrap());
+        assert_ne!(a, Handle::from_cap_file(cap.open("b").unwrap()).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn error_kind() {
+        use super::{is_same_file_detailed, Error, ErrorKind};
+
+        let kind = |errno| Error::open(io::Error::from_raw_os_error(errno));
+        assert_eq!(kind(libc::ENOENT).kind(), ErrorKind::NotFound);
+        assert_eq!(kind(libc::EACCES).kind(), ErrorKind::PermissionDenied);
+        assert_eq!(kind(libc::EPERM).kind(), ErrorKind::PermissionDenied);
+        assert_eq!(kind(libc::EMFILE).kind(), ErrorKind::TooManyOpenFiles);
+        assert_eq!(kind(libc::ENFILE).kind(), ErrorKind::TooManyOpenFiles);
+        assert_eq!(kind(libc::EINTR).kind(), ErrorKind::Interrupted);
+        assert_eq!(kind(libc::EIO).kind(), ErrorKind::Other);
+        assert!(kind(libc::EMFILE).is_retryable());
+        assert!(kind(libc::EAGAIN).is_retryable());
+        assert!(!kind(libc::EACCES).is_retryable());
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        assert!(is_same_file_detailed(dir.join("a"), dir.join("a")).unwrap());
+        let err = is_same_file_detailed(dir.join("a"), dir.join("nope"))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.path(), Some(&*dir.join("nope")));
+    }
+
+    #[test]
+    fn from_path_detailed() {
+        use std::error::Error as _;
+
+        use super::{ErrorKind, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let a = Handle::from_path_detailed(dir.join("a")).unwrap();
+        assert_eq!(a, Handle::from_path(dir.join("a")).unwrap());
+
+        let err = Handle::from_path_detailed(dir.join("nope")).unwrap_err();
+        assert!(err.is_open_error());
+        assert!(!err.is_stat_error());
+        assert_eq!(err.io_error().kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(!err.is_retryable());
+        assert_eq!(err.path(), Some(&*dir.join("nope")));
+        let msg =
+            format!("failed to open file {}: ", dir.join("nope").display());
+        assert!(err.to_string().starts_with(&msg));
+        assert!(err.source().is_some());
+        let err = io::Error::from(err);
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn equal_however_obtained() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::convert::TryFrom;
+        use std::hash::{Hash, Hasher};
+
+        use super::{Handle, HandleOptions};
+
+        fn hash(h: &Handle) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            h.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let mut handles = vec![
+            Handle::from_path(dir.join("alink")).unwrap(),
+            Handle::from_path_canonical(dir.join("a")).unwrap(),
+            HandleOptions::new().open(dir.join("a")).unwrap(),
+            Handle::from_file(File::open(dir.join("a")).unwrap()).unwrap(),
+            Handle::try_from(&File::open(dir.join("a")).unwrap()).unwrap(),
+            a.try_clone().unwrap(),
+            a.try_clone_fresh().unwrap(),
+        ];
+        match a.reopen(fs::OpenOptions::new().read(true)) {
+            Ok(file) => handles.push(Handle::from_file(file).unwrap()),
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::Unsupported),
+        }
+        #[cfg(any(target_os = "redox", unix))]
+        {
+            let mut opts = HandleOptions::new();
+            opts.stat_only(true);
+            handles.push(opts.open(dir.join("a")).unwrap());
+            for entry in fs::read_dir(dir).unwrap() {
+                let entry = entry.unwrap();
+                handles.push(Handle::from_dir_entry(&entry).unwrap());
+                handles.push(Handle::from_dir_entry_snapshot(&entry).unwrap());
+            }
+        }
+
+        handles.push(a);
+        for h1 in &handles {
+            for h2 in &handles {
+                assert_eq!(h1, h2);
+                assert_eq!(hash(h1), hash(h2));
+                assert_eq!(h1.key(), h2.key());
+            }
+        }
+    }
+
+    #[test]
+    fn modified_at_open() {
+        use std::io::Write;
+        use std::time::Duration;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut file = File::create(dir.join("a")).unwrap();
+        let mtime = file.metadata().unwrap().modified().unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        assert_eq!(a.modified_at_open(), Some(mtime));
+        assert_eq!(a.try_clone().unwrap().modified_at_open(), Some(mtime));
+
+        // Wait for the modification time to move, which depends on the
+        // granularity of the file system's timestamps.
+        let mut later = mtime;
+        for _ in 0..300 {
+            file.write_all(b"changed").unwrap();
+            later = file.metadata().unwrap().modified().unwrap();
+            if later != mtime {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_ne!(later, mtime);
+        assert_eq!(a.modified_at_open(), Some(mtime));
+        assert_eq!(
+            Handle::from_path(dir.join("a")).unwrap().modified_at_open(),
+            Some(later)
+        );
+        assert_eq!(Handle::dangling().modified_at_open(), None);
+    }
+
+    #[test]
+    fn eq_with_mtime() {
+        use std::io::Write;
+        use std::time::Duration;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut file = File::create(dir.join("a")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        assert!(a1.eq_with_mtime(&a2));
+        assert!(a1.eq_with_mtime(&a1.try_clone().unwrap()));
+
+        File::create(dir.join("b")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert!(!a1.eq_with_mtime(&b));
+
+        // Wait for the modification time to move, which depends on the
+        // granularity of the file system's timestamps.
+        let mtime = a1.modified_at_open().unwrap();
+        for _ in 0..300 {
+            file.write_all(b"changed").unwrap();
+            if file.metadata().unwrap().modified().unwrap() != mtime {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let a3 = Handle::from_path(dir.join("a")).unwrap();
+        assert_eq!(a1, a3);
+        assert!(!a1.eq_with_mtime(&a3));
+
+        let d = Handle::dangling();
+        assert!(!d.eq_with_mtime(&d));
+    }
+
+    #[test]
+    fn dangling() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        use super::Handle;
+
+        fn hash(h: &Handle) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            h.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let d1 = Handle::dangling();
+        let d2 = Handle::dangling();
+        assert_eq!(d1, d1);
+        assert_ne!(d1, d2);
+        assert_ne!(d1, a);
+        assert_ne!(a, d1);
+        assert_ne!(hash(&d1), hash(&d2));
+        assert!(d1.key().is_none());
+        assert!(d1.identity_parts().is_none());
+        assert!(!d1.is_snapshot());
+        assert!(!d1.weak_eq(&d1));
+        assert!(!d1.same_volume(&a));
+        assert!(!d1.same_inode(&d1));
+        assert_ne!(d1.try_clone().unwrap(), d1);
+        assert!(d1.try_clone_fresh().is_err());
+        assert!(d1.into_file().is_none());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn memfd() {
+        use std::fs::OpenOptions;
+        use std::os::unix::io::FromRawFd;
+
+        use super::Handle;
+
+        let memfd = |name: &[u8]| {
+            let fd = unsafe { libc::memfd_create(name.as_ptr().cast(), 0) };
+            assert!(fd != -1, "{}", io::Error::last_os_error());
+            unsafe { File::from_raw_fd(fd) }
+        };
+        let a = Handle::from_file(memfd(b"a\0")).unwrap();
+        let b = Handle::from_file(memfd(b"b\0")).unwrap();
+        let a2 = Handle::from_file(a.as_file().try_clone().unwrap()).unwrap();
+        assert_eq!(a, a2);
+        assert_ne!(a, b);
+        assert!(a.is_unlinked().unwrap());
+        let reopened = a.reopen(OpenOptions::new().read(true)).unwrap();
+        assert_eq!(a, Handle::from_file(reopened).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tmpfile_linked() {
+        use std::ffi::CString;
+        use std::fs::OpenOptions;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_TMPFILE)
+            .open(dir)
+        {
+            Ok(file) => file,
+            // Not every file system supports `O_TMPFILE`.
+            Err(_) => return,
+        };
+        let fd = file.as_raw_fd();
+        let h = Handle::from_file(file).unwrap();
+        assert!(h.is_unlinked().unwrap());
+
+        let src = CString::new(format!("/proc/self/fd/{}", fd)).unwrap();
+        let linked = dir.join("linked");
+        let dst = CString::new(linked.as_os_str().as_bytes()).unwrap();
+        let rc = unsafe {
+            libc::linkat(
+                libc::AT_FDCWD,
+                src.as_ptr(),
+                libc::AT_FDCWD,
+                dst.as_ptr(),
+                libc::AT_SYMLINK_FOLLOW,
+            )
+        };
+        assert_eq!(rc, 0, "{}", io::Error::last_os_error());
+        assert!(!h.is_unlinked().unwrap());
+        assert_eq!(h, Handle::from_path(&linked).unwrap());
+
+        fs::remove_file(&linked).unwrap();
+        assert!(h.is_unlinked().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn same_mount() {
+        use super::{imp, is_same_mount, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        assert!(is_same_mount(dir.join("a"), dir.join("a")).unwrap());
+        assert!(is_same_mount(dir.join("a"), dir.join("b")).unwrap());
+        assert!(!is_same_mount(dir.join("a"), "/proc/self/status").unwrap());
+
+        // The fallback must agree with statx, where statx knows the answer.
+        let fallback = imp::mountinfo_mount_id(&dir.join("a")).unwrap();
+        let proc_fallback =
+            imp::mountinfo_mount_id(Path::new("/proc/self/status")).unwrap();
+        assert_ne!(fallback, proc_fallback);
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        if let Some(id) = a.mount_id().unwrap() {
+            assert_eq!(id, fallback);
+        }
+    }
+
+    #[test]
+    fn identity_parts() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+
+        let parts = a.identity_parts().unwrap();
+        assert_eq!(Some(parts), alink.identity_parts());
+        assert_ne!(Some(parts), b.identity_parts());
+        assert_eq!(parts.0, b.identity_parts().unwrap().0);
+        #[cfg(any(target_os = "redox", unix))]
+        assert_eq!(parts, (a.dev(), u128::from(a.ino())));
+    }
+
+    #[test]
+    fn into_file_roundtrip() {
+        use std::io::{Read, Write};
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let file = File::create(dir.join("a")).unwrap();
+        let mut file = Handle::from_file(file).unwrap().into_file().unwrap();
+        file.write_all(b"foo").unwrap();
+
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        let mut contents = String::new();
+        File::try_from(h).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "foo");
+
+        if let Ok(stdout) = Handle::stdout() {
+            let stdout = File::try_from(stdout).unwrap_err();
+            assert!(stdout.as_borrowed_file().is_some());
+            assert!(stdout.into_file().is_none());
+            // The stream is still open.
+            assert!(Handle::stdout().is_ok());
+        }
+    }
+
+    #[test]
+    fn same_inode_and_volume() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert!(a.same_inode(&alink));
+        assert!(a.same_volume(&alink));
+        assert!(!a.same_inode(&b));
+        assert!(a.same_volume(&b));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn path_retry_sharing_violation() {
+        use std::fs::OpenOptions;
+        use std::os::windows::fs::OpenOptionsExt;
+        use std::thread;
+        use std::time::Duration;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let locked = OpenOptions::new()
+            .read(true)
+            .share_mode(0)
+            .open(dir.join("a"))
+            .unwrap();
+
+        let delay = Duration::from_millis(10);
+        let err =
+            Handle::from_path_retry(dir.join("a"), 2, delay).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(32));
+
+        let unlocker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(locked);
+        });
+        let delay = Duration::from_millis(50);
+        let handle =
+            Handle::from_path_retry(dir.join("a"), 100, delay).unwrap();
+        unlocker.join().unwrap();
+        assert_eq!(handle, Handle::from_path(dir.join("a")).unwrap());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn path_timeout_trivial() {
+        use std::io;
+        use std::time::Duration;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let timeout = Duration::from_secs(60);
+        assert_eq!(
+            Handle::from_path_timeout(dir.join("a"), timeout).unwrap(),
+            Handle::from_path(dir.join("a")).unwrap(),
+        );
+        let err =
+            Handle::from_path_timeout(dir.join("b"), timeout).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn final_path_soft() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
+        let alink = Handle::from_path(dir.join("alink")).unwrap();
+        let expected = fs::canonicalize(dir.join("a")).unwrap();
+        assert_eq!(alink.final_path_verbatim().unwrap(), expected);
+        let path = alink.final_path().unwrap();
+        assert!(!path.to_str().unwrap().starts_with(r"\\?\"));
+        assert!(is_same_file(path, dir.join("a")).unwrap());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn same_volume_path() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        let volume = a.volume_path().unwrap();
+        assert_eq!(volume, b.volume_path().unwrap());
+        assert!(volume.is_dir());
+    }
+
+    #[test]
+    fn path_retry_trivial() {
+        use std::time::Duration;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let delay = Duration::from_millis(1);
+        assert_eq!(
+            Handle::from_path_retry(dir.join("a"), 3, delay).unwrap(),
+            Handle::from_path(dir.join("a")).unwrap(),
+        );
+        assert!(Handle::from_path_retry(dir.join("b"), 3, delay).is_err());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn path_follows_rename() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let handle = Handle::from_path(dir.join("a")).unwrap();
+        let expected = fs::canonicalize(dir.join("a")).unwrap();
+        assert_eq!(handle.path().unwrap(), expected);
+
+        fs::rename(dir.join("a"), dir.join("b")).unwrap();
+        let expected = fs::canonicalize(dir.join("b")).unwrap();
+        assert_eq!(handle.path().unwrap(), expected);
+    }
+
+    // Paths in the data volume can be reached both directly and through the
+    // firmlinks from the system volume. The temporary directory lives in the
+    // data volume, so both spellings must refer to the same file.
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn same_file_firmlink() {
+        let data = Path::new("/System/Volumes/Data");
+        if !data.is_dir() {
+            return;
+        }
+        let tdir = tmpdir();
+        let path = fs::canonicalize(tdir.path()).unwrap().join("a");
+        File::create(&path).unwrap();
+        let data_path = data.join(path.strip_prefix("/").unwrap());
+        if !data_path.exists() {
+            return;
+        }
+        assert!(is_same_file(&path, &data_path).unwrap());
+        #[cfg(feature = "backend-libc")]
+        {
+            use super::Handle;
+
+            let h1 = Handle::from_path(&path).unwrap();
+            let h2 = Handle::from_path(&data_path).unwrap();
+            assert!(h1.same_volume_uuid(&h2).unwrap());
+            let root = Handle::from_path(data).unwrap();
+            assert!(h1.same_volume_uuid(&root).unwrap());
+            // The system volume is in the same volume group, but it is not
+            // the same volume.
+            let system = Handle::from_path("/System").unwrap();
+            assert!(!system.same_volume_uuid(&root).unwrap());
+            assert!(!root.same_volume_uuid(&system).unwrap());
+        }
+    }
+
+    #[cfg(all(target_os = "macos", feature = "backend-libc"))]
+    #[test]
+    fn shares_storage_clone() {
+        use std::ffi::CString;
+        use std::io::Write;
+        use std::os::unix::ffi::OsStrExt;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::write(dir.join("a"), vec![b'a'; 1 << 16]).unwrap();
+        let src = CString::new(dir.join("a").as_os_str().as_bytes()).unwrap();
+        let dst = CString::new(dir.join("b").as_os_str().as_bytes()).unwrap();
+        // Cloning is only supported on APFS.
+        if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) } == -1 {
+            return;
+        }
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_ne!(a, b);
+        assert!(a.shares_storage(&b).unwrap());
+
+        let mut file =
+            fs::OpenOptions::new().write(true).open(dir.join("b")).unwrap();
+        file.write_all(&[b'b'; 1 << 16]).unwrap();
+        file.sync_all().unwrap();
+        assert!(!a.shares_storage(&b).unwrap());
+    }
+
+    // Whether there is a generation number depends on the file system of the
+    // temporary directory, so this mostly checks that querying it doesn't
+    // fail and that it is stable.
+    #[cfg(unix)]
+    #[test]
+    fn same_generation() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        assert_eq!(a1.generation(), a2.generation());
+        assert_eq!(Handle::from_path("/dev/null").unwrap().generation(), None);
+    }
+
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn generation_matches_stat() {
+        use std::process::Command;
+
+        use super::{Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let handle = Handle::from_path(dir.join("a")).unwrap();
+        let out = match Command::new("stat")
+            .arg("-f")
+            .arg("%v")
+            .arg(dir.join("a"))
+            .output()
+        {
+            Ok(out) => out,
+            Err(_) => return,
+        };
+        let expected: u64 =
+            String::from_utf8(out.stdout).unwrap().trim().parse().unwrap();
+        // Zero means that the generation number is hidden from us.
+        let expected = if expected == 0 { None } else { Some(expected) };
+        assert_eq!(handle.generation(), expected);
+        assert_eq!(handle.key().unwrap().generation(), expected);
+
+        // Snapshots get it from the same `stat` buffer.
+        let mut opts = HandleOptions::new();
+        opts.stat_only(true);
+        let snapshot = opts.open(dir.join("a")).unwrap();
+        assert!(snapshot.is_snapshot());
+        assert_eq!(snapshot.generation(), expected);
+        assert_eq!(snapshot.key().unwrap().generation(), expected);
+    }
+
+    // VxWorks has no `fstatvfs`.
+    #[cfg(all(unix, not(target_os = "vxworks")))]
+    #[test]
+    fn same_fs_id() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a.fs_id().unwrap(), b.fs_id().unwrap());
+    }
+
+    // VxWorks has no `fstatvfs`.
+    #[cfg(all(unix, not(target_os = "vxworks")))]
+    #[test]
+    fn fsid_eq_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert!(a1.fsid_eq(&a2).unwrap());
+        assert!(!a1.fsid_eq(&b).unwrap());
+    }
+
+    // VxWorks has no `statvfs`.
+    #[cfg(all(unix, not(target_os = "vxworks")))]
+    #[test]
+    fn fsid_snapshot() {
+        use super::{ComparisonOptions, Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let mut stat_only = HandleOptions::new();
+        stat_only.stat_only(true);
+        let snap = stat_only.open(dir.join("a")).unwrap();
+        let b = stat_only.open(dir.join("b")).unwrap();
+        assert!(snap.is_snapshot());
+        assert_eq!(snap.fs_id().unwrap(), a.fs_id().unwrap());
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(snap.fs_magic().unwrap(), a.fs_magic().unwrap());
+        assert!(snap.fsid_eq(&a).unwrap());
+
+        let mut opts = ComparisonOptions::new();
+        opts.fsid(true);
+        assert_eq!(opts.compare(&snap, &a).unwrap(), Some(true));
+        assert_eq!(opts.compare(&a, &snap).unwrap(), Some(true));
+        assert_eq!(opts.compare(&snap, &b).unwrap(), Some(false));
+    }
+
+    // This only checks something interesting when the temporary directory
+    // is on btrfs. Otherwise, it checks that we detect that it isn't.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn same_subvolume_id() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a.subvolume_id().unwrap(), b.subvolume_id().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn overlay_eq_trivial() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a1.overlay_eq(&a2).unwrap(), Some(true));
+        if a1.is_overlayfs().unwrap() {
+            assert_eq!(a1.overlay_eq(&b).unwrap(), None);
+        } else {
+            assert_eq!(a1.overlay_eq(&b).unwrap(), Some(false));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn overlay_eq_copied_up() {
+        use std::fs::{self, OpenOptions};
+        use std::io::Write;
+        use std::process::Command;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+        for sub in &["lower", "upper", "work", "merged"] {
+            fs::create_dir(dir.join(sub)).unwrap();
+        }
+        File::create(dir.join("lower/f")).unwrap();
+        File::create(dir.join("lower/g")).unwrap();
+
+        let opts = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            dir.join("lower").display(),
+            dir.join("upper").display(),
+            dir.join("work").display(),
+        );
+        let mounted = Command::new("mount")
+            .args(["-t", "overlay", "overlay", "-o", &opts])
+            .arg(dir.join("merged"))
+            .output()
+            .map_or(false, |out| out.status.success());
+        if !mounted {
+            // Mounting needs privileges that are not usually available.
+            return;
+        }
+
+        // Writing through the merged view copies the file up.
+        OpenOptions::new()
+            .append(true)
+            .open(dir.join("merged/f"))
+            .unwrap()
+            .write_all(b"copied up")
+            .unwrap();
+        let merged = Handle::from_path(dir.join("merged/f")).unwrap();
+        let upper = Handle::from_path(dir.join("upper/f")).unwrap();
+        let lower = Handle::from_path(dir.join("lower/f")).unwrap();
+        let other = Handle::from_path(dir.join("lower/g")).unwrap();
+        assert!(merged.is_overlayfs().unwrap());
+        assert_ne!(upper, lower);
+        // Without libc, the origin of `upper` can't be verified.
+        let verified = cfg!(feature = "backend-libc");
+        for &(a, b) in &[(&upper, &lower), (&lower, &upper)] {
+            let eq = a.overlay_eq(b).unwrap();
+            if !verified {
+                assert_eq!(eq, None);
+            } else if upper.dev() == lower.dev() {
+                assert_eq!(eq, Some(true));
+            } else {
+                assert_ne!(eq, Some(false));
+            }
+        }
+        let expected = if verified { Some(false) } else { None };
+        assert_eq!(upper.overlay_eq(&other).unwrap(), expected);
+
+        drop(merged);
+        Command::new("umount").arg(dir.join("merged")).status().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_fifo_does_not_block() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::AsRawFd;
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let fifo = tdir.path().join("fifo");
+        let cpath = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) });
+
+        let (tx, rx) = mpsc::channel();
+        let path = fifo.clone();
+        thread::spawn(move || {
+            let result = Handle::from_path(&path)
+                .and_then(|h1| Ok(h1 == Handle::from_path(&path)?));
+            tx.send(result.map_err(|e| e.to_string())).unwrap();
+        });
+        let same = rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("opening a FIFO blocked")
+            .unwrap();
+        assert!(same);
+
+        // Only the FIFO is left in non-blocking mode.
+        let nonblocking = |h: Handle| {
+            let flags = unsafe { libc::fcntl(h.as_raw_fd(), libc::F_GETFL) };
+            flags & libc::O_NONBLOCK != 0
+        };
+        assert!(nonblocking(Handle::from_path(&fifo).unwrap()));
+        File::create(tdir.path().join("file")).unwrap();
+        let file = Handle::from_path(tdir.path().join("file")).unwrap();
+        assert!(!nonblocking(file));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_device_node() {
+        use super::Handle;
+
+        if !Path::new("/dev/null").exists() || !Path::new("/dev/zero").exists()
+        {
+            return;
+        }
+        let null1 = Handle::from_path("/dev/null").unwrap();
+        let null2 = Handle::from_path("/dev/null").unwrap();
+        let zero = Handle::from_path("/dev/zero").unwrap();
+        assert_eq!(null1, null2);
+        assert_ne!(null1, zero);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn same_file_unreadable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let perms = fs::Permissions::from_mode(0o000);
+        fs::set_permissions(dir.join("a"), perms).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a1, a2);
+        assert_ne!(a1, b);
+    }
+
+    // Without a controlling terminal, only an `O_PATH` open of /dev/tty can
+    // succeed, so this is limited to the platforms where we use it.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn same_device_tty() {
+        use super::Handle;
+
+        if !Path::new("/dev/tty").exists() {
+            return;
+        }
+        let tty1 = Handle::from_path("/dev/tty").unwrap();
+        let tty2 = Handle::from_path("/dev/tty").unwrap();
+        assert_eq!(tty1, tty2);
+        assert_ne!(tty1, Handle::from_path("/dev/null").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn from_link() {
+        use super::{is_same_link, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("link")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("other")).unwrap();
+        // This links the symbolic link itself, not its target.
+        fs::hard_link(dir.join("link"), dir.join("sub").join("hard")).unwrap();
+
+        let link = Handle::from_link(dir.join("link")).unwrap();
+        assert_eq!(link, Handle::from_link(dir.join("sub/../link")).unwrap());
+        assert_eq!(link, Handle::from_link(dir.join("sub/hard")).unwrap());
+        assert_ne!(link, Handle::from_link(dir.join("other")).unwrap());
+        assert_ne!(link, Handle::from_path(dir.join("a")).unwrap());
+        assert_ne!(link, Handle::from_path(dir.join("link")).unwrap());
+        assert!(is_same_link(dir.join("link"), dir.join("sub/hard")).unwrap());
+        assert!(!is_same_link(dir.join("link"), dir.join("other")).unwrap());
+
+        let err = Handle::from_link(dir.join("a")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        assert!(is_same_link(dir.join("a"), dir.join("link")).is_err());
+    }
+
+    // Symbolic links can only be created on Unix and Windows.
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn same_file_no_follow() {
+        use super::is_same_file_no_follow as same;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::hard_link(dir.join("a"), dir.join("ahard")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("link")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("other")).unwrap();
+        soft_link_file(dir.join("missing"), dir.join("dangling")).unwrap();
+
+        assert!(same(dir.join("a"), dir.join("a")).unwrap());
+        assert!(same(dir.join("a"), dir.join("ahard")).unwrap());
+        assert!(same(dir.join("link"), dir.join("link")).unwrap());
+        assert!(!same(dir.join("link"), dir.join("a")).unwrap());
+        assert!(!same(dir.join("a"), dir.join("link")).unwrap());
+        assert!(!same(dir.join("link"), dir.join("ahard")).unwrap());
+        assert!(!same(dir.join("link"), dir.join("other")).unwrap());
+        assert!(same(dir.join("dangling"), dir.join("dangling")).unwrap());
+        assert!(!same(dir.join("dangling"), dir.join("link")).unwrap());
+        assert!(same(dir.join("missing"), dir.join("dangling")).is_err());
+        // Only the last component is not followed.
+        fs::create_dir(dir.join("sub")).unwrap();
+        soft_link_dir(dir.join("sub"), dir.join("sublink")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("sub").join("link")).unwrap();
+        assert!(same(
+            dir.join("sub").join("link"),
+            dir.join("sublink").join("link")
+        )
+        .unwrap());
+
+        #[cfg(unix)]
+        {
+            fs::hard_link(dir.join("link"), dir.join("linkhard")).unwrap();
+            assert!(same(dir.join("link"), dir.join("linkhard")).unwrap());
+            assert!(!same(dir.join("linkhard"), dir.join("a")).unwrap());
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn same_file_no_follow_junction() {
+        use super::is_same_file_no_follow as same;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        junction(dir.join("a"), dir.join("j1")).unwrap();
+        junction(dir.join("a"), dir.join("j2")).unwrap();
+        assert!(same(dir.join("a"), dir.join("a")).unwrap());
+        assert!(same(dir.join("j1"), dir.join("j1")).unwrap());
+        assert!(!same(dir.join("j1"), dir.join("a")).unwrap());
+        assert!(!same(dir.join("j1"), dir.join("j2")).unwrap());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn from_link_junction() {
+        use super::{is_same_link, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        junction(dir.join("a"), dir.join("j1")).unwrap();
+        junction(dir.join("a"), dir.join("j2")).unwrap();
+
+        let j1 = Handle::from_link(dir.join("j1")).unwrap();
+        assert_eq!(j1, Handle::from_link(dir.join("a/../j1")).unwrap());
+        assert_ne!(j1, Handle::from_link(dir.join("j2")).unwrap());
+        assert_ne!(j1, Handle::from_path(dir.join("j1")).unwrap());
+        assert!(is_same_link(dir.join("j1"), dir.join("j1")).unwrap());
+        assert!(!is_same_link(dir.join("j1"), dir.join("j2")).unwrap());
+        // ERROR_NOT_A_REPARSE_POINT
+        let err = Handle::from_link(dir.join("a")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(4390));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn from_path_or_canonical() {
+        use std::collections::HashSet;
+
+        use super::{imp, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path_or_canonical(dir.join("a")).unwrap();
+        assert!(!a.is_path_based());
+        assert_eq!(a, Handle::from_path(dir.join("a")).unwrap());
+
+        let denied = |p: &Path| {
+            imp::Handle::path_fallback(p, |_| {
+                Err(io::Error::from_raw_os_error(5)) // ERROR_ACCESS_DENIED
+            })
+            .map(Handle)
+        };
+        let a1 = denied(&dir.join("a")).unwrap();
+        let a2 = denied(&dir.join(".").join("A")).unwrap();
+        let b = denied(&dir.join("b")).unwrap();
+        assert!(a1.is_path_based());
+        assert!(a1.key().is_none());
+        assert!(a1.modified_at_open().is_none());
+        assert!(a.modified_at_open().is_some());
+        assert_eq!(
+            a1.canonical_path(),
+            Some(&*fs::canonicalize(dir.join("a")).unwrap())
+        );
+        assert_eq!(a1, a2);
+        assert_ne!(a1, b);
+        assert_ne!(a1, a);
+        let set: HashSet<_> = vec![a1, a2, b].into_iter().collect();
+        assert_eq!(set.len(), 2);
+
+        // A file that doesn't exist may still have a canonical path.
+        let c = denied(&dir.join("c")).unwrap();
+        assert_eq!(
+            c.canonical_path(),
+            Some(&*fs::canonicalize(dir).unwrap().join("c"))
+        );
+        let err = imp::Handle::path_fallback(&dir.join("c"), |_| {
+            Err(io::Error::from_raw_os_error(2)) // ERROR_FILE_NOT_FOUND
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn short_names() {
+        use std::ffi::OsString;
+        use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+        use windows_sys::Win32::Storage::FileSystem::GetShortPathNameW;
+
+        use super::{imp, Handle};
+
+        let tdir = tmpdir();
+        let dir = fs::canonicalize(tdir.path()).unwrap();
+
+        let long = dir.join("a long file name.txt");
+        File::create(&long).unwrap();
+        let wide: Vec<u16> =
+            long.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut buf = vec![0u16; 1024];
+        let len = unsafe {
+            GetShortPathNameW(wide.as_ptr(), buf.as_mut_ptr(), 1024)
+        } as usize;
+        assert!(len > 0 && len < buf.len());
+        let short = PathBuf::from(OsString::from_wide(&buf[..len]));
+        // 8.3 names may be disabled on the volume, in which case the short
+        // name is the long name.
+        if short == long {
+            return;
+        }
+
+        assert!(is_same_file(&short, &long).unwrap());
+        assert_eq!(imp::long_path_name(&short).unwrap(), long);
+        let denied = |p: &Path| {
+            imp::Handle::path_fallback(p, |_| {
+                Err(io::Error::from_raw_os_error(5)) // ERROR_ACCESS_DENIED
+            })
+            .map(Handle)
+            .unwrap()
+        };
+        assert_eq!(denied(&short), denied(&long));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn reparse_tag_junction() {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        use super::Handle;
+
+        // From winbase.h and winnt.h.
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+        const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        junction(dir.join("a"), dir.join("ajunction")).unwrap();
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT,
+            )
+            .open(dir.join("ajunction"))
+            .unwrap();
+        let link = Handle::from_file(file).unwrap();
+        assert_eq!(
+            link.reparse_tag().unwrap(),
+            Some(IO_REPARSE_TAG_MOUNT_POINT)
+        );
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        assert_ne!(link, a);
+        assert_eq!(a.reparse_tag().unwrap(), None);
+        let target = Handle::from_path(dir.join("ajunction")).unwrap();
+        assert_eq!(target.reparse_tag().unwrap(), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn same_file_junction() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        File::create(dir.join("a").join("file")).unwrap();
+        File::create(dir.join("a").join("other")).unwrap();
+        junction(dir.join("a"), dir.join("ajunction")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("ajunction")).unwrap());
+        assert!(is_same_file(
+            dir.join("a").join("file"),
+            dir.join("ajunction").join("file"),
+        )
+        .unwrap());
+        assert!(!is_same_file(
+            dir.join("a").join("file"),
+            dir.join("ajunction").join("other"),
+        )
+        .unwrap());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn same_file_nested_junction() {
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir_all(dir.join("a").join("b")).unwrap();
+        File::create(dir.join("a").join("b").join("file")).unwrap();
+        junction(dir.join("a"), dir.join("j1")).unwrap();
+        junction(dir.join("j1").join("b"), dir.join("j2")).unwrap();
+        assert!(is_same_file(
+            dir.join("a").join("b").join("file"),
+            dir.join("j2").join("file"),
+        )
+        .unwrap());
+        assert!(is_same_file(
+            dir.join("j1").join("b").join("file"),
+            dir.join("j2").join("file"),
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn snapshot_fallback_trivial() {
+        use super::{Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let mut opts = HandleOptions::new();
+        opts.snapshot_fallback(true);
+        let h = opts.open(dir.join("a")).unwrap();
+        assert!(!h.is_snapshot());
+        assert_eq!(h, Handle::from_path(dir.join("a")).unwrap());
+        assert!(opts.is_same_file(dir.join("a"), dir.join("a")).unwrap());
+    }
+
+    #[cfg(any(target_os = "redox", unix))]
+    #[test]
+    fn stat_only() {
+        use super::{Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("d")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
+
+        let mut opts = HandleOptions::new();
+        opts.stat_only(true);
+        let h = opts.open(dir.join("a")).unwrap();
+        assert!(h.is_snapshot());
+        assert!(h.into_file().is_none());
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        assert_eq!(opts.open(dir.join("a")).unwrap(), a);
+        assert_eq!(opts.open(dir.join("alink")).unwrap(), a);
+        assert!(opts.open(dir.join("nope")).is_err());
+
+        opts.require_directory(true);
+        assert!(opts.open(dir.join("d")).unwrap().is_snapshot());
+        let err = opts.open(dir.join("a")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+
+        opts.require_directory(false).follow_symlinks(false);
+        let err = opts.open(dir.join("alink")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dont_sync() {
+        use std::cell::Cell;
+        use std::os::unix::io::AsRawFd;
+
+        use super::{imp, Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let file = File::create(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("d")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
+
+        let seen = Cell::new(None);
+        imp::statx_dont_sync(file.as_raw_fd(), |fd, flags, mask, _| {
+            seen.set(Some((fd, flags, mask)));
+            Ok(())
+        })
+        .unwrap();
+        let (fd, flags, mask) = seen.get().unwrap();
+        assert_eq!(fd, file.as_raw_fd());
+        // AT_STATX_DONT_SYNC, and the type and inode number.
+        assert_eq!(flags, libc::AT_EMPTY_PATH | 0x4000);
+        assert_eq!(mask & 0x101, 0x101);
+
+        let mut opts = HandleOptions::new();
+        opts.dont_sync(true);
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let h = opts.open(dir.join("a")).unwrap();
+        assert_eq!(h, a);
+        assert_eq!(h.modified_at_open(), a.modified_at_open());
+        assert_eq!(opts.open(dir.join("alink")).unwrap(), a);
+        assert_eq!(
+            opts.open(dir.join("d")).unwrap(),
+            Handle::from_path(dir.join("d")).unwrap()
+        );
+        let null = opts.open("/dev/null").unwrap();
+        assert_eq!(null, Handle::from_path("/dev/null").unwrap());
+        assert_eq!(null.rdev(), Some(libc::makedev(1, 3)));
+
+        opts.require_directory(true);
+        let err = opts.open(dir.join("a")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+
+        opts.require_directory(false).follow_symlinks(false);
+        let err = opts.open(dir.join("alink")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn preserve_atime() {
+        use std::ffi::CString;
+        use std::io::Read;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::MetadataExt;
+
+        use super::{Handle, HandleOptions};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::write(dir.join("a"), b"contents").unwrap();
+        // Move the access time well before the modification time, so that
+        // even `relatime` would update it on the next read.
+        let path = CString::new(dir.join("a").as_os_str().as_bytes()).unwrap();
+        let times = [
+            libc::timespec { tv_sec: 1_000_000, tv_nsec: 0 },
+            libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+        ];
+        let rc = unsafe {
+            libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), 0)
+        };
+        assert_eq!(rc, 0);
+
+        let mut opts = HandleOptions::new();
+        opts.preserve_atime(true);
+        let mut h = opts.open(dir.join("a")).unwrap();
+        assert_eq!(h, Handle::from_path(dir.join("a")).unwrap());
+        let mut contents = String::new();
+        h.as_file_mut().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "contents");
+        assert_eq!(fs::metadata(dir.join("a")).unwrap().atime(), 1_000_000);
+    }
+
+    // Elsewhere, there are no keys to compare.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix, windows))]
+    #[test]
+    fn strict_handle_trivial() {
+        use super::{Handle, StrictHandle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        let key = h.key().unwrap();
+        let strict = StrictHandle::new(h).unwrap();
+        assert_eq!(strict.key(), key);
+        assert_eq!(
+            strict,
+            StrictHandle::new(Handle::from_path(dir.join("a")).unwrap())
+                .unwrap()
+        );
+        assert_eq!(strict.into_handle().key(), Some(key));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+    #[test]
+    fn same_kernel_handle() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let h1 = Handle::from_path(dir.join("a")).unwrap();
+        let h2 = Handle::from_path(dir.join("a")).unwrap();
+        let h3 = Handle::from_path(dir.join("b")).unwrap();
+        let k1 = match h1.kernel_handle() {
+            Ok(k) => k,
+            // The temporary directory may be on a file system that doesn't
+            // support kernel handles, in which case there's nothing to test.
+            Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+                return
+            }
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(k1, h2.kernel_handle().unwrap());
+        assert_ne!(k1, h3.kernel_handle().unwrap());
+        assert!(h1.kernel_handle_eq(&h2).unwrap());
+        assert!(!h1.kernel_handle_eq(&h3).unwrap());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+    #[test]
+    fn kernel_handle_unsupported() {
+        use super::Handle;
+
+        // procfs can't be exported, so it has no kernel handles.
+        let h1 = Handle::from_path("/proc/self/status").unwrap();
+        let h2 = Handle::from_path("/proc/self/status").unwrap();
+        match h1.kernel_handle() {
+            Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => {}
+            _ => return,
+        }
+        assert!(h1.kernel_handle_eq(&h2).unwrap());
+    }
+
+    #[test]
+    fn canonical_survives_cwd_change() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = fs::canonicalize(tdir.path()).unwrap();
+
+        fs::create_dir(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("b")).unwrap();
+        File::create(dir.join("a").join("file")).unwrap();
+        File::create(dir.join("b").join("file")).unwrap();
+
+        let _lock = lock_cwd();
+        let cwd = env::current_dir().unwrap();
+        env::set_current_dir(dir.join("a")).unwrap();
+        let h1 = Handle::from_path_canonical("file");
+        env::set_current_dir(dir.join("b")).unwrap();
+        let h2 = h1
+            .as_ref()
+            .ok()
+            .and_then(|h| h.canonical_path())
+            .map(Handle::from_path_canonical);
+        env::set_current_dir(cwd).unwrap();
+
+        let (h1, h2) = (h1.unwrap(), h2.unwrap().unwrap());
+        assert_eq!(h1, h2);
+        assert_eq!(h1.canonical_path(), Some(&*dir.join("a").join("file")));
+        assert_eq!(h2.canonical_path(), h1.canonical_path());
+        assert_ne!(h1, Handle::from_path(dir.join("b").join("file")).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn path_beneath() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir_all(dir.join("root").join("sub")).unwrap();
+        File::create(dir.join("root").join("sub").join("file")).unwrap();
+        File::create(dir.join("outside")).unwrap();
+        soft_link_file(dir.join("outside"), dir.join("root").join("abs"))
+            .unwrap();
+        soft_link_file("../outside", dir.join("root").join("rel")).unwrap();
+
+        let root = Handle::from_dir_path(dir.join("root")).unwrap();
+        let h = match Handle::from_path_beneath(&root, "sub/file") {
+            Ok(h) => h,
+            // Kernels older than 5.6 don't have openat2.
+            Err(ref err) if err.raw_os_error() == Some(libc::ENOSYS) => return,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(
+            h,
+            Handle::from_path(dir.join("root").join("sub").join("file"))
+                .unwrap()
+        );
+        assert!(Handle::from_path_beneath(&root, "abs").is_err());
+        assert!(Handle::from_path_beneath(&root, "rel").is_err());
+        assert!(Handle::from_path_beneath(&root, "../outside").is_err());
+        assert!(Handle::from_path_beneath(&root, dir.join("outside")).is_err());
+    }
+
+    // Elsewhere, there are no handles to drop.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix, windows))]
+    #[test]
+    fn try_from_borrowed_file() {
+        use super::Handle;
+        use std::io::Write;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut file = File::create(dir.join("a")).unwrap();
+        let h1 = Handle::try_from(&file).unwrap();
+        // The original file is still usable after the handle was created...
+        file.write_all(b"foo").unwrap();
+        assert_eq!(h1, Handle::from_path(dir.join("a")).unwrap());
+        // ... and the handle outlives it.
+        let h2 = Handle::try_from(file).unwrap();
+        assert_eq!(h1, h2);
+        drop(h2);
+        assert_eq!(h1, Handle::from_path(dir.join("a")).unwrap());
+    }
+
+    // Symbolic links can only be created on Unix and Windows.
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn same_dir_entry() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("b")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let h = Handle::from_dir_entry(&entry).unwrap();
+            let snapshot = Handle::from_dir_entry_snapshot(&entry).unwrap();
+            let target = if entry.file_name() == "alink" {
+                dir.join("a")
+            } else {
+                entry.path()
+            };
+            assert!(!h.is_snapshot());
+            assert_eq!(h, Handle::from_path(&target).unwrap());
+            assert_eq!(snapshot, Handle::from_path(&target).unwrap());
+            // Symbolic links are opened to follow them.
+            assert_eq!(
+                snapshot.is_snapshot(),
+                cfg!(any(target_os = "redox", unix))
+                    && entry.file_name() != "alink"
+            );
+        }
+    }
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[test]
+    fn uring_keys_match_handles() {
+        use super::{keys_for_uring, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let mut paths = vec![dir.to_path_buf()];
+        for i in 0..20 {
+            let sub = dir.join(format!("dir{}", i));
+            fs::create_dir(&sub).unwrap();
+            paths.push(sub.clone());
+            for j in 0..20 {
+                let file = sub.join(format!("file{}", j));
+                File::create(&file).unwrap();
+                paths.push(file.clone());
+                if j % 5 == 0 {
+                    let link = sub.join(format!("hard{}", j));
+                    fs::hard_link(&file, &link).unwrap();
+                    paths.push(link);
+                    let link = sub.join(format!("soft{}", j));
+                    soft_link_file(&file, &link).unwrap();
+                    paths.push(link);
+                }
+            }
+            paths.push(sub.join("does-not-exist"));
+        }
+
+        let keys = keys_for_uring(&paths);
+        assert_eq!(keys.len(), paths.len());
+        for (path, key) in paths.iter().zip(keys) {
+            match Handle::from_path(path) {
+                Ok(h) => assert_eq!(key.unwrap(), h.key().unwrap()),
+                Err(err) => assert_eq!(key.unwrap_err().kind(), err.kind()),
+            }
+        }
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn null() {
+        use std::io::Read;
+
+        use super::{FileKind, Handle};
+
+        let mut null1 = Handle::null().unwrap();
+        let null2 = Handle::null().unwrap();
+        let mut buf = vec![];
+        null1.as_file_mut().read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(null1.kind(), FileKind::Other);
+        assert_eq!(null1, null1);
+        if cfg!(windows) {
+            assert!(null1.key().is_none());
+            assert_ne!(null1, null2);
+        } else {
+            assert_eq!(null1, null2);
+            assert_eq!(null1, Handle::from_path("/dev/null").unwrap());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn metadata_at_open() {
+        use std::io::Write;
+        use std::os::unix::fs::MetadataExt;
+
+        use super::unix::HandleExt;
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::write(dir.join("a"), "hello").unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        let md = h.metadata_at_open().unwrap().clone();
+        let fresh = fs::metadata(dir.join("a")).unwrap();
+        assert_eq!(md.len(), fresh.len());
+        assert_eq!(md.mode(), fresh.mode());
+        assert_eq!(md.uid(), fresh.uid());
+        assert_eq!(md.gid(), fresh.gid());
+        assert_eq!(md.nlink(), fresh.nlink());
+        assert_eq!(md.dev(), fresh.dev());
+        assert_eq!(md.ino(), fresh.ino());
+        assert_eq!(md.modified().unwrap(), fresh.modified().unwrap());
+
+        let entry = fs::read_dir(dir).unwrap().next().unwrap().unwrap();
+        let snapshot = Handle::from_dir_entry_snapshot(&entry).unwrap();
+        assert_eq!(snapshot.metadata_at_open().unwrap().len(), 5);
+
+        // The metadata is what it was at construction, not what it is now.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("a"))
+            .unwrap()
+            .write_all(b" world")
+            .unwrap();
+        assert_eq!(h.metadata_at_open().unwrap().len(), 5);
+        assert_eq!(h.as_file().metadata().unwrap().len(), 11);
+
+        assert!(Handle::dangling().metadata_at_open().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let _listener = UnixListener::bind(dir.join("sock")).unwrap();
+        File::create(dir.join("file")).unwrap();
+        let sock = Handle::from_path(dir.join("sock")).unwrap();
+        assert!(sock.key().is_some());
+        assert_eq!(sock, Handle::from_path(dir.join("sock")).unwrap());
+        assert!(is_same_file(dir.join("sock"), dir.join("sock")).unwrap());
+        assert!(!is_same_file(dir.join("sock"), dir.join("file")).unwrap());
+        assert_eq!(
+            sock.is_snapshot(),
+            !cfg!(any(target_os = "linux", target_os = "android"))
+        );
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn raw() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        assert_eq!(h.raw(), h.raw());
+        assert!(!format!("{:?}", h.raw()).is_empty());
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            assert_eq!(h.raw().as_raw_fd(), h.as_raw_fd());
+            assert_eq!(Handle::dangling().raw().as_raw_fd(), -1);
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::io::AsRawHandle;
+
+            assert_eq!(h.raw().as_raw_handle(), h.as_raw_handle());
+            assert!(Handle::dangling().raw().as_raw_handle().is_null());
+        }
+        let other = Handle::from_path(dir.join("a")).unwrap();
+        assert_eq!(h, other);
+        assert_ne!(h.raw(), other.raw());
+    }
+
+    // Elsewhere, there are no handles to drop.
+    #[cfg(any(target_os = "redox", target_os = "hermit", unix, windows))]
+    #[test]
+    fn same_clone() {
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let h1 = Handle::from_path(dir.join("a")).unwrap();
+        let h2 = h1.try_clone().unwrap();
+        let h3 = h1.try_clone_fresh().unwrap();
+        assert_eq!(h1, h2);
+        assert_eq!(h1, h3);
+        assert_eq!(h1.key(), h2.key());
+        drop(h1);
+        assert_eq!(h2, Handle::from_path(dir.join("a")).unwrap());
+        assert_eq!(h3, Handle::from_path(dir.join("a")).unwrap());
+    }
+
+    #[test]
+    fn current_dir_trivial() {
+        use super::Handle;
+
+        let _lock = lock_cwd();
+        let cwd = Handle::current_dir().unwrap();
+        assert_eq!(
+            cwd,
+            Handle::from_path(env::current_dir().unwrap()).unwrap()
+        );
+        assert!(cwd.as_file().metadata().unwrap().is_dir());
+    }
+
+    // Symbolic links can only be created on Unix and Windows.
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn require_directory() {
+        use super::HandleOptions;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("dir")).unwrap();
+        File::create(dir.join("file")).unwrap();
+        soft_link_dir(dir.join("dir"), dir.join("dirlink")).unwrap();
+
+        let mut opts = HandleOptions::new();
+        opts.require_directory(true);
+        let err = opts.open(dir.join("file")).unwrap_err();
+        #[cfg(unix)]
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+        #[cfg(windows)]
+        assert_eq!(err.raw_os_error(), Some(267));
+        assert!(opts.open(dir.join("dir")).is_ok());
+        assert!(opts
+            .is_same_file(dir.join("dir"), dir.join("dirlink"))
+            .unwrap());
+
+        opts.follow_symlinks(false);
+        assert!(opts.open(dir.join("dir")).is_ok());
+        assert!(opts.open(dir.join("dirlink")).is_err());
+        assert!(opts.open(dir.join("file")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn no_backup_semantics() {
+        use super::HandleOptions;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        fs::create_dir(dir.join("dir")).unwrap();
+        File::create(dir.join("file")).unwrap();
+
+        let mut opts = HandleOptions::new();
+        assert!(opts.open(dir.join("dir")).is_ok());
+
+        opts.backup_semantics(false);
+        assert!(opts.open(dir.join("file")).is_ok());
+        let err = opts.open(dir.join("dir")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    // Symbolic links can only be created on Unix and Windows.
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn no_follow_symlinks() {
+        use super::HandleOptions;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("file")).unwrap();
+        soft_link_file(dir.join("file"), dir.join("filelink")).unwrap();
+
+        let mut opts = HandleOptions::new();
+        opts.follow_symlinks(false);
+        assert!(opts.open(dir.join("file")).is_ok());
+        assert!(opts.open(dir.join("filelink")).is_err());
+    }
+
+    #[test]
+    fn same_kind() {
+        use super::{FileKind, Handle};
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("file")).unwrap();
+        fs::hard_link(dir.join("file"), dir.join("hard")).unwrap();
+        let file = Handle::from_path(dir.join("file")).unwrap();
+        let hard = Handle::from_path(dir.join("hard")).unwrap();
+        let d = Handle::from_path(dir).unwrap();
+        assert_eq!(file.kind(), FileKind::File);
+        assert_eq!(hard.kind(), FileKind::File);
+        assert_eq!(d.kind(), FileKind::Dir);
+        assert_eq!(file, hard);
+        assert_ne!(file, d);
     }
-}
 
-/// Returns true if the two file paths may correspond to the same file.
-///
-/// Note that it's possible for this to produce a false positive on some
-/// platforms. Namely, this can return true even if the two file paths *don't*
-/// resolve to the same file.
-/// # Errors
-/// This function will return an [`io::Error`] if any of the two paths cannot
-/// be opened. The most common reasons for this are: the path does not exist,
-/// or there were not enough permissions.
-///
-/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
-///
-/// # Example
-///
-/// ```rust,no_run
-/// use same_file::is_same_file;
-///
-/// assert!(is_same_file("./foo", "././foo").unwrap_or(false));
-/// ```
-pub fn is_same_file<P, Q>(path1: P, path2: Q) -> io::Result<bool>
-where
-    P: AsRef<Path>,
-    Q: AsRef<Path>,
-{
-    Ok(Handle::from_path(path1)? == Handle::from_path(path2)?)
-}
+    #[test]
+    fn borrowed_file_trivial() {
+        use super::Handle;
 
-#[cfg(test)]
-mod tests {
-    use std::env;
-    use std::error;
-    use std::fs::{self, File};
-    use std::io;
-    use std::path::{Path, PathBuf};
-    use std::result;
+        let tdir = tmpdir();
+        let dir = tdir.path();
 
-    use super::is_same_file;
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        assert!(h.as_borrowed_file().is_none());
+        assert!(Handle::stdin().unwrap().as_borrowed_file().is_some());
+    }
 
-    type Result<T> = result::Result<T, Box<dyn error::Error + Send + Sync>>;
+    // This runs itself in a child process, with stdin connected to a pipe.
+    #[cfg(all(
+        any(target_os = "redox", target_os = "hermit", unix, windows),
+        not(target_os = "emscripten")
+    ))]
+    #[test]
+    fn stdin_pipe_readable() {
+        use super::Handle;
+        use std::io::{Read, Write};
+        use std::process::{Command, Stdio};
 
-    /// Create an error from a format!-like syntax.
-    macro_rules! err {
-        ($($tt:tt)*) => {
-            Box::<dyn error::Error + Send + Sync>::from(format!($($tt)*))
+        const CHILD: &str = "SAME_FILE_TEST_STDIN_CHILD";
+
+        if env::var_os(CHILD).is_some() {
+            let stdin = Handle::stdin().unwrap();
+            let mut input = String::new();
+            stdin.as_file().read_to_string(&mut input).unwrap();
+            assert_eq!(input, "hello");
+            // The stream is still open after the handle is dropped.
+            drop(stdin);
+            assert!(Handle::stdin().is_ok());
+            return;
         }
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "tests::stdin_pipe_readable",
+                "--test-threads=1",
+            ])
+            .env(CHILD, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"hello").unwrap();
+        assert!(child.wait().unwrap().success());
     }
 
-    /// A simple wrapper for creating a temporary directory that is
-    /// automatically deleted when it's dropped.
-    ///
-    /// We use this in lieu of tempfile because tempfile brings in too many
-    /// dependencies.
-    #[derive(Debug)]
-    struct TempDir(PathBuf);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn proc_fd_deleted() {
+        use super::Handle;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
 
-    impl Drop for TempDir {
-        fn drop(&mut self) {
-            fs::remove_dir_all(&self.0).unwrap();
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        let file = File::create(dir.join("a")).unwrap();
+        let opath = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(dir.join("a"))
+            .unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        fs::remove_file(dir.join("a")).unwrap();
+        File::create(dir.join("a")).unwrap();
+
+        for fd in [file.as_raw_fd(), opath.as_raw_fd()] {
+            for p in [
+                format!("/proc/self/fd/{}", fd),
+                format!("/proc/{}/fd/{}", std::process::id(), fd),
+                format!("/dev/fd/{}", fd),
+            ] {
+                assert_eq!(Handle::from_path(&p).unwrap(), h);
+                assert!(!is_same_file(&p, dir.join("a")).unwrap());
+            }
         }
     }
 
-    impl TempDir {
-        /// Create a new empty temporary directory under the system's
-        /// configured temporary directory.
-        fn new() -> Result<TempDir> {
-            #![allow(deprecated)]
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "illumos",
+        target_os = "solaris",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    #[test]
+    fn dev_fd_pipe() {
+        use std::os::unix::io::{AsRawFd, FromRawFd};
 
-            use std::sync::atomic::{
-                AtomicUsize, Ordering, ATOMIC_USIZE_INIT,
-            };
+        use super::Handle;
 
-            static TRIES: usize = 100;
-            static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (r, w) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let flags = unsafe { libc::fcntl(r.as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(
+            unsafe {
+                libc::fcntl(
+                    r.as_raw_fd(),
+                    libc::F_SETFL,
+                    flags | libc::O_NONBLOCK,
+                )
+            },
+            0
+        );
 
-            let tmpdir = env::temp_dir();
-            for _ in 0..TRIES {
-                let count = COUNTER.fetch_add(1, Ordering::SeqCst);
-                let path = tmpdir.join("rust-walkdir").join(count.to_string());
-                if path.is_dir() {
-                    continue;
-                }
-                fs::create_dir_all(&path).map_err(|e| {
-                    err!("failed to create {}: {}", path.display(), e)
-                })?;
-                return Ok(TempDir(path));
+        let rpath = format!("/dev/fd/{}", r.as_raw_fd());
+        let wpath = format!("/dev/fd/{}", w.as_raw_fd());
+        // The write end isn't open for reading, which must not matter.
+        let hw = Handle::from_path(&wpath).unwrap();
+        assert_eq!(hw, Handle::from_as_fd(&w).unwrap());
+        let hr = Handle::from_path(&rpath).unwrap();
+        assert_eq!(hr, Handle::from_as_fd(&r).unwrap());
+        assert!(is_same_file(&rpath, &rpath).unwrap());
+        // The original descriptor is left alone.
+        let after = unsafe { libc::fcntl(r.as_raw_fd(), libc::F_GETFL) };
+        assert_ne!(after & libc::O_NONBLOCK, 0);
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "illumos",
+        target_os = "solaris"
+    ))]
+    #[test]
+    fn proc_fd_closed() {
+        // Descriptors are allocated lowest first, so this one is never open.
+        let p = format!("/proc/self/fd/{}", i32::MAX);
+        let err = is_same_file(&p, "/").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    }
+
+    // This runs itself in a child process, with stdin redirected from a file.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "illumos",
+        target_os = "solaris"
+    ))]
+    #[test]
+    fn proc_fd_redirected_stdin() {
+        use std::process::{Command, Stdio};
+
+        const CHILD: &str = "SAME_FILE_TEST_PROC_FD_CHILD";
+
+        if let Some(path) = env::var_os(CHILD) {
+            for p in ["/dev/stdin", "/dev/fd/0", "/proc/self/fd/0"] {
+                assert!(is_same_file(p, &path).unwrap());
+                assert!(!is_same_file(p, "/dev/stdout").unwrap());
             }
-            Err(err!("failed to create temp dir after {} tries", TRIES))
+            return;
         }
+        let tdir = tmpdir();
+        let dir = tdir.path();
+        File::create(dir.join("a")).unwrap();
 
-        /// Return the underlying path to this temporary directory.
-        fn path(&self) -> &Path {
-            &self.0
-        }
+        let status = Command::new(env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "tests::proc_fd_redirected_stdin",
+                "--test-threads=1",
+            ])
+            .env(CHILD, dir.join("a"))
+            .stdin(File::open(dir.join("a")).unwrap())
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
     }
 
-    fn tmpdir() -> TempDir {
-        TempDir::new().unwrap()
-    }
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos",
+        windows
+    ))]
+    #[test]
+    fn reopen_write_read() {
+        use super::Handle;
+        use std::fs::OpenOptions;
+        use std::io::{Read, Write};
 
-    #[cfg(unix)]
-    pub fn soft_link_dir<P: AsRef<Path>, Q: AsRef<Path>>(
-        src: P,
-        dst: Q,
-    ) -> io::Result<()> {
-        use std::os::unix::fs::symlink;
-        symlink(src, dst)
-    }
+        let tdir = tmpdir();
+        let dir = tdir.path();
 
-    #[cfg(unix)]
-    pub fn soft_link_file<P: AsRef<Path>, Q: AsRef<Path>>(
-        src: P,
-        dst: Q,
-    ) -> io::Result<()> {
-        soft_link_dir(src, dst)
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        fs::rename(dir.join("a"), dir.join("b")).unwrap();
+        let mut file = h.reopen(OpenOptions::new().write(true)).unwrap();
+        file.write_all(b"foo").unwrap();
+        drop(file);
+
+        let mut contents = String::new();
+        h.as_file().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "foo");
     }
 
-    #[cfg(windows)]
-    pub fn soft_link_dir<P: AsRef<Path>, Q: AsRef<Path>>(
-        src: P,
-        dst: Q,
-    ) -> io::Result<()> {
-        use std::os::windows::fs::symlink_dir;
-        symlink_dir(src, dst)
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_events() {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::span;
+        use tracing::{Event, Metadata};
+
+        use super::Handle;
+
+        // Records the target of every event.
+        struct Targets(Arc<Mutex<Vec<String>>>);
+
+        impl tracing::Subscriber for Targets {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let target = event.metadata().target().to_string();
+                self.0.lock().unwrap().push(target);
+            }
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+        File::create(dir.join("a")).unwrap();
+
+        let targets = Arc::new(Mutex::new(vec![]));
+        let subscriber = Targets(targets.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let h1 = Handle::from_path(dir.join("a")).unwrap();
+            let h2 = Handle::from_path(dir.join("a")).unwrap();
+            assert!(h1 == h2);
+            // Comparisons with handles that have no identity count, too.
+            assert!(h1 != Handle::dangling());
+            assert!(Handle::from_path(dir.join("nope")).is_err());
+        });
+
+        let targets = targets.lock().unwrap();
+        let count =
+            |target: &str| targets.iter().filter(|t| *t == target).count();
+        assert_eq!(count("same_file::open"), 3);
+        assert_eq!(count("same_file::information"), 2);
+        assert_eq!(count("same_file::compare"), 2);
     }
 
-    #[cfg(windows)]
-    pub fn soft_link_file<P: AsRef<Path>, Q: AsRef<Path>>(
-        src: P,
-        dst: Q,
-    ) -> io::Result<()> {
-        use std::os::windows::fs::symlink_file;
-        symlink_file(src, dst)
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    #[test]
+    fn solarish_fs_type() {
+        use std::os::unix::fs::MetadataExt;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
+
+        File::create(dir.join("a")).unwrap();
+        let h = Handle::from_path(dir.join("a")).unwrap();
+        let md = fs::metadata(dir.join("a")).unwrap();
+        assert_eq!(h.dev(), md.dev());
+        assert_eq!(h.ino(), md.ino());
+        assert!(!h.fs_type().unwrap().is_empty());
+
+        let proc = Handle::from_path("/proc").unwrap();
+        assert_eq!(proc.fs_type().unwrap(), "proc");
     }
 
-    // These tests are rather uninteresting. The really interesting tests
-    // would stress the edge cases. On Unix, this might be comparing two files
-    // on different mount points with the same inode number. On Windows, this
-    // might be comparing two files whose file indices are the same on file
-    // systems where such things aren't guaranteed to be unique.
-    //
-    // Alas, I don't know how to create those environmental conditions. ---AG
+    #[cfg(any(target_os = "redox", unix))]
+    #[test]
+    fn dev_ino_metadata() {
+        use std::os::unix::fs::MetadataExt;
+
+        use super::Handle;
+
+        let tdir = tmpdir();
+        let dir = tdir.path();
 
+        File::create(dir.join("a")).unwrap();
+        for p in [dir.join("a"), dir.to_path_buf()] {
+            let h = Handle::from_path(&p).unwrap();
+            let md = fs::metadata(&p).unwrap();
+            assert_eq!(h.dev(), md.dev());
+            assert_eq!(h.ino(), md.ino());
+        }
+    }
+
+    // This needs a ZFS dataset that we're allowed to snapshot, so it passes
+    // trivially everywhere else.
+    #[cfg(target_os = "illumos")]
     #[test]
-    fn same_file_trivial() {
+    fn zfs_snapshot() {
+        use std::process::Command;
+
+        use super::Handle;
+
+        fn zfs(args: &[&str]) -> Option<String> {
+            let out = Command::new("zfs").args(args).output().ok()?;
+            if !out.status.success() {
+                return None;
+            }
+            Some(String::from_utf8(out.stdout).ok()?.trim().to_string())
+        }
+
+        // Destroys the snapshot when dropped, even if an assertion fails.
+        struct Snapshot(String);
+
+        impl Drop for Snapshot {
+            fn drop(&mut self) {
+                zfs(&["destroy", &self.0]);
+            }
+        }
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
         File::create(dir.join("a")).unwrap();
-        assert!(is_same_file(dir.join("a"), dir.join("a")).unwrap());
+        let live = Handle::from_path(dir.join("a")).unwrap();
+        if live.fs_type().unwrap() != "zfs" {
+            return;
+        }
+        let dir_str = dir.to_str().unwrap();
+        let dataset = match zfs(&["list", "-H", "-o", "name", dir_str]) {
+            Some(dataset) => dataset,
+            None => return,
+        };
+        let mountpoint =
+            zfs(&["list", "-H", "-o", "mountpoint", dir_str]).unwrap();
+        let name = format!("same-file-test-{}", std::process::id());
+        let snapshot = format!("{}@{}", dataset, name);
+        if zfs(&["snapshot", &snapshot]).is_none() {
+            return;
+        }
+        let _snapshot = Snapshot(snapshot);
+
+        let rel = dir.join("a");
+        let rel = rel.strip_prefix(&mountpoint).unwrap();
+        let snap_path = Path::new(&mountpoint)
+            .join(".zfs")
+            .join("snapshot")
+            .join(&name)
+            .join(rel);
+        // This is dropped before the snapshot, which can't be destroyed
+        // while it's in use.
+        let snap = Handle::from_path(snap_path).unwrap();
+        assert_ne!(live, snap);
+        assert!(live.same_inode(&snap));
+        assert_eq!(snap.fs_type().unwrap(), "zfs");
     }
 
+    #[cfg(target_os = "nto")]
     #[test]
-    fn same_dir_trivial() {
+    fn qnx_resource_managers() {
+        use super::Handle;
+
+        let null1 = Handle::from_path("/dev/null").unwrap();
+        let null2 = Handle::from_path("/dev/null").unwrap();
+        let zero = Handle::from_path("/dev/zero").unwrap();
+        assert_eq!(null1, null1);
+        assert_eq!(null1 == null2, null1.key().is_some());
+        assert_ne!(null1, zero);
+    }
+
+    #[cfg(target_os = "haiku")]
+    #[test]
+    fn haiku_system_dirs() {
+        use super::Handle;
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
+        // Directories on BFS are opened like any other directory, and their
+        // attribute directories are out of reach of ordinary paths.
         fs::create_dir(dir.join("a")).unwrap();
-        assert!(is_same_file(dir.join("a"), dir.join("a")).unwrap());
+        soft_link_dir(dir.join("a"), dir.join("alink")).unwrap();
+        soft_link_dir("..", dir.join("a").join("up")).unwrap();
+        let a = Handle::from_dir_path(dir.join("a")).unwrap();
+        assert_eq!(a, Handle::from_path(dir.join("alink")).unwrap());
+        assert_eq!(
+            Handle::from_path(dir).unwrap(),
+            Handle::from_path(dir.join("a").join("up")).unwrap()
+        );
+        assert!(is_same_file("/boot/system", "/boot/system/.").unwrap());
+        // Whatever the stdio streams are connected to, this must not panic.
+        let _ = Handle::stdin();
+        let _ = Handle::stdout();
+        let _ = Handle::stderr();
     }
 
+    // Emscripten can't run most of the tests, which rely on hard links or
+    // child processes, so CI only runs this one there.
+    #[cfg(target_os = "emscripten")]
     #[test]
-    fn not_same_file_trivial() {
+    fn emscripten_trivial() {
+        use super::Handle;
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
         File::create(dir.join("a")).unwrap();
         File::create(dir.join("b")).unwrap();
+        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
+        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
         assert!(!is_same_file(dir.join("a"), dir.join("b")).unwrap());
+        assert!(Handle::from_path(dir.join("a")).unwrap().key().is_some());
+        // The stdio streams are emulated, but must still work.
+        let _ = (Handle::stdin(), Handle::stdout(), Handle::stderr());
     }
 
+    #[cfg(target_os = "espidf")]
     #[test]
-    fn not_same_dir_trivial() {
+    fn espidf_trivial() {
+        use super::Handle;
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
-        fs::create_dir(dir.join("a")).unwrap();
-        fs::create_dir(dir.join("b")).unwrap();
-        assert!(!is_same_file(dir.join("a"), dir.join("b")).unwrap());
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a1, a1);
+        assert_eq!(a1 == a2, a1.key().is_some());
+        assert_ne!(a1, b);
+        assert!(Handle::from_dir_path(dir.join("a")).is_err());
+        // There may be no console at all, which must not panic.
+        let _ = (Handle::stdin(), Handle::stdout(), Handle::stderr());
     }
 
+    #[cfg(target_os = "vxworks")]
     #[test]
-    fn same_file_hard() {
+    fn vxworks_trivial() {
+        use super::Handle;
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
         File::create(dir.join("a")).unwrap();
-        fs::hard_link(dir.join("a"), dir.join("alink")).unwrap();
-        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
+        File::create(dir.join("b")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert_eq!(a1, a1);
+        assert_eq!(a1 == a2, a1.key().is_some());
+        assert_ne!(a1, b);
+        assert!(a1.fs_id().is_err());
+        // Targets without a console have no stdio descriptors at all, which
+        // must be an error rather than a panic.
+        let _ = (Handle::stdin(), Handle::stdout(), Handle::stderr());
     }
 
+    #[cfg(target_os = "redox")]
     #[test]
-    fn same_file_soft() {
+    fn redox_trivial() {
+        use super::Handle;
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
         File::create(dir.join("a")).unwrap();
-        soft_link_file(dir.join("a"), dir.join("alink")).unwrap();
-        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
+        File::create(dir.join("b")).unwrap();
+        let a = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        let file = File::open(dir.join("a")).unwrap();
+        assert_eq!(a, Handle::from_file(file).unwrap());
+        assert_ne!(a, b);
+        let _ = (Handle::stdin(), Handle::stdout(), Handle::stderr());
     }
 
+    #[cfg(target_os = "fuchsia")]
     #[test]
-    fn same_dir_soft() {
+    fn fuchsia_trivial() {
+        use super::Handle;
+
         let tdir = tmpdir();
         let dir = tdir.path();
 
-        fs::create_dir(dir.join("a")).unwrap();
-        soft_link_dir(dir.join("a"), dir.join("alink")).unwrap();
-        assert!(is_same_file(dir.join("a"), dir.join("alink")).unwrap());
+        File::create(dir.join("a")).unwrap();
+        File::create(dir.join("b")).unwrap();
+        let a1 = Handle::from_path(dir.join("a")).unwrap();
+        let a2 = Handle::from_path(dir.join("a")).unwrap();
+        let b = Handle::from_path(dir.join("b")).unwrap();
+        assert!(a1.key().is_some());
+        assert_eq!(a1, a2);
+        assert_ne!(a1, b);
+        assert!(is_same_file(dir.join("a"), dir.join("a")).unwrap());
+        assert!(!is_same_file(dir.join("a"), dir.join("b")).unwrap());
+        // Components may run without any stdio at all, which must be an
+        // error rather than a panic.
+        let _ = (Handle::stdin(), Handle::stdout(), Handle::stderr());
+    }
+
+    // Synthetic keys, since file systems without inode numbers can't be
+    // conjured up on demand.
+    #[cfg(any(target_os = "redox", unix))]
+    #[test]
+    fn key_zero_inode() {
+        use super::imp::Key;
+
+        let keyless = cfg!(any(
+            target_os = "nto",
+            target_os = "emscripten",
+            target_os = "espidf",
+            target_os = "vxworks"
+        ));
+        assert_eq!(Key::new(1, 0).is_known(), !keyless);
+        assert!(Key::new(1, 1).is_known());
+    }
+
+    // Synthetic keys, since ReFS volumes can't be conjured up on demand.
+    #[cfg(windows)]
+    #[test]
+    fn key_refs_index() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        use std::hash::{Hash, Hasher};
+
+        use super::imp::Key;
+        use super::FileKey;
+
+        fn hash(key: &FileKey) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let high = 1u128 << 64;
+        let keys = [
+            FileKey(Key::new(1, 5), None),
+            FileKey(Key::new(1, high | 5), None),
+            FileKey(Key::new(1, (high << 63) | 5), None),
+            FileKey(Key::new(1, u128::MAX), None),
+            FileKey(Key::new(1, u128::from(u64::MAX)), None),
+            FileKey(Key::new(2, 5), None),
+        ];
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(hash(&keys[0]), hash(&keys[1]));
+        assert_ne!(hash(&keys[0]), hash(&keys[2]));
+        assert_ne!(hash(&keys[3]), hash(&keys[4]));
+        let hashes: HashSet<u64> = keys.iter().map(hash).collect();
+        assert_eq!(hashes.len(), keys.len());
+        let set: HashSet<FileKey> = keys.iter().cloned().collect();
+        assert_eq!(set.len(), keys.len());
+        assert!(keys[0] < keys[1] && keys[1] < keys[2]);
+    }
+
+    // Synthetic keys, since large inode numbers can't be conjured up on
+    // demand.
+    #[cfg(any(target_os = "redox", unix))]
+    #[test]
+    fn key_large_inode() {
+        use std::collections::HashSet;
+
+        use super::imp::Key;
+        use super::FileKey;
+
+        let big = u64::from(u32::MAX) + 1;
+        let keys = [
+            FileKey(Key::new(1, 0), None),
+            FileKey(Key::new(1, big), None),
+            FileKey(Key::new(1, big + 5), None),
+            FileKey(Key::new(1, 5), None),
+            FileKey(Key::new(1, u64::MAX), None),
+            FileKey(Key::new(1, u64::from(u32::MAX)), None),
+            FileKey(Key::new(big, 5), None),
+        ];
+        let set: HashSet<FileKey> = keys.iter().cloned().collect();
+        assert_eq!(set.len(), keys.len());
+        assert!(keys[0] < keys[1] && keys[1] < keys[2]);
+        assert!(keys[5] < keys[1]);
     }
 
     #[test]
@@ -569,4 +7992,31 @@ mod tests {
         fn assert_sync<T: Sync>() {}
         assert_sync::<super::Handle>();
     }
+
+    #[test]
+    fn test_send_sync() {
+        use super::{
+            Comparison, ComparisonOptions, Error, ErrorKind, FileKey,
+            FileKind, Handle, HandleOptions, StrictHandle,
+        };
+
+        fn _assert_send_sync<T: Send + Sync>() {}
+        _assert_send_sync::<Handle>();
+        _assert_send_sync::<StrictHandle>();
+        _assert_send_sync::<FileKey>();
+        _assert_send_sync::<super::RawDescriptor>();
+        _assert_send_sync::<FileKind>();
+        _assert_send_sync::<Comparison>();
+        _assert_send_sync::<Error>();
+        _assert_send_sync::<ErrorKind>();
+        _assert_send_sync::<HandleOptions>();
+        _assert_send_sync::<ComparisonOptions>();
+        #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+        _assert_send_sync::<super::KernelHandle>();
+
+        // Handles that borrow stdio are moved between threads too.
+        let stdout = Handle::stdout().unwrap();
+        let key = std::thread::spawn(move || stdout.key()).join().unwrap();
+        assert_eq!(key, Handle::stdout().unwrap().key());
+    }
 }
@@ -0,0 +1,61 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use rayon::prelude::*;
+
+use crate::{FileKey, Handle};
+
+/// The number of independently locked maps that keys are merged into.
+///
+/// Each key always goes to the same shard, so threads only contend when
+/// their keys happen to hash to the same one.
+const SHARDS: usize = 64;
+
+pub fn group<P: AsRef<Path> + Sync>(
+    paths: &[P],
+) -> io::Result<Vec<Vec<PathBuf>>> {
+    let shards: Vec<Mutex<HashMap<FileKey, Vec<usize>>>> =
+        (0..SHARDS).map(|_| Mutex::default()).collect();
+    let keyless = Mutex::new(vec![]);
+    paths.par_iter().enumerate().try_for_each(|(i, p)| -> io::Result<()> {
+        match Handle::from_path(p)?.key() {
+            Some(key) => {
+                let mut shard = shards[shard(&key)].lock().unwrap();
+                shard.entry(key).or_default().push(i);
+            }
+            None => keyless.lock().unwrap().push(i),
+        }
+        Ok(())
+    })?;
+
+    // Groups are made of indices into `paths` so far. Sorting them puts
+    // everything back in the order the sequential version produces.
+    let mut groups: Vec<Vec<usize>> =
+        keyless.into_inner().unwrap().into_iter().map(|i| vec![i]).collect();
+    for shard in shards {
+        groups.extend(shard.into_inner().unwrap().into_values());
+    }
+    for group in &mut groups {
+        group.sort_unstable();
+    }
+    groups.sort_unstable_by_key(|group| group[0]);
+    Ok(groups
+        .into_iter()
+        .map(|group| {
+            group
+                .into_iter()
+                .map(|i| paths[i].as_ref().to_path_buf())
+                .collect()
+        })
+        .collect())
+}
+
+fn shard(key: &FileKey) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % SHARDS as u64) as usize
+}
@@ -0,0 +1,45 @@
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+
+use crate::{imp, FileKey};
+
+/// The representation of a `FileKey`, which is the same on every platform.
+///
+/// The index is always 128 bits wide, so that keys written on Unix (or
+/// before file indexes were widened on Windows) can still be read once they
+/// aren't. The generation number is optional when deserializing from
+/// self-describing formats, since it is unknown on most platforms anyway.
+/// This is a stable format: changing it breaks persisted keys.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename = "FileKey")]
+struct Repr {
+    volume: u64,
+    index: u128,
+    #[serde(default)]
+    generation: Option<u64>,
+}
+
+impl Serialize for FileKey {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let (volume, index) = self.0.parts();
+        Repr { volume, index, generation: self.1 }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FileKey {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FileKey, D::Error> {
+        let Repr { volume, index, generation } =
+            Repr::deserialize(deserializer)?;
+        match imp::Key::from_parts(volume, index) {
+            Some(key) => Ok(FileKey(key, generation)),
+            None => {
+                Err(D::Error::custom("file key is not valid on this platform"))
+            }
+        }
+    }
+}
@@ -1,26 +1,245 @@
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::hash::{Hash, Hasher};
 use std::io;
-use std::os::unix::fs::MetadataExt;
-use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
-use std::path::Path;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsFd, AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[cfg(not(target_os = "espidf"))]
+use self::c::{O_DIRECTORY, O_NOFOLLOW};
+
+// The constants that std doesn't have come from the same backend as the
+// system calls. With libc, that's all of libc. With rustix, only the ones
+// this crate uses are spelled out, with the same names and types as in
+// libc.
+#[cfg(not(feature = "backend-rustix"))]
+pub(crate) use libc as c;
+
+#[cfg(feature = "backend-rustix")]
+#[allow(dead_code)]
+pub(crate) mod c {
+    use rustix::fs::OFlags;
+    use rustix::io::Errno;
+
+    pub const EACCES: i32 = Errno::ACCESS.raw_os_error();
+    pub const EAGAIN: i32 = Errno::AGAIN.raw_os_error();
+    pub const EINTR: i32 = Errno::INTR.raw_os_error();
+    pub const EINVAL: i32 = Errno::INVAL.raw_os_error();
+    pub const ELOOP: i32 = Errno::LOOP.raw_os_error();
+    pub const EMFILE: i32 = Errno::MFILE.raw_os_error();
+    pub const ENFILE: i32 = Errno::NFILE.raw_os_error();
+    pub const ENOENT: i32 = Errno::NOENT.raw_os_error();
+    pub const ENOSYS: i32 = Errno::NOSYS.raw_os_error();
+    pub const ENOTDIR: i32 = Errno::NOTDIR.raw_os_error();
+    pub const ENXIO: i32 = Errno::NXIO.raw_os_error();
+    pub const EOPNOTSUPP: i32 = Errno::OPNOTSUPP.raw_os_error();
+    pub const EOVERFLOW: i32 = Errno::OVERFLOW.raw_os_error();
+    pub const EPERM: i32 = Errno::PERM.raw_os_error();
+
+    pub const O_CLOEXEC: i32 = OFlags::CLOEXEC.bits() as i32;
+    #[cfg(not(target_os = "espidf"))]
+    pub const O_DIRECTORY: i32 = OFlags::DIRECTORY.bits() as i32;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const O_NOATIME: i32 = OFlags::NOATIME.bits() as i32;
+    #[cfg(not(any(
+        target_os = "espidf",
+        target_os = "redox",
+        target_os = "vxworks"
+    )))]
+    pub const O_NOCTTY: i32 = OFlags::NOCTTY.bits() as i32;
+    // rustix doesn't have it for Redox, so this is relibc's value.
+    #[cfg(target_os = "redox")]
+    pub const O_NOCTTY: i32 = 0x200;
+    #[cfg(not(target_os = "espidf"))]
+    pub const O_NOFOLLOW: i32 = OFlags::NOFOLLOW.bits() as i32;
+    pub const O_NONBLOCK: i32 = OFlags::NONBLOCK.bits() as i32;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const O_PATH: i32 = OFlags::PATH.bits() as i32;
+    pub const O_RDONLY: i32 = OFlags::RDONLY.bits() as i32;
+
+    #[cfg(target_os = "linux")]
+    pub const AT_EMPTY_PATH: i32 =
+        rustix::fs::AtFlags::EMPTY_PATH.bits() as i32;
+    #[cfg(target_os = "linux")]
+    pub const RESOLVE_BENEATH: u64 = rustix::fs::ResolveFlags::BENEATH.bits();
+    #[cfg(target_os = "linux")]
+    pub const RESOLVE_NO_MAGICLINKS: u64 =
+        rustix::fs::ResolveFlags::NO_MAGICLINKS.bits();
+
+    #[cfg(target_os = "linux")]
+    pub const S_IFMT: u32 = 0o170000;
+    #[cfg(target_os = "linux")]
+    pub const S_IFBLK: u32 = rustix::fs::FileType::BlockDevice.as_raw_mode();
+    #[cfg(target_os = "linux")]
+    pub const S_IFCHR: u32 =
+        rustix::fs::FileType::CharacterDevice.as_raw_mode();
+    #[cfg(target_os = "linux")]
+    pub const S_IFDIR: u32 = rustix::fs::FileType::Directory.as_raw_mode();
+    #[cfg(target_os = "linux")]
+    pub const S_IFLNK: u32 = rustix::fs::FileType::Symlink.as_raw_mode();
+    #[cfg(target_os = "linux")]
+    pub const S_IFREG: u32 = rustix::fs::FileType::RegularFile.as_raw_mode();
+
+    // From linux/magic.h, with the type libc gives them on glibc. See
+    // `fs_magic` for how they're compared with `f_type`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const BTRFS_SUPER_MAGIC: std::os::raw::c_long =
+        0x9123_683E_u32 as std::os::raw::c_long;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const FUSE_SUPER_MAGIC: std::os::raw::c_long = 0x6573_5546;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const NFS_SUPER_MAGIC: std::os::raw::c_long = 0x6969;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const OVERLAYFS_SUPER_MAGIC: std::os::raw::c_long = 0x794C_7630;
+
+    #[cfg(target_os = "linux")]
+    pub fn makedev(major: u32, minor: u32) -> u64 {
+        rustix::fs::makedev(major, minor)
+    }
+}
+
+// ESP-IDF's VFS layer has none of these flags. None of its file systems have
+// symbolic links or controlling terminals, so `O_NOFOLLOW` and `O_NOCTTY`
+// have nothing to do anyway. `O_DIRECTORY` is checked against the type of
+// the opened file instead (see `require_dir`).
+#[cfg(target_os = "espidf")]
+const O_DIRECTORY: i32 = 0;
+#[cfg(target_os = "espidf")]
+const O_NOFOLLOW: i32 = 0;
+// VxWorks doesn't have controlling terminals either.
+#[cfg(any(target_os = "espidf", target_os = "vxworks"))]
+const O_NOCTTY: i32 = 0;
+
+pub(crate) type RawDescriptor = RawFd;
+
+// The device number of dangling handles. No actual file is ever on `NODEV`.
+const NODEV: u64 = u64::MAX;
 
 #[derive(Debug)]
-pub struct Handle {
+pub(crate) struct Handle {
     file: Option<File>,
     // If is_std is true, then we don't drop the corresponding File since it
     // will close the handle.
     is_std: bool,
+    key: Key,
+    // The inode generation number, when the platform and file system expose
+    // one. This is deliberately not part of equality, since both handles
+    // being open already rules out inode reuse.
+    generation: Option<u64>,
+    // The modification time when the handle was constructed.
+    modified: Option<SystemTime>,
+    // The device number of a device node.
+    rdev: Option<u64>,
+    // The canonical path the handle was opened with, if any.
+    path: Option<PathBuf>,
+    // The type of the file, which lets comparisons between a directory and
+    // anything else bail early.
+    kind: crate::FileKind,
+    // Everything `fstat` said when the handle was constructed, which is
+    // handed out by `HandleExt::metadata_at_open`. It's boxed since it is
+    // several times larger than everything else put together.
+    metadata: Option<Box<fs::Metadata>>,
+    // What a snapshot's file system is, since it has no descriptor to ask
+    // later. Handles with a descriptor query this on demand instead. It's
+    // boxed for the same reason as `metadata`.
+    fs: Option<Box<FsInfo>>,
+}
+
+/// What `statvfs` and `statfs` said about the file system of a snapshot.
+#[derive(Clone, Debug)]
+struct FsInfo {
+    id: Option<u64>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    magic: Option<u64>,
+}
+
+// Device and inode numbers are always stored with 64 bits, even on 32-bit
+// targets. std's `Metadata` always comes from the 64-bit variants of `stat`
+// (`statx`, `stat64` and friends, regardless of `_FILE_OFFSET_BITS`), so
+// large inode numbers (e.g., on XFS, NFS or AIX's JFS2) are never
+// truncated. Any code that calls into libc directly to get at them must do
+// the same.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialOrd)]
+pub(crate) struct Key {
     dev: u64,
     ino: u64,
 }
 
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        // Files on different devices are never equal, and comparing devices
+        // first lets us bail early when comparing across devices.
+        if self.dev != other.dev {
+            return false;
+        }
+        self.ino == other.ino
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.dev.hash(state);
+        self.ino.hash(state);
+    }
+}
+
+impl Key {
+    pub(crate) fn new(dev: u64, ino: u64) -> Key {
+        Key { dev, ino }
+    }
+
+    pub(crate) fn from_metadata(md: &fs::Metadata) -> Key {
+        Key::new(md.dev(), md.ino())
+    }
+
+    pub fn parts(&self) -> (u64, u128) {
+        (self.dev, u128::from(self.ino))
+    }
+
+    /// The inverse of `parts`, which fails if the index doesn't fit in an
+    /// inode number.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(volume: u64, index: u128) -> Option<Key> {
+        u64::try_from(index).ok().map(|ino| Key::new(volume, ino))
+    }
+
+    /// Returns false if this key doesn't identify a file.
+    ///
+    /// This is always the case for dangling handles. On QNX, resource
+    /// managers that don't assign inode numbers report zero for every file
+    /// they serve, which would make them all equal. On Fuchsia, servers that
+    /// don't know the inode number of a node (e.g., for pipes, sockets or
+    /// some pseudo-files) report `INO_UNKNOWN`. On Emscripten, inode numbers
+    /// are synthetic, and zero is never assigned to a real node. On ESP-IDF,
+    /// file systems without inode numbers (e.g., SPIFFS, or FAT for some
+    /// entries) report zero, and so does dosFs on VxWorks.
+    pub(crate) fn is_known(&self) -> bool {
+        if self.dev == NODEV {
+            false
+        } else if cfg!(any(
+            target_os = "nto",
+            target_os = "emscripten",
+            target_os = "espidf",
+            target_os = "vxworks"
+        )) {
+            self.ino != 0
+        } else if cfg!(target_os = "fuchsia") {
+            self.ino != u64::MAX
+        } else {
+            true
+        }
+    }
+}
+
 impl Drop for Handle {
     fn drop(&mut self) {
         if self.is_std {
-            // unwrap() will not panic. Since we were able to open an
-            // std stream successfully, then `file` is guaranteed to be Some()
-            self.file.take().unwrap().into_raw_fd();
+            // `file` is None if it was already given away, e.g., by
+            // `into_raw_fd`.
+            if let Some(file) = self.file.take() {
+                let _ = file.into_raw_fd();
+            }
         }
     }
 }
@@ -29,48 +248,518 @@ impl Eq for Handle {}
 
 impl PartialEq for Handle {
     fn eq(&self, other: &Handle) -> bool {
-        (self.dev, self.ino) == (other.dev, other.ino)
+        // Need this branch to satisfy `Eq` for handles without an identity.
+        if !self.key.is_known() || !other.key.is_known() {
+            let equal = std::ptr::eq(self, other);
+            event!(
+                target: "same_file::compare",
+                tracing::Level::TRACE,
+                equal,
+                keyless = true,
+            );
+            return equal;
+        }
+        let equal = self.kind == other.kind && self.key == other.key;
+        event!(target: "same_file::compare", tracing::Level::TRACE, equal);
+        equal
     }
 }
 
+/// This panics if the handle has no open file, like `Handle::as_file`.
 impl AsRawFd for crate::Handle {
     fn as_raw_fd(&self) -> RawFd {
-        // unwrap() will not panic. Since we were able to open the
-        // file successfully, then `file` is guaranteed to be Some()
-        self.0.file.as_ref().take().unwrap().as_raw_fd()
+        self.0.as_file().as_raw_fd()
+    }
+}
+
+impl AsRawFd for crate::RawDescriptor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
     }
 }
 
+/// This panics if the handle has no open file, like `Handle::as_file`.
 impl IntoRawFd for crate::Handle {
     fn into_raw_fd(mut self) -> RawFd {
-        // unwrap() will not panic. Since we were able to open the
-        // file successfully, then `file` is guaranteed to be Some()
-        self.0.file.take().unwrap().into_raw_fd()
+        // This only panics for snapshot and dangling handles, which is
+        // documented.
+        self.0.file.take().expect("handle has no file").into_raw_fd()
+    }
+}
+
+/// Unix-specific extensions to [`Handle`].
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+///
+/// [`Handle`]: ../struct.Handle.html
+pub trait HandleExt: sealed::Sealed {
+    /// Return the metadata of the file as it was when the handle was
+    /// constructed.
+    ///
+    /// Constructing a handle already calls `fstat` (or `fstatat`, for
+    /// [`Handle::from_dir_entry_snapshot`]) to learn the file's identity, and
+    /// this hands out everything else it returned: the size, mode, owner,
+    /// link count, timestamps and so on, through
+    /// [`std::os::unix::fs::MetadataExt`]. When scanning many files, this
+    /// saves a second system call per file.
+    ///
+    /// The metadata is never refreshed, so it may be stale by the time it
+    /// is looked at, e.g., if the file was written to since. To query the
+    /// current metadata, use `handle.as_file().metadata()`.
+    ///
+    /// This returns `None` for dangling handles, and for handles constructed
+    /// with [`HandleOptions::dont_sync`], which only ask the file system
+    /// for the file's identity.
+    ///
+    /// [`Handle::from_dir_entry_snapshot`]: ../struct.Handle.html#method.from_dir_entry_snapshot
+    /// [`std::os::unix::fs::MetadataExt`]: https://doc.rust-lang.org/std/os/unix/fs/trait.MetadataExt.html
+    /// [`HandleOptions::dont_sync`]: ../struct.HandleOptions.html#method.dont_sync
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// use std::os::unix::fs::MetadataExt;
+    ///
+    /// use same_file::unix::HandleExt;
+    /// use same_file::Handle;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn Error>> {
+    /// let h = Handle::from_path("./foo")?;
+    /// if let Some(md) = h.metadata_at_open() {
+    ///     println!("{} bytes, mode {:o}", md.len(), md.mode());
+    /// }
+    /// #     Ok(())
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #     try_main().unwrap();
+    /// # }
+    /// ```
+    fn metadata_at_open(&self) -> Option<&fs::Metadata>;
+}
+
+impl HandleExt for crate::Handle {
+    fn metadata_at_open(&self) -> Option<&fs::Metadata> {
+        self.0.metadata.as_deref()
     }
 }
 
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::Handle {}
+}
+
 impl Hash for Handle {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.dev.hash(state);
-        self.ino.hash(state);
+        self.key.hash(state);
     }
 }
 
 impl Handle {
     pub fn from_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
-        Handle::from_file(OpenOptions::new().read(true).open(p)?)
+        Handle::open_with(p.as_ref(), 0)
+    }
+
+    pub fn from_path_detailed<P: AsRef<Path>>(
+        p: P,
+    ) -> Result<Handle, crate::Error> {
+        Handle::open_with_detailed(p.as_ref(), 0)
+    }
+
+    /// Open the given path with the given additional flags, which may
+    /// include `O_DIRECTORY` and `O_NOFOLLOW`.
+    fn open_with(p: &Path, flags: i32) -> io::Result<Handle> {
+        Handle::open_with_detailed(p, flags).map_err(io::Error::from)
+    }
+
+    /// Like `open_with`, but tell failing to open the file apart from
+    /// failing to query its identity.
+    fn open_with_detailed(
+        p: &Path,
+        flags: i32,
+    ) -> Result<Handle, crate::Error> {
+        let file = match Handle::open_file(p, flags) {
+            Ok(file) => file,
+            Err(err) => {
+                return Handle::unopenable(p, flags, err)
+                    .map_err(crate::Error::open)
+            }
+        };
+        Handle::from_file(file).map_err(crate::Error::stat)
+    }
+
+    /// Settle for a snapshot of the identity of a file that could not be
+    /// opened with the given error, if it is a file that can't be opened
+    /// at all.
+    ///
+    /// Without `O_PATH`, opening a socket fails (with `ENXIO`, or
+    /// `EOPNOTSUPP` on the BSDs), and so does opening a device whose driver
+    /// isn't there. Such files still have an identity, which `stat` reports
+    /// just fine. Any other error is returned as is.
+    // `O_NOFOLLOW` is zero on ESP-IDF.
+    #[cfg_attr(target_os = "espidf", allow(clippy::bad_bit_mask))]
+    fn unopenable(p: &Path, flags: i32, err: io::Error) -> io::Result<Handle> {
+        match err.raw_os_error() {
+            Some(c::ENXIO) | Some(c::EOPNOTSUPP) => {}
+            _ => return Err(err),
+        }
+        let md = if flags & O_NOFOLLOW != 0 {
+            fs::symlink_metadata(p)
+        } else {
+            fs::metadata(p)
+        };
+        let md = match md {
+            Ok(md) => md,
+            Err(_) => return Err(err),
+        };
+        let ft = md.file_type();
+        if !ft.is_socket() && !ft.is_char_device() && !ft.is_block_device() {
+            return Err(err);
+        }
+        Ok(Handle::snapshot(&md, p))
+    }
+
+    /// Open the file that a handle of the given path should be constructed
+    /// from, with the given additional flags.
+    // Both flags are zero on ESP-IDF.
+    #[cfg_attr(target_os = "espidf", allow(clippy::bad_bit_mask))]
+    fn open_file(p: &Path, flags: i32) -> io::Result<File> {
+        // Going through procfs to one of our own descriptors would open a
+        // new file description at best, and fail outright for `O_PATH`
+        // descriptors, sockets or a procfs we can't read. On the BSDs
+        // (including Apple's), opening `/dev/fd/N` duplicates the descriptor
+        // instead, but fails unless it was opened for reading, and
+        // restoring blocking mode below would change that of the original. The descriptor is right here, so use it directly
+        // instead.
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "illumos",
+            target_os = "solaris",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos",
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        if flags & O_NOFOLLOW == 0 {
+            if let Some(fd) = proc_fd(p) {
+                let file = dup(fd)?;
+                if flags & O_DIRECTORY != 0 && !file.metadata()?.is_dir() {
+                    return Err(io::Error::from_raw_os_error(c::ENOTDIR));
+                }
+                return Ok(file);
+            }
+        }
+        // Opening a FIFO for reading blocks until a writer shows up, which
+        // could be forever, and opening a device can wait on it (e.g., for
+        // a modem's carrier) or make it our controlling terminal. We don't
+        // need to read anything to determine the identity of a file, so
+        // always open it in non-blocking mode and without acquiring a
+        // terminal, rather than stat it first to decide. Blocking mode is
+        // restored once the descriptor is ours, except for FIFOs, so that
+        // reading from one doesn't wait for a writer either.
+        #[cfg(not(any(target_os = "espidf", target_os = "vxworks")))]
+        use self::c::O_NOCTTY;
+
+        let file = match open(p, c::O_NONBLOCK | O_NOCTTY | flags) {
+            Ok(file) => file,
+            Err(err) => return open_unopenable(p, flags, err),
+        };
+        if !file.metadata().map_err(overflow)?.file_type().is_fifo() {
+            set_blocking(&file)?;
+        }
+        Ok(file)
+    }
+
+    pub fn from_path_with<P: AsRef<Path>>(
+        p: P,
+        opts: &crate::HandleOptions,
+    ) -> io::Result<Handle> {
+        let p = p.as_ref();
+        let mut flags = 0;
+        if opts.require_directory {
+            flags |= O_DIRECTORY;
+        }
+        if !opts.follow_symlinks {
+            flags |= O_NOFOLLOW;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if opts.preserve_atime {
+            flags |= c::O_NOATIME;
+        }
+        #[cfg(target_os = "linux")]
+        if opts.dont_sync && !opts.stat_only {
+            return Handle::open_dont_sync(p, flags);
+        }
+        let err = if opts.stat_only {
+            None
+        } else {
+            match Handle::open_with(p, flags) {
+                Ok(h) if opts.require_directory => return require_dir(h),
+                Ok(h) => return Ok(h),
+                Err(err) => Some(err),
+            }
+        };
+        match err {
+            Some(err)
+                if !opts.snapshot_fallback
+                    || err.kind() != io::ErrorKind::PermissionDenied =>
+            {
+                return Err(err)
+            }
+            _ => {}
+        }
+        // Some files (e.g., other apps' files or scoped storage on Android)
+        // can be stat'd but not opened at all, and in some sandboxes (e.g.,
+        // iOS app extensions) opening a file is an offense in itself. Settle
+        // for a snapshot of their identity, without a descriptor to pin it
+        // down.
+        let md = if opts.follow_symlinks {
+            fs::metadata(p)
+        } else {
+            fs::symlink_metadata(p)
+        };
+        let md = match err {
+            Some(err) => md.map_err(|_| err)?,
+            None => md.map_err(overflow)?,
+        };
+        // Without a descriptor, the kernel can't enforce the options for
+        // us, so check them against the metadata instead.
+        if md.file_type().is_symlink() {
+            return Err(io::Error::from_raw_os_error(c::ELOOP));
+        }
+        if opts.require_directory && !md.is_dir() {
+            return Err(io::Error::from_raw_os_error(c::ENOTDIR));
+        }
+        Ok(Handle::snapshot(&md, p))
+    }
+
+    /// Open the given path without forcing a network file system to talk to
+    /// its server, where possible, for `HandleOptions::dont_sync`.
+    ///
+    /// An `O_PATH` open only looks the path up, and `AT_STATX_DONT_SYNC`
+    /// lets the file system answer `statx` from its cache.
+    #[cfg(target_os = "linux")]
+    fn open_dont_sync(p: &Path, flags: i32) -> io::Result<Handle> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(c::O_PATH | c::O_NONBLOCK | flags)
+            .open(p)?;
+        let stx = match statx_dont_sync(file.as_raw_fd(), statx) {
+            Ok(stx) => stx,
+            // Kernels before 4.11 don't have `statx`, so settle for `fstat`.
+            Err(ref err) if err.raw_os_error() == Some(c::ENOSYS) => {
+                return Handle::from_file(file);
+            }
+            Err(err) => return Err(err),
+        };
+        let mode = u32::from(stx.mode) & c::S_IFMT;
+        // With `O_PATH`, `O_NOFOLLOW` opens a symbolic link itself rather
+        // than failing.
+        if flags & O_NOFOLLOW != 0 && mode == c::S_IFLNK {
+            return Err(io::Error::from_raw_os_error(c::ELOOP));
+        }
+        let key = Key::new(c::makedev(stx.dev_major, stx.dev_minor), stx.ino);
+        event!(
+            target: "same_file::information",
+            tracing::Level::TRACE,
+            key = ?Some(key),
+        );
+        Ok(Handle {
+            file: Some(file),
+            is_std: false,
+            key,
+            generation: None,
+            modified: system_time(stx.mtime_sec, stx.mtime_nsec),
+            rdev: match mode {
+                c::S_IFCHR | c::S_IFBLK => {
+                    Some(c::makedev(stx.rdev_major, stx.rdev_minor))
+                }
+                _ => None,
+            },
+            path: None,
+            kind: match mode {
+                c::S_IFDIR => crate::FileKind::Dir,
+                c::S_IFREG => crate::FileKind::File,
+                _ => crate::FileKind::Other,
+            },
+            // `statx` only filled in the fields that were asked for.
+            metadata: None,
+            fs: None,
+        })
+    }
+
+    pub fn from_link<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        let p = p.as_ref();
+        // With `O_PATH`, `O_NOFOLLOW` opens a symbolic link itself.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let h = Handle::from_file(
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(c::O_PATH | O_NOFOLLOW)
+                .open(p)?,
+        )?;
+        // Elsewhere, a symbolic link can't be opened at all, so settle for a
+        // snapshot of its identity.
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let h = {
+            // The link is on the file system of its directory, which is not
+            // necessarily the one its target is on.
+            let dir = match p.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            Handle::snapshot(&fs::symlink_metadata(p).map_err(overflow)?, dir)
+        };
+        match h.metadata {
+            Some(ref md) if md.file_type().is_symlink() => Ok(h),
+            // This is what `readlink` fails with, too.
+            _ => Err(io::Error::from_raw_os_error(c::EINVAL)),
+        }
+    }
+
+    pub fn from_dir_entry(entry: &fs::DirEntry) -> io::Result<Handle> {
+        Handle::from_path(entry.path())
+    }
+
+    pub fn from_dir_entry_snapshot(
+        entry: &fs::DirEntry,
+    ) -> io::Result<Handle> {
+        let ft = entry.file_type()?;
+        // Handles always refer to the target of a symbolic link, which the
+        // directory entry knows nothing about.
+        if ft.is_symlink() {
+            return Handle::from_path(entry.path());
+        }
+        // This is an `fstatat` relative to the directory being read, rather
+        // than a lookup of the full path. Note that `d_ino` can't be used on
+        // its own: for a mount point, it is the inode of the directory that
+        // was mounted over.
+        let md = entry.metadata().map_err(overflow)?;
+        Ok(Handle::snapshot(&md, &entry.path()))
+    }
+
+    pub fn dangling() -> Handle {
+        Handle {
+            file: None,
+            is_std: false,
+            // The inode number is only there to spread dangling handles out
+            // when hashed.
+            key: Key::new(NODEV, crate::dangling_id()),
+            generation: None,
+            modified: None,
+            rdev: None,
+            path: None,
+            kind: crate::FileKind::Other,
+            metadata: None,
+            fs: None,
+        }
+    }
+
+    /// Build a handle that records the identity in the given metadata,
+    /// without an open file.
+    ///
+    /// The file system is looked up through `p`, which must be on the same
+    /// file system as the file the metadata is for.
+    fn snapshot(md: &fs::Metadata, p: &Path) -> Handle {
+        Handle {
+            file: None,
+            is_std: false,
+            key: Key::from_metadata(md),
+            generation: st_gen(md),
+            modified: md.modified().ok(),
+            rdev: device_number(md),
+            path: None,
+            kind: file_kind(md),
+            metadata: Some(Box::new(md.clone())),
+            fs: Some(Box::new(FsInfo {
+                id: path_fs_id(p),
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                magic: path_fs_magic(p),
+            })),
+        }
+    }
+
+    pub fn from_path_canonical<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        let p = fs::canonicalize(p)?;
+        let mut h = Handle::from_path(&p)?;
+        h.path = Some(p);
+        Ok(h)
+    }
+
+    pub fn canonical_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn from_path_beneath<P: AsRef<Path>>(
+        dir: &Handle,
+        p: P,
+    ) -> io::Result<Handle> {
+        let resolve = c::RESOLVE_BENEATH | c::RESOLVE_NO_MAGICLINKS;
+        let dirfd = dir.file()?.as_raw_fd();
+        let p = p.as_ref();
+        let flags = (c::O_RDONLY | c::O_CLOEXEC) as u64;
+        let err = match openat2(dirfd, p, flags, resolve) {
+            Ok(file) => return Handle::from_file(file),
+            Err(err) => err,
+        };
+        // Mirror `open`, falling back to `O_PATH` if reading isn't allowed.
+        if err.kind() != io::ErrorKind::PermissionDenied {
+            return Err(err);
+        }
+        let flags = (c::O_PATH | c::O_CLOEXEC) as u64;
+        openat2(dirfd, p, flags, resolve)
+            .and_then(Handle::from_file)
+            .map_err(|_| err)
+    }
+
+    pub fn from_path_retry<P: AsRef<Path>>(
+        p: P,
+        _attempts: usize,
+        _delay: Duration,
+    ) -> io::Result<Handle> {
+        Handle::from_path(p)
+    }
+
+    pub fn from_dir_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        require_dir(Handle::from_file(open(p.as_ref(), O_DIRECTORY)?)?)
     }
 
     pub fn from_file(file: File) -> io::Result<Handle> {
-        let md = file.metadata()?;
+        let md = file.metadata().map_err(overflow)?;
+        let generation = generation(&file, &md);
+        let key = Key::from_metadata(&md);
+        event!(
+            target: "same_file::information",
+            tracing::Level::TRACE,
+            key = ?Some(key),
+        );
         Ok(Handle {
             file: Some(file),
             is_std: false,
-            dev: md.dev(),
-            ino: md.ino(),
+            key,
+            generation,
+            modified: md.modified().ok(),
+            rdev: device_number(&md),
+            path: None,
+            kind: file_kind(&md),
+            metadata: Some(Box::new(md)),
+            fs: None,
         })
     }
 
+    pub fn from_as_fd<T: AsFd>(t: &T) -> io::Result<Handle> {
+        Handle::from_file(File::from(t.as_fd().try_clone_to_owned()?))
+    }
+
     pub fn from_std(file: File) -> io::Result<Handle> {
         Handle::from_file(file).map(|mut h| {
             h.is_std = true;
@@ -90,23 +779,1484 @@ impl Handle {
         Handle::from_std(unsafe { File::from_raw_fd(2) })
     }
 
+    pub fn raw(&self) -> RawFd {
+        self.file.as_ref().map_or(-1, |file| file.as_raw_fd())
+    }
+
     pub fn as_file(&self) -> &File {
-        // unwrap() will not panic. Since we were able to open the
-        // file successfully, then `file` is guaranteed to be Some()
-        self.file.as_ref().take().unwrap()
+        // This only panics for snapshot and dangling handles, which is
+        // documented.
+        self.file.as_ref().expect("handle has no file")
     }
 
     pub fn as_file_mut(&mut self) -> &mut File {
-        // unwrap() will not panic. Since we were able to open the
-        // file successfully, then `file` is guaranteed to be Some()
-        self.file.as_mut().take().unwrap()
+        // This only panics for snapshot and dangling handles, which is
+        // documented.
+        self.file.as_mut().expect("handle has no file")
+    }
+
+    pub fn into_file(mut self) -> Result<File, Handle> {
+        if self.is_std {
+            return Err(self);
+        }
+        match self.file.take() {
+            Some(file) => Ok(file),
+            None => Err(self),
+        }
+    }
+
+    pub fn as_borrowed_file(&self) -> Option<&File> {
+        if self.is_std {
+            self.file.as_ref()
+        } else {
+            None
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn reopen(&self, options: &OpenOptions) -> io::Result<File> {
+        // Opening a descriptor's magic link in procfs opens the file itself,
+        // regardless of what its path is now (or whether it has one).
+        let fd = self.file()?.as_raw_fd();
+        options.open(format!("/proc/self/fd/{}", fd))
+    }
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    ))]
+    pub fn reopen(&self, options: &OpenOptions) -> io::Result<File> {
+        let file = options.open(self.path()?)?;
+        // The path may have been replaced since it was looked up.
+        if self.key != Key::from_metadata(&file.metadata()?) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "file was replaced while being reopened",
+            ));
+        }
+        Ok(file)
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    )))]
+    pub fn reopen(&self, _options: &OpenOptions) -> io::Result<File> {
+        Err(crate::unsupported("reopened files"))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Handle> {
+        let file = match self.file {
+            Some(ref file) => Some(file.try_clone()?),
+            None => None,
+        };
+        Ok(Handle {
+            file,
+            is_std: false,
+            key: self.key,
+            generation: self.generation,
+            modified: self.modified,
+            rdev: self.rdev,
+            path: self.path.clone(),
+            kind: self.kind,
+            metadata: self.metadata.clone(),
+            fs: self.fs.clone(),
+        })
+    }
+
+    pub fn try_clone_fresh(&self) -> io::Result<Handle> {
+        let mut h = Handle::from_file(self.file()?.try_clone()?)?;
+        h.path = self.path.clone();
+        Ok(h)
+    }
+
+    pub fn modified_at_open(&self) -> Option<SystemTime> {
+        if self.key.is_known() {
+            self.modified
+        } else {
+            None
+        }
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        self.file.is_none() && self.key.dev != NODEV
+    }
+
+    pub fn is_path_based(&self) -> bool {
+        false
+    }
+
+    /// Return the underlying file, or an error for snapshot and dangling
+    /// handles.
+    fn file(&self) -> io::Result<&File> {
+        self.file.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "handle has no open file")
+        })
+    }
+
+    pub fn same_as_file(&self, file: &File) -> io::Result<bool> {
+        let md = file.metadata().map_err(overflow)?;
+        let key = Key::from_metadata(&md);
+        Ok(self.key.is_known() && key.is_known() && self.key == key)
+    }
+
+    pub fn weak_eq(&self, other: &Handle) -> bool {
+        match (self.key.is_known(), other.key.is_known()) {
+            (true, true) => self == other,
+            (false, false) => match (&self.file, &other.file) {
+                (Some(f1), Some(f2)) => f1.as_raw_fd() == f2.as_raw_fd(),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn same_volume(&self, other: &Handle) -> bool {
+        self.key.is_known()
+            && other.key.is_known()
+            && self.key.dev == other.key.dev
+    }
+
+    pub fn same_inode(&self, other: &Handle) -> bool {
+        self.key.is_known()
+            && other.key.is_known()
+            && self.key.ino == other.key.ino
+    }
+
+    #[cfg(not(target_os = "vxworks"))]
+    pub fn fs_id(&self) -> io::Result<u64> {
+        if let Some(id) = self.fs.as_ref().and_then(|fs| fs.id) {
+            return Ok(id);
+        }
+        #[cfg(feature = "backend-rustix")]
+        {
+            Ok(rustix::fs::fstatvfs(self.file()?)?.f_fsid)
+        }
+        #[cfg(not(feature = "backend-rustix"))]
+        {
+            let mut buf: libc::statvfs = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstatvfs(self.file()?.as_raw_fd(), &mut buf) }
+                == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(fsid(&buf))
+        }
+    }
+
+    #[cfg(target_os = "vxworks")]
+    pub fn fs_id(&self) -> io::Result<u64> {
+        Err(crate::unsupported("file system IDs"))
+    }
+
+    pub fn fsid_eq(&self, other: &Handle) -> io::Result<bool> {
+        if self == other {
+            return Ok(true);
+        }
+        if self.key.ino != other.key.ino {
+            return Ok(false);
+        }
+        Ok(self.fs_id()? == other.fs_id()?)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn mount_id(&self) -> io::Result<Option<u64>> {
+        statx_mount_id(self.file()?.as_raw_fd())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn subvolume_id(&self) -> io::Result<Option<u64>> {
+        // The objectid of the root directory of every btrfs subvolume.
+        const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+        // _IOWR(BTRFS_IOCTL_MAGIC, 18, struct btrfs_ioctl_ino_lookup_args)
+        const BTRFS_IOC_INO_LOOKUP: u32 = 0xD000_9412;
+
+        #[repr(C)]
+        struct InoLookupArgs {
+            treeid: u64,
+            objectid: u64,
+            name: [u8; 4080],
+        }
+
+        if self.fs_magic()? != c::BTRFS_SUPER_MAGIC as u64 {
+            return Ok(None);
+        }
+        // Looking up the subvolume root with a tree id of zero asks the
+        // kernel to fill in the tree id of the subvolume containing `fd`.
+        // This particular query doesn't require any privileges.
+        let mut args = InoLookupArgs {
+            treeid: 0,
+            objectid: BTRFS_FIRST_FREE_OBJECTID,
+            name: [0; 4080],
+        };
+        let file = self.file()?;
+        #[cfg(feature = "backend-rustix")]
+        unsafe {
+            use rustix::ioctl::{ioctl, Opcode, Updater};
+
+            const OPCODE: Opcode = BTRFS_IOC_INO_LOOKUP as Opcode;
+            ioctl(file, Updater::<OPCODE, InoLookupArgs>::new(&mut args))?;
+        }
+        #[cfg(not(feature = "backend-rustix"))]
+        if unsafe {
+            libc::ioctl(file.as_raw_fd(), BTRFS_IOC_INO_LOOKUP as _, &mut args)
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(args.treeid))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn is_overlayfs(&self) -> io::Result<bool> {
+        Ok(self.fs_magic()? == c::OVERLAYFS_SUPER_MAGIC as u64)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn overlay_eq(&self, other: &Handle) -> io::Result<Option<bool>> {
+        if self == other {
+            return Ok(Some(true));
+        }
+        // Only pay for the extra syscalls when the handles differ. A file in
+        // an upper layer that was copied up from a lower layer records where
+        // it came from. If either handle is such a file, the other one can
+        // be checked against its origin.
+        let mut unknown = false;
+        for &(upper, lower) in &[(self, other), (other, self)] {
+            if let Some(origin) = upper.overlay_origin() {
+                match upper.is_origin(&origin, lower) {
+                    Some(true) => return Ok(Some(true)),
+                    Some(false) => {}
+                    None => unknown = true,
+                }
+            }
+        }
+        // Outside of overlayfs, differing handles are definitely different
+        // files. But overlayfs hides the origin of a file from the merged
+        // view, so there is nothing more to go on for those.
+        if unknown || self.is_overlayfs()? || other.is_overlayfs()? {
+            return Ok(None);
+        }
+        Ok(Some(false))
+    }
+
+    /// Return the origin that overlayfs recorded for this file, if it is a
+    /// file in an upper layer that was copied up from a lower layer.
+    ///
+    /// This is the `trusted.overlay.origin` xattr (or `user.overlay.origin`
+    /// for overlays mounted with `userxattr`). Reading it through the merged
+    /// view never works, since overlayfs hides its private xattrs there.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn overlay_origin(&self) -> Option<OverlayOrigin> {
+        let file = self.file().ok()?;
+        let names: [&[u8]; 2] =
+            [b"trusted.overlay.origin\0", b"user.overlay.origin\0"];
+        for name in &names {
+            // The header and the largest file handle fit easily.
+            let mut buf = [0u8; 256];
+            #[cfg(feature = "backend-rustix")]
+            let n = std::ffi::CStr::from_bytes_with_nul(name).ok().and_then(
+                |name| rustix::fs::fgetxattr(file, name, &mut buf[..]).ok(),
+            );
+            #[cfg(not(feature = "backend-rustix"))]
+            let n = usize::try_from(unsafe {
+                libc::fgetxattr(
+                    file.as_raw_fd(),
+                    name.as_ptr().cast(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                )
+            })
+            .ok();
+            if let Some(n) = n {
+                return OverlayOrigin::parse(&buf[..n]);
+            }
+        }
+        None
+    }
+
+    /// Returns whether `other` is the file that this copied up file
+    /// originates from, or `None` if that can't be determined.
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        feature = "backend-libc"
+    ))]
+    fn is_origin(
+        &self,
+        origin: &OverlayOrigin,
+        other: &Handle,
+    ) -> Option<bool> {
+        let file = other.file().ok()?;
+        let (_, handle_type, bytes) = name_to_handle(file.as_raw_fd()).ok()?;
+        if handle_type != origin.handle_type || bytes != origin.fid {
+            return Some(false);
+        }
+        // File handles are only unique within a file system, which the
+        // origin names by its UUID. A null UUID is recorded for file systems
+        // that have none (or with `uuid=null`), which is only conclusive if
+        // both files are on the same file system anyway.
+        if origin.uuid == [0; 16] {
+            return if self.key.dev == other.key.dev {
+                Some(true)
+            } else {
+                None
+            };
+        }
+        fs_uuid(file).map(|uuid| uuid == origin.uuid)
+    }
+
+    /// Without libc, there is no way to get the kernel's file handle for
+    /// `other`, so it can't be compared with the origin.
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        not(feature = "backend-libc")
+    ))]
+    fn is_origin(&self, _: &OverlayOrigin, _: &Handle) -> Option<bool> {
+        None
+    }
+
+    #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+    pub fn kernel_handle(&self) -> io::Result<crate::KernelHandle> {
+        let (mount_id, handle_type, bytes) =
+            name_to_handle(self.file()?.as_raw_fd())?;
+        Ok(crate::KernelHandle { mount_id, handle_type, bytes })
+    }
+
+    #[cfg(all(target_os = "linux", feature = "backend-libc"))]
+    pub fn kernel_handle_eq(&self, other: &Handle) -> io::Result<bool> {
+        // Kernel handles are only unique within a file system. Compare
+        // devices rather than mount IDs, so that bind mounts of the same
+        // file system still compare equal.
+        if self.key.dev != other.key.dev {
+            return Ok(false);
+        }
+        match (self.kernel_handle(), other.kernel_handle()) {
+            (Ok(k1), Ok(k2)) => {
+                Ok(k1.handle_type == k2.handle_type && k1.bytes == k2.bytes)
+            }
+            (Err(err), _) | (_, Err(err)) => {
+                if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                    Ok(self == other)
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn fs_type(&self) -> io::Result<String> {
+        // These targets are all 64-bit, so `fstat` is the 64-bit variant.
+        #[cfg(feature = "backend-rustix")]
+        let st = rustix::fs::fstat(self.file()?)?;
+        #[cfg(not(feature = "backend-rustix"))]
+        let st = {
+            let mut st: libc::stat = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstat(self.file()?.as_raw_fd(), &mut st) } == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            st
+        };
+        let len = st.st_fstype.iter().position(|&c| c == 0);
+        let name = &st.st_fstype[..len.unwrap_or(st.st_fstype.len())];
+        Ok(name.iter().map(|&c| c as u8 as char).collect())
+    }
+
+    /// Return the `f_type` reported by `fstatfs`.
+    ///
+    /// The width and signedness of `f_type` varies by target, and so do the
+    /// `*_SUPER_MAGIC` constants in `c`. Both are cast to `u64` the same way
+    /// so they can be compared.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn fs_magic(&self) -> io::Result<u64> {
+        if let Some(magic) = self.fs.as_ref().and_then(|fs| fs.magic) {
+            return Ok(magic);
+        }
+        #[cfg(feature = "backend-rustix")]
+        {
+            Ok(f_type(&rustix::fs::fstatfs(self.file()?)?))
+        }
+        #[cfg(not(feature = "backend-rustix"))]
+        {
+            let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstatfs(self.file()?.as_raw_fd(), &mut buf) }
+                == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(buf.f_type as u64)
+        }
+    }
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    ))]
+    pub fn path(&self) -> io::Result<PathBuf> {
+        #[cfg(feature = "backend-rustix")]
+        {
+            use std::ffi::OsString;
+            use std::os::unix::ffi::OsStringExt;
+
+            let path = rustix::fs::getpath(self.file()?)?;
+            Ok(PathBuf::from(OsString::from_vec(path.into_bytes())))
+        }
+        #[cfg(not(feature = "backend-rustix"))]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+
+            let mut buf = vec![0u8; libc::MAXPATHLEN as usize];
+            let fd = self.file()?.as_raw_fd();
+            if unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) }
+                == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            Ok(PathBuf::from(OsStr::from_bytes(&buf[..len])))
+        }
+    }
+
+    #[cfg(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    ))]
+    pub fn same_volume_uuid(&self, other: &Handle) -> io::Result<bool> {
+        if self.key.dev == other.key.dev {
+            return Ok(true);
+        }
+        Ok(self.volume_uuid()? == other.volume_uuid()?)
+    }
+
+    /// Return the UUID of the volume containing this handle's file.
+    ///
+    /// Volume attributes can only be queried from the root of a volume, so
+    /// this first looks up where the volume is mounted.
+    #[cfg(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    ))]
+    fn volume_uuid(&self) -> io::Result<[u8; 16]> {
+        #[repr(C, packed(4))]
+        struct VolumeUuid {
+            len: u32,
+            uuid: [u8; 16],
+        }
+
+        let mut sfs: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatfs(self.file()?.as_raw_fd(), &mut sfs) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut attrs: libc::attrlist = unsafe { std::mem::zeroed() };
+        attrs.bitmapcount = libc::ATTR_BIT_MAP_COUNT;
+        attrs.volattr = libc::ATTR_VOL_INFO | libc::ATTR_VOL_UUID;
+        let mut buf = VolumeUuid { len: 0, uuid: [0; 16] };
+        if unsafe {
+            libc::getattrlist(
+                sfs.f_mntonname.as_ptr(),
+                &mut attrs as *mut libc::attrlist as *mut libc::c_void,
+                &mut buf as *mut VolumeUuid as *mut libc::c_void,
+                std::mem::size_of::<VolumeUuid>(),
+                0,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(buf.uuid)
+    }
+
+    #[cfg(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    ))]
+    pub fn link_id(&self) -> io::Result<u64> {
+        cmnext_u64(self.file()?, libc::ATTR_CMNEXT_LINKID)
+    }
+
+    #[cfg(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    ))]
+    pub fn shares_storage(&self, other: &Handle) -> io::Result<bool> {
+        if self.key.dev != other.key.dev {
+            return Ok(false);
+        }
+        if self == other {
+            return Ok(true);
+        }
+        let off1 = physical_offset(self.file()?)?;
+        let off2 = physical_offset(other.file()?)?;
+        Ok(off1.is_some() && off1 == off2)
+    }
+
+    #[cfg(not(all(
+        any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "visionos"
+        ),
+        feature = "backend-libc"
+    )))]
+    pub fn link_id(&self) -> io::Result<u64> {
+        Err(crate::unsupported("link identifiers"))
+    }
+
+    pub fn is_unlinked(&self) -> io::Result<bool> {
+        Ok(self.file()?.metadata().map_err(overflow)?.nlink() == 0)
+    }
+
+    pub fn generation(&self) -> Option<u64> {
+        self.generation
+    }
+
+    pub fn allocated_size(&self) -> io::Result<u64> {
+        let blocks = match (&self.file, &self.metadata) {
+            (Some(file), _) => file.metadata()?.blocks(),
+            // A snapshot has no file to ask, but it has what `stat` said.
+            (None, Some(md)) => md.blocks(),
+            (None, None) => self.file()?.metadata()?.blocks(),
+        };
+        // `st_blocks` is always in units of 512 bytes, regardless of the
+        // file system's block size.
+        Ok(blocks * 512)
+    }
+
+    pub fn kind(&self) -> crate::FileKind {
+        self.kind
+    }
+
+    pub fn key(&self) -> Option<Key> {
+        if self.key.is_known() {
+            Some(self.key)
+        } else {
+            None
+        }
     }
 
     pub fn dev(&self) -> u64 {
-        self.dev
+        self.key.dev
+    }
+
+    pub fn rdev(&self) -> Option<u64> {
+        self.rdev
     }
 
     pub fn ino(&self) -> u64 {
-        self.ino
+        self.key.ino
+    }
+}
+
+fn file_kind(md: &fs::Metadata) -> crate::FileKind {
+    let ft = md.file_type();
+    if ft.is_dir() {
+        crate::FileKind::Dir
+    } else if ft.is_file() {
+        crate::FileKind::File
+    } else {
+        crate::FileKind::Other
+    }
+}
+
+/// Convert a timestamp in seconds and nanoseconds since the Unix epoch.
+#[cfg(target_os = "linux")]
+fn system_time(sec: i64, nsec: u32) -> Option<SystemTime> {
+    let epoch = SystemTime::UNIX_EPOCH;
+    let t = if sec >= 0 {
+        epoch.checked_add(Duration::from_secs(sec as u64))
+    } else {
+        epoch.checked_sub(Duration::from_secs(sec.unsigned_abs()))
+    };
+    t?.checked_add(Duration::from_nanos(u64::from(nsec)))
+}
+
+/// Return the device number of a character or block device.
+fn device_number(md: &fs::Metadata) -> Option<u64> {
+    let ft = md.file_type();
+    if ft.is_char_device() || ft.is_block_device() {
+        Some(md.rdev())
+    } else {
+        None
+    }
+}
+
+/// Turn an `EOVERFLOW` from `stat` into a clearer error.
+///
+/// Since std uses the 64-bit variants of `stat`, this shouldn't happen. But
+/// if it does, then some value in the file's metadata (possibly its inode
+/// number) didn't fit, and the error should say so rather than leave the
+/// caller to puzzle over "value too large for defined data type".
+fn overflow(err: io::Error) -> io::Error {
+    if err.raw_os_error() != Some(c::EOVERFLOW) {
+        return err;
+    }
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("file metadata does not fit in this platform's stat: {}", err),
+    )
+}
+
+/// If the given path refers to one of this process's own descriptors through
+/// procfs or `/dev/fd`, then return that descriptor.
+///
+/// This recognizes `/dev/fd/N`, as well as `/dev/stdin`, `/dev/stdout` and
+/// `/dev/stderr`, which refer to the first three descriptors. Where there is
+/// a procfs, it also recognizes `/proc/self/fd/N`, `/proc/thread-self/fd/N`
+/// and `/proc/<our pid>/fd/N`. Paths are matched literally, without being
+/// normalized.
+///
+/// On Linux, the entries in `/proc/self/fd` are magic symbolic links, while
+/// on illumos and Solaris, they aren't links at all, and `/dev/fd` is a
+/// separate file system. On the BSDs, `/dev/fd` is served by `fdescfs`,
+/// which duplicates the descriptor when opened. Either way, they name
+/// descriptors we already have.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "illumos",
+    target_os = "solaris",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    target_os = "visionos",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn proc_fd(p: &Path) -> Option<RawFd> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let p = p.as_os_str().as_bytes();
+    match p {
+        b"/dev/stdin" => return Some(0),
+        b"/dev/stdout" => return Some(1),
+        b"/dev/stderr" => return Some(2),
+        _ => {}
+    }
+    let fd = match p.strip_prefix(b"/dev/fd/") {
+        Some(fd) => fd,
+        None if cfg!(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "illumos",
+            target_os = "solaris"
+        )) =>
+        {
+            let rest = p.strip_prefix(b"/proc/")?;
+            let slash = rest.iter().position(|&b| b == b'/')?;
+            let (pid, rest) = rest.split_at(slash);
+            if pid != b"self"
+                && pid != b"thread-self"
+                && pid != std::process::id().to_string().as_bytes()
+            {
+                return None;
+            }
+            rest.strip_prefix(b"/fd/")?
+        }
+        None => return None,
+    };
+    if fd.is_empty() || !fd.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    std::str::from_utf8(fd).ok()?.parse().ok()
+}
+
+/// Duplicate the given descriptor, which is not owned by the caller.
+///
+/// If the descriptor isn't open, this fails with `EBADF`.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "illumos",
+    target_os = "solaris",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    target_os = "visionos",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn dup(fd: RawFd) -> io::Result<File> {
+    #[cfg(feature = "backend-rustix")]
+    {
+        use std::os::unix::io::BorrowedFd;
+
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        Ok(File::from(rustix::io::fcntl_dupfd_cloexec(fd, 0)?))
+    }
+    #[cfg(not(feature = "backend-rustix"))]
+    {
+        let fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+/// Open the given path for reading with the given additional flags.
+///
+/// If reading isn't permitted, then on Linux and Android, the path is opened
+/// with `O_PATH` instead. Such a descriptor can't be used for I/O, but it's
+/// enough to determine the identity of the file. If that fails too (e.g., on
+/// kernels where `fstat` doesn't support `O_PATH` descriptors), then the
+/// original error is returned.
+fn open(p: &Path, flags: i32) -> io::Result<File> {
+    let err = match open_read(p, flags) {
+        Ok(file) => return Ok(file),
+        Err(err) => err,
+    };
+    if err.kind() != io::ErrorKind::PermissionDenied {
+        return Err(err);
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let opened = OpenOptions::new()
+            .read(true)
+            .custom_flags(flags | c::O_PATH)
+            .open(p)
+            .and_then(|file| file.metadata().map(|_| file));
+        if let Ok(file) = opened {
+            return Ok(file);
+        }
+    }
+    Err(err)
+}
+
+/// Open the given path for reading with the given additional flags, and
+/// nothing else.
+///
+/// `O_NOATIME` is only permitted for the owner of a file (or with
+/// `CAP_FOWNER`), so if it is among the flags and fails with `EPERM`, then
+/// this tries again without it.
+fn open_read(p: &Path, flags: i32) -> io::Result<File> {
+    let result = OpenOptions::new().read(true).custom_flags(flags).open(p);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if flags & c::O_NOATIME != 0 {
+        if let Err(ref err) = result {
+            if err.raw_os_error() == Some(c::EPERM) {
+                return open_read(p, flags & !c::O_NOATIME);
+            }
+        }
+    }
+    result
+}
+
+/// Open the given path relative to `dirfd` with `openat2(2)`.
+///
+/// There is deliberately no fallback for kernels older than 5.6 that lack
+/// `openat2`, since nothing else enforces the same resolution restrictions.
+/// On those kernels, this fails with `ENOSYS`.
+#[cfg(target_os = "linux")]
+fn openat2(
+    dirfd: RawFd,
+    p: &Path,
+    flags: u64,
+    resolve: u64,
+) -> io::Result<File> {
+    #[cfg(feature = "backend-rustix")]
+    {
+        use rustix::fs::{Mode, OFlags, ResolveFlags};
+        use std::os::unix::io::BorrowedFd;
+
+        let dirfd = unsafe { BorrowedFd::borrow_raw(dirfd) };
+        let fd = rustix::fs::openat2(
+            dirfd,
+            p,
+            OFlags::from_bits_retain(flags as u32),
+            Mode::empty(),
+            ResolveFlags::from_bits_retain(resolve),
+        )?;
+        Ok(File::from(fd))
+    }
+    #[cfg(not(feature = "backend-rustix"))]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let p = CString::new(p.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = flags;
+        how.resolve = resolve;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                dirfd,
+                p.as_ptr(),
+                &mut how as *mut libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+    }
+}
+
+/// `struct statx` from linux/stat.h, which libc only provides for some C
+/// libraries. Only the fields we need are spelled out.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+pub(crate) struct Statx {
+    mask: u32,
+    _blksize: u32,
+    _attributes: u64,
+    _nlink: u32,
+    _uid: u32,
+    _gid: u32,
+    mode: u16,
+    _spare0: u16,
+    ino: u64,
+    // The size, the number of blocks, the attribute mask, and the access,
+    // creation and change times.
+    _before_mtime: [u64; 9],
+    mtime_sec: i64,
+    mtime_nsec: u32,
+    _mtime_reserved: i32,
+    rdev_major: u32,
+    rdev_minor: u32,
+    dev_major: u32,
+    dev_minor: u32,
+    mnt_id: u64,
+    _after: [u64; 13],
+}
+
+/// Call `statx(2)` on the given descriptor (with `AT_EMPTY_PATH` in
+/// `flags`), through the raw system call since not every C library has a
+/// wrapper for it.
+#[cfg(all(target_os = "linux", not(feature = "backend-rustix")))]
+fn statx(fd: RawFd, flags: i32, mask: u32, buf: &mut Statx) -> io::Result<()> {
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            fd,
+            b"\0".as_ptr(),
+            flags,
+            mask,
+            buf as *mut Statx,
+        )
+    };
+    if rc == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Call `statx(2)` on the given descriptor (with `AT_EMPTY_PATH` in
+/// `flags`) through rustix, and copy the fields we need.
+#[cfg(all(target_os = "linux", feature = "backend-rustix"))]
+fn statx(fd: RawFd, flags: i32, mask: u32, buf: &mut Statx) -> io::Result<()> {
+    use rustix::fs::{AtFlags, StatxFlags};
+    use std::os::unix::io::BorrowedFd;
+
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    let stx = rustix::fs::statx(
+        fd,
+        "",
+        AtFlags::from_bits_retain(flags as u32),
+        StatxFlags::from_bits_retain(mask),
+    )?;
+    buf.mask = stx.stx_mask;
+    buf.mode = stx.stx_mode;
+    buf.ino = stx.stx_ino;
+    buf.mtime_sec = stx.stx_mtime.tv_sec;
+    buf.mtime_nsec = stx.stx_mtime.tv_nsec;
+    buf.rdev_major = stx.stx_rdev_major;
+    buf.rdev_minor = stx.stx_rdev_minor;
+    buf.dev_major = stx.stx_dev_major;
+    buf.dev_minor = stx.stx_dev_minor;
+    buf.mnt_id = stx.stx_mnt_id;
+    Ok(())
+}
+
+/// Query the identity of the given descriptor with `statx(2)`, telling the
+/// file system not to synchronize with a remote server first.
+///
+/// The system call is made with the given function, which has the same
+/// signature as `statx` above, so that tests can check how it's called.
+#[cfg(target_os = "linux")]
+pub(crate) fn statx_dont_sync<F>(fd: RawFd, statx: F) -> io::Result<Statx>
+where
+    F: FnOnce(RawFd, i32, u32, &mut Statx) -> io::Result<()>,
+{
+    const STATX_TYPE: u32 = 0x1;
+    const STATX_MTIME: u32 = 0x40;
+    const STATX_INO: u32 = 0x100;
+    const AT_STATX_DONT_SYNC: i32 = 0x4000;
+
+    let mut buf: Statx = unsafe { std::mem::zeroed() };
+    let flags = c::AT_EMPTY_PATH | AT_STATX_DONT_SYNC;
+    statx(fd, flags, STATX_TYPE | STATX_MTIME | STATX_INO, &mut buf)?;
+    Ok(buf)
+}
+
+/// Return the ID of the mount containing the given descriptor, as reported
+/// by `statx(2)`.
+///
+/// This returns `None` on kernels that don't report mount IDs (before 5.8),
+/// including those without `statx` at all (before 4.11).
+#[cfg(target_os = "linux")]
+fn statx_mount_id(fd: RawFd) -> io::Result<Option<u64>> {
+    const STATX_MNT_ID: u32 = 0x1000;
+
+    let mut buf: Statx = unsafe { std::mem::zeroed() };
+    if let Err(err) = statx(fd, c::AT_EMPTY_PATH, STATX_MNT_ID, &mut buf) {
+        if err.raw_os_error() == Some(c::ENOSYS) {
+            return Ok(None);
+        }
+        return Err(err);
+    }
+    if buf.mask & STATX_MNT_ID == 0 {
+        return Ok(None);
+    }
+    Ok(Some(buf.mnt_id))
+}
+
+/// Return the ID of the mount containing the given path, going by the
+/// longest mount point in `/proc/self/mountinfo` that contains it.
+///
+/// This is for kernels where `statx_mount_id` returns `None`. The path is
+/// made canonical first, so that symbolic links can't throw off the match.
+#[cfg(target_os = "linux")]
+pub(crate) fn mountinfo_mount_id(p: &Path) -> io::Result<u64> {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    let p = fs::canonicalize(p)?;
+    let mountinfo = fs::read("/proc/self/mountinfo")?;
+    let mut best: Option<(usize, u64)> = None;
+    for line in mountinfo.split(|&b| b == b'\n') {
+        // The fields are: mount ID, parent ID, major:minor, root, mount
+        // point, and then some we don't care about.
+        let mut fields = line.split(|&b| b == b' ');
+        let id = match fields.next().and_then(parse_u64) {
+            Some(id) => id,
+            None => continue,
+        };
+        let mount_point = match fields.nth(3) {
+            Some(mount_point) => unescape_mountinfo(mount_point),
+            None => continue,
+        };
+        let mount_point = PathBuf::from(OsString::from_vec(mount_point));
+        let len = mount_point.as_os_str().len();
+        // A later mount on the same mount point hides the earlier ones.
+        if p.starts_with(&mount_point) && best.map_or(true, |(l, _)| len >= l)
+        {
+            best = Some((len, id));
+        }
+    }
+    best.map(|(_, id)| id).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "path is not on any mount")
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn parse_u64(field: &[u8]) -> Option<u64> {
+    std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+/// Undo the octal escapes (e.g., `\040` for a space) that the kernel uses
+/// for whitespace and backslashes in `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+fn unescape_mountinfo(field: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        let octal = field.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match octal {
+            Some(b) if field[i] == b'\\' => {
+                out.push(b);
+                i += 4;
+            }
+            _ => {
+                out.push(field[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn files_eq(file1: &File, file2: &File) -> io::Result<bool> {
+    let md1 = file1.metadata().map_err(overflow)?;
+    let md2 = file2.metadata().map_err(overflow)?;
+    let (key1, key2) = (Key::from_metadata(&md1), Key::from_metadata(&md2));
+    // Like handles, files without an identity are never equal.
+    Ok(key1.is_known() && key2.is_known() && key1 == key2)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn is_same_mount(p1: &Path, p2: &Path) -> io::Result<bool> {
+    let h1 = Handle::from_path(p1)?;
+    let h2 = Handle::from_path(p2)?;
+    if let (Some(m1), Some(m2)) = (h1.mount_id()?, h2.mount_id()?) {
+        return Ok(m1 == m2);
+    }
+    Ok(mountinfo_mount_id(p1)? == mountinfo_mount_id(p2)?)
+}
+
+/// Query an extended common attribute (one of the `ATTR_CMNEXT_*` values)
+/// of the given file whose value is a `u64`.
+#[cfg(all(
+    any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    ),
+    feature = "backend-libc"
+))]
+fn cmnext_u64(file: &File, attr: libc::attrgroup_t) -> io::Result<u64> {
+    #[repr(C, packed(4))]
+    struct Attribute {
+        len: u32,
+        value: u64,
+    }
+
+    let mut attrs: libc::attrlist = unsafe { std::mem::zeroed() };
+    attrs.bitmapcount = libc::ATTR_BIT_MAP_COUNT;
+    attrs.forkattr = attr;
+    let mut buf = Attribute { len: 0, value: 0 };
+    if unsafe {
+        libc::fgetattrlist(
+            file.as_raw_fd(),
+            &mut attrs as *mut libc::attrlist as *mut libc::c_void,
+            &mut buf as *mut Attribute as *mut libc::c_void,
+            std::mem::size_of::<Attribute>(),
+            libc::FSOPT_ATTR_CMN_EXTENDED,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(buf.value)
+}
+
+/// Return the offset on the underlying device of the first byte of the given
+/// file, or `None` if the file is empty and therefore has no storage.
+#[cfg(all(
+    any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "tvos",
+        target_os = "watchos",
+        target_os = "visionos"
+    ),
+    feature = "backend-libc"
+))]
+fn physical_offset(file: &File) -> io::Result<Option<i64>> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    let mut l2p: libc::log2phys = unsafe { std::mem::zeroed() };
+    // On input, these are the length and the offset within the file to map.
+    l2p.l2p_contigbytes = 1;
+    l2p.l2p_devoffset = 0;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_LOG2PHYS_EXT, &mut l2p) }
+        == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(l2p.l2p_devoffset))
+}
+
+/// Return the `f_fsid` of the given `statvfs` as a single number.
+///
+/// It's an integer nearly everywhere, but a pair of 32-bit halves on AIX.
+#[cfg(all(target_os = "aix", not(feature = "backend-rustix")))]
+fn fsid(buf: &libc::statvfs) -> u64 {
+    (u64::from(buf.f_fsid.val[0]) << 32) | u64::from(buf.f_fsid.val[1])
+}
+
+/// Return the `f_fsid` of the file system containing the given path, or
+/// `None` if it can't be queried.
+#[cfg(not(target_os = "vxworks"))]
+fn path_fs_id(p: &Path) -> Option<u64> {
+    #[cfg(feature = "backend-rustix")]
+    {
+        rustix::fs::statvfs(p).ok().map(|buf| buf.f_fsid)
+    }
+    #[cfg(not(feature = "backend-rustix"))]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let p = CString::new(p.as_os_str().as_bytes()).ok()?;
+        let mut buf: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(p.as_ptr(), &mut buf) } == -1 {
+            return None;
+        }
+        Some(fsid(&buf))
+    }
+}
+
+#[cfg(target_os = "vxworks")]
+fn path_fs_id(_: &Path) -> Option<u64> {
+    None
+}
+
+/// Return the `f_type` of the file system containing the given path, or
+/// `None` if it can't be queried. See `Handle::fs_magic`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn path_fs_magic(p: &Path) -> Option<u64> {
+    #[cfg(feature = "backend-rustix")]
+    {
+        rustix::fs::statfs(p).ok().map(|buf| f_type(&buf))
+    }
+    #[cfg(not(feature = "backend-rustix"))]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let p = CString::new(p.as_os_str().as_bytes()).ok()?;
+        let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statfs(p.as_ptr(), &mut buf) } == -1 {
+            return None;
+        }
+        Some(buf.f_type as u64)
+    }
+}
+
+/// Return the `f_type` of the given `statfs` as a `u64`, cast the way libc's
+/// `f_type` is cast on glibc, where both it and the `*_SUPER_MAGIC` constants
+/// are a `c_long`.
+///
+/// Elsewhere, rustix's `f_type` may be unsigned, so it's converted to a
+/// `c_long` first to compare equal to the constants in `c`.
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    feature = "backend-rustix"
+))]
+#[allow(clippy::unnecessary_cast)]
+fn f_type(buf: &rustix::fs::StatFs) -> u64 {
+    buf.f_type as std::os::raw::c_long as u64
+}
+
+// The width of `f_fsid` varies by target, so the cast is only unnecessary on
+// some of them.
+#[cfg(not(any(
+    target_os = "aix",
+    target_os = "vxworks",
+    feature = "backend-rustix"
+)))]
+#[allow(clippy::unnecessary_cast)]
+fn fsid(buf: &libc::statvfs) -> u64 {
+    buf.f_fsid as u64
+}
+
+/// Return the mount ID, the type and the bytes of the kernel's file handle
+/// for the given descriptor, as reported by `name_to_handle_at(2)`.
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    feature = "backend-libc"
+))]
+fn name_to_handle(fd: RawFd) -> io::Result<(i32, i32, Vec<u8>)> {
+    // MAX_HANDLE_SZ, which is large enough for any file system.
+    const MAX_HANDLE_SZ: usize = 128;
+
+    // The same layout as `struct file_handle`, with room for the trailing
+    // flexible array member.
+    #[repr(C)]
+    struct FileHandle {
+        handle_bytes: u32,
+        handle_type: i32,
+        f_handle: [u8; MAX_HANDLE_SZ],
+    }
+
+    let mut fh = FileHandle {
+        handle_bytes: MAX_HANDLE_SZ as u32,
+        handle_type: 0,
+        f_handle: [0; MAX_HANDLE_SZ],
+    };
+    let mut mount_id: i32 = 0;
+    // libc doesn't declare `name_to_handle_at` for Android.
+    if unsafe {
+        libc::syscall(
+            libc::SYS_name_to_handle_at,
+            fd,
+            b"\0".as_ptr(),
+            &mut fh as *mut FileHandle,
+            &mut mount_id as *mut i32,
+            libc::AT_EMPTY_PATH,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let bytes = fh.f_handle[..fh.handle_bytes as usize].to_vec();
+    Ok((mount_id, fh.handle_type, bytes))
+}
+
+/// Return the UUID of the file system the given file is on, or `None` if it
+/// can't be queried.
+///
+/// This needs Linux 6.5, and a file system that has a UUID.
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    feature = "backend-libc"
+))]
+fn fs_uuid(file: &File) -> Option<[u8; 16]> {
+    // _IOR(0x15, 0, struct fsuuid2)
+    const FS_IOC_GETFSUUID: u32 = 0x8011_1500;
+
+    #[repr(C)]
+    struct FsUuid2 {
+        len: u8,
+        uuid: [u8; 16],
+    }
+
+    #[cfg(feature = "backend-rustix")]
+    let buf = unsafe {
+        use rustix::ioctl::{ioctl, Getter, Opcode};
+
+        const OPCODE: Opcode = FS_IOC_GETFSUUID as Opcode;
+        ioctl(file, Getter::<OPCODE, FsUuid2>::new()).ok()?
+    };
+    #[cfg(not(feature = "backend-rustix"))]
+    let buf = {
+        let mut buf = FsUuid2 { len: 0, uuid: [0; 16] };
+        let fd = file.as_raw_fd();
+        if unsafe { libc::ioctl(fd, FS_IOC_GETFSUUID as _, &mut buf) } == -1 {
+            return None;
+        }
+        buf
+    };
+    if buf.len != 16 {
+        return None;
+    }
+    Some(buf.uuid)
+}
+
+/// The lower layer file that overlayfs recorded as the origin of a copied
+/// up file.
+///
+/// Without libc, it is only ever checked for existence (see `is_origin`).
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg_attr(not(feature = "backend-libc"), allow(dead_code))]
+struct OverlayOrigin {
+    handle_type: i32,
+    uuid: [u8; 16],
+    fid: Vec<u8>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl OverlayOrigin {
+    /// Parse the contents of an origin xattr, which is a `struct ovl_fb`:
+    /// a version, a magic number, the total length, flags, the file handle
+    /// type, the UUID of the file system and finally the file handle.
+    fn parse(buf: &[u8]) -> Option<OverlayOrigin> {
+        const OVL_FH_MAGIC: u8 = 0xfb;
+        const HEADER: usize = 21;
+
+        if buf.len() < HEADER || buf[0] != 0 || buf[1] != OVL_FH_MAGIC {
+            return None;
+        }
+        let len = usize::from(buf[2]);
+        if len < HEADER || len > buf.len() {
+            return None;
+        }
+        let mut uuid = [0; 16];
+        uuid.copy_from_slice(&buf[5..HEADER]);
+        Some(OverlayOrigin {
+            handle_type: i32::from(buf[4]),
+            uuid,
+            fid: buf[HEADER..len].to_vec(),
+        })
+    }
+}
+
+/// Return the generation number of the given file's inode.
+///
+/// The generation number changes when an inode number is recycled for a new
+/// file. Not every file system supports this (and the ioctl is refused for
+/// `O_PATH` descriptors), so any failure simply means there is no generation
+/// number.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn generation(file: &File, _md: &fs::Metadata) -> Option<u64> {
+    #[cfg(feature = "backend-rustix")]
+    let generation = unsafe {
+        use rustix::ioctl::{ioctl, opcode, Getter, Opcode};
+
+        // FS_IOC_GETVERSION is declared with a `long`, but the kernel only
+        // ever writes an `int`.
+        const OPCODE: Opcode = opcode::read::<std::os::raw::c_long>(b'v', 1);
+        ioctl(file, Getter::<OPCODE, i32>::new()).ok()?
+    };
+    #[cfg(not(feature = "backend-rustix"))]
+    let generation = {
+        let mut generation: i32 = 0;
+        let fd = file.as_raw_fd();
+        if unsafe { libc::ioctl(fd, libc::FS_IOC_GETVERSION, &mut generation) }
+            == -1
+        {
+            return None;
+        }
+        generation
+    };
+    Some(generation as u32 as u64)
+}
+
+/// Return the generation number of the given file's inode.
+///
+/// Elsewhere, it can only come from the metadata.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn generation(_file: &File, md: &fs::Metadata) -> Option<u64> {
+    st_gen(md)
+}
+
+/// Return the generation number of an inode recorded in its metadata.
+///
+/// The BSDs report it as `st_gen`, but only to the owner of the file (or
+/// root). Everyone else sees zero, which we treat as not having one. Since
+/// this needs no open file, snapshot handles have it too.
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn st_gen(md: &fs::Metadata) -> Option<u64> {
+    #[cfg(target_os = "freebsd")]
+    use std::os::freebsd::fs::MetadataExt;
+    #[cfg(target_os = "netbsd")]
+    use std::os::netbsd::fs::MetadataExt;
+    #[cfg(target_os = "openbsd")]
+    use std::os::openbsd::fs::MetadataExt;
+
+    match md.st_gen() {
+        0 => None,
+        gen => Some(u64::from(gen)),
+    }
+}
+
+#[cfg(not(any(
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn st_gen(_md: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Open a file that can't be opened normally, which failed with the given
+/// error, for the purposes of determining its identity only.
+///
+/// Sockets can't be opened at all, and neither can devices whose driver
+/// isn't there (or `/dev/tty` without a controlling terminal), all of which
+/// fail with `ENXIO`. `O_PATH` doesn't open the file at all, so it works for
+/// them. The resulting file can't be used for I/O, though.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn open_unopenable(p: &Path, flags: i32, err: io::Error) -> io::Result<File> {
+    if err.raw_os_error() != Some(c::ENXIO) {
+        return Err(err);
+    }
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(c::O_PATH | flags)
+        .open(p)
+        .map_err(|_| err)
+}
+
+/// Without `O_PATH`, files that can't be opened normally can't be opened at
+/// all, so the error is returned as is.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn open_unopenable(_: &Path, _: i32, err: io::Error) -> io::Result<File> {
+    Err(err)
+}
+
+/// Fail with `ENOTDIR` if the given handle isn't a directory.
+///
+/// This is only needed where `O_DIRECTORY` doesn't exist, since the kernel
+/// checks it for us otherwise.
+fn require_dir(h: Handle) -> io::Result<Handle> {
+    if cfg!(target_os = "espidf") && h.kind != crate::FileKind::Dir {
+        return Err(io::Error::from_raw_os_error(c::ENOTDIR));
+    }
+    Ok(h)
+}
+
+/// Clear `O_NONBLOCK` on the given file.
+///
+/// `O_PATH` descriptors can't have their flags changed (nor be used for I/O,
+/// so blocking doesn't matter), so they are left alone.
+fn set_blocking(file: &File) -> io::Result<()> {
+    #[cfg(feature = "backend-rustix")]
+    {
+        use rustix::fs::{fcntl_getfl, fcntl_setfl, OFlags};
+
+        let flags = fcntl_getfl(file)?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if flags.contains(OFlags::PATH) {
+            return Ok(());
+        }
+        fcntl_setfl(file, flags - OFlags::NONBLOCK)?;
+        Ok(())
+    }
+    #[cfg(not(feature = "backend-rustix"))]
+    {
+        let fd = file.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if flags & c::O_PATH != 0 {
+            return Ok(());
+        }
+        if unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !c::O_NONBLOCK) }
+            == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
     }
 }
@@ -1,27 +1,112 @@
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::path::Path;
+use std::time::{Duration, SystemTime};
 
 static ERROR_MESSAGE: &str = "same-file is not supported on this platform.";
 // This implementation is to allow same-file to be compiled on
 // unsupported platforms in case it was incidentally included
-// as a transitive, unused dependency
-#[derive(Debug, Hash)]
-pub struct Handle;
+// as a transitive, unused dependency, or by a crate that only needs it on
+// real operating systems. Every constructor fails with `Unsupported`, except
+// for `dangling`, which doesn't need the operating system. So the only
+// handles that can exist are dangling ones, which have no file and no
+// identity.
+// There are no descriptors, but dangling handles need something to return.
+pub(crate) type RawDescriptor = i32;
+
+#[derive(Debug)]
+pub struct Handle {
+    // Only there to spread dangling handles out when hashed.
+    id: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Key {}
+
+impl Key {
+    pub fn parts(&self) -> (u64, u128) {
+        match *self {}
+    }
+
+    /// There are no keys to construct.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(_volume: u64, _index: u128) -> Option<Key> {
+        None
+    }
+}
 
 impl Eq for Handle {}
 
 impl PartialEq for Handle {
-    fn eq(&self, _other: &Handle) -> bool {
-        unreachable!(ERROR_MESSAGE);
+    fn eq(&self, other: &Handle) -> bool {
+        // Dangling handles are only equal to themselves.
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Hash for Handle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
     }
 }
 
 impl Handle {
+    pub fn dangling() -> Handle {
+        Handle { id: crate::dangling_id() }
+    }
+
     pub fn from_path<P: AsRef<Path>>(_p: P) -> io::Result<Handle> {
         error()
     }
 
+    pub fn from_path_detailed<P: AsRef<Path>>(
+        _p: P,
+    ) -> Result<Handle, crate::Error> {
+        error().map_err(crate::Error::open)
+    }
+
+    pub fn from_path_with<P: AsRef<Path>>(
+        _p: P,
+        _opts: &crate::HandleOptions,
+    ) -> io::Result<Handle> {
+        error()
+    }
+
+    pub fn from_link<P: AsRef<Path>>(_p: P) -> io::Result<Handle> {
+        error()
+    }
+
+    pub fn from_dir_entry(_entry: &std::fs::DirEntry) -> io::Result<Handle> {
+        error()
+    }
+
+    pub fn from_dir_entry_snapshot(
+        _entry: &std::fs::DirEntry,
+    ) -> io::Result<Handle> {
+        error()
+    }
+
+    pub fn from_path_canonical<P: AsRef<Path>>(_p: P) -> io::Result<Handle> {
+        error()
+    }
+
+    pub fn canonical_path(&self) -> Option<&Path> {
+        None
+    }
+
+    pub fn from_path_retry<P: AsRef<Path>>(
+        p: P,
+        _attempts: usize,
+        _delay: Duration,
+    ) -> io::Result<Handle> {
+        Handle::from_path(p)
+    }
+
+    pub fn from_dir_path<P: AsRef<Path>>(_p: P) -> io::Result<Handle> {
+        error()
+    }
+
     pub fn from_file(_file: File) -> io::Result<Handle> {
         error()
     }
@@ -38,15 +123,87 @@ impl Handle {
         error()
     }
 
+    pub fn into_file(self) -> Result<File, Handle> {
+        Err(self)
+    }
+
+    pub fn as_borrowed_file(&self) -> Option<&File> {
+        None
+    }
+
+    pub fn reopen(&self, _options: &std::fs::OpenOptions) -> io::Result<File> {
+        error()
+    }
+
+    pub fn try_clone(&self) -> io::Result<Handle> {
+        Ok(Handle::dangling())
+    }
+
+    pub fn try_clone_fresh(&self) -> io::Result<Handle> {
+        error()
+    }
+
+    pub fn modified_at_open(&self) -> Option<SystemTime> {
+        None
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        false
+    }
+
+    pub fn is_path_based(&self) -> bool {
+        false
+    }
+
+    pub fn kind(&self) -> crate::FileKind {
+        crate::FileKind::Other
+    }
+
+    pub fn key(&self) -> Option<Key> {
+        None
+    }
+
+    pub fn generation(&self) -> Option<u64> {
+        None
+    }
+
+    pub fn link_id(&self) -> io::Result<u64> {
+        Err(crate::unsupported("link identifiers"))
+    }
+
+    pub fn same_as_file(&self, _file: &File) -> io::Result<bool> {
+        error()
+    }
+
+    pub fn same_volume(&self, _other: &Handle) -> bool {
+        false
+    }
+
+    pub fn weak_eq(&self, _other: &Handle) -> bool {
+        false
+    }
+
+    pub fn same_inode(&self, _other: &Handle) -> bool {
+        false
+    }
+
+    pub fn raw(&self) -> RawDescriptor {
+        -1
+    }
+
     pub fn as_file(&self) -> &File {
-        unreachable!(ERROR_MESSAGE);
+        panic!("dangling handle has no file")
     }
 
     pub fn as_file_mut(&self) -> &mut File {
-        unreachable!(ERROR_MESSAGE);
+        panic!("dangling handle has no file")
     }
 }
 
+pub(crate) fn files_eq(_file1: &File, _file2: &File) -> io::Result<bool> {
+    error()
+}
+
 fn error<T>() -> io::Result<T> {
-    Err(io::Error::new(io::ErrorKind::Other, ERROR_MESSAGE))
+    Err(io::Error::new(io::ErrorKind::Unsupported, ERROR_MESSAGE))
 }
@@ -0,0 +1,140 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use io_uring::{opcode, types, IoUring, Probe};
+
+use crate::unix::Key;
+
+/// The number of `statx` operations submitted to the ring at once.
+///
+/// This bounds the memory used for in flight path names and `statx` buffers,
+/// regardless of how many paths are queried in total.
+const QUEUE_DEPTH: u32 = 256;
+
+pub fn keys<P: AsRef<Path>>(paths: &[P]) -> Vec<io::Result<Key>> {
+    let mut ring = ring();
+    let mut keys = Vec::with_capacity(paths.len());
+    for chunk in paths.chunks(QUEUE_DEPTH as usize) {
+        let chunk_keys = match ring {
+            Some(ref mut ring) => keys_chunk(ring, chunk).ok(),
+            None => None,
+        };
+        match chunk_keys {
+            Some(chunk_keys) => keys.extend(chunk_keys),
+            // Either there is no ring, or something went wrong with the ring
+            // itself rather than with any one path. In the latter case, it
+            // may still have operations in flight, so stop using it and
+            // answer the rest the slow way.
+            None => {
+                ring = None;
+                keys.extend(chunk.iter().map(key_sequential));
+            }
+        }
+    }
+    keys
+}
+
+/// Create a ring, if io_uring is available and supports `statx`.
+///
+/// `IORING_OP_STATX` was added in Linux 5.6, and io_uring may be disabled
+/// altogether (e.g., by `kernel.io_uring_disabled` or a seccomp filter).
+fn ring() -> Option<IoUring> {
+    let ring = IoUring::new(QUEUE_DEPTH).ok()?;
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe).ok()?;
+    if !probe.is_supported(opcode::Statx::CODE) {
+        return None;
+    }
+    Some(ring)
+}
+
+fn keys_chunk<P: AsRef<Path>>(
+    ring: &mut IoUring,
+    paths: &[P],
+) -> io::Result<Vec<io::Result<Key>>> {
+    // The kernel may read the path names and write the statx buffers at any
+    // point until the corresponding completion is reaped, so both must stay
+    // put until then. Neither vector is resized after this.
+    let mut results: Vec<Option<io::Result<Key>>> =
+        (0..paths.len()).map(|_| None).collect();
+    let mut names = Vec::with_capacity(paths.len());
+    for (i, p) in paths.iter().enumerate() {
+        match cstring(p.as_ref()) {
+            Ok(name) => names.push(Some(name)),
+            Err(err) => {
+                names.push(None);
+                results[i] = Some(Err(err));
+            }
+        }
+    }
+    let mut bufs: Vec<libc::statx> =
+        (0..paths.len()).map(|_| unsafe { mem::zeroed() }).collect();
+
+    let mut submitted = 0;
+    for (i, (name, buf)) in names.iter().zip(bufs.iter_mut()).enumerate() {
+        let name = match *name {
+            Some(ref name) => name,
+            None => continue,
+        };
+        let entry = opcode::Statx::new(
+            types::Fd(libc::AT_FDCWD),
+            name.as_ptr(),
+            buf as *mut libc::statx as *mut types::statx,
+        )
+        .flags(libc::AT_STATX_SYNC_AS_STAT)
+        .mask(libc::STATX_INO)
+        .build()
+        .user_data(i as u64);
+        // The submission queue has room for a whole chunk, so this can't
+        // fail.
+        unsafe { ring.submission().push(&entry) }
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        submitted += 1;
+    }
+
+    let mut reaped = 0;
+    while reaped < submitted {
+        if let Err(err) = ring.submit_and_wait(submitted - reaped) {
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            // Operations may still be in flight, and the kernel could write
+            // to their buffers at any time, so leak them rather than freeing
+            // them.
+            mem::forget(names);
+            mem::forget(bufs);
+            return Err(err);
+        }
+        for cqe in ring.completion() {
+            let i = cqe.user_data() as usize;
+            results[i] = Some(if cqe.result() < 0 {
+                Err(io::Error::from_raw_os_error(-cqe.result()))
+            } else {
+                Ok(key_from_statx(&bufs[i]))
+            });
+            reaped += 1;
+        }
+    }
+    // Every slot was filled in, either by a completion or by an error.
+    Ok(results.into_iter().map(|r| r.unwrap()).collect())
+}
+
+fn key_sequential<P: AsRef<Path>>(p: &P) -> io::Result<Key> {
+    fs::metadata(p).map(|md| Key::from_metadata(&md))
+}
+
+fn key_from_statx(buf: &libc::statx) -> Key {
+    // `stx_ino` is 64 bits wide on every target, so this is safe on 32-bit
+    // ones too.
+    let dev = libc::makedev(buf.stx_dev_major, buf.stx_dev_minor);
+    Key::new(dev as u64, buf.stx_ino)
+}
+
+fn cstring(p: &Path) -> io::Result<CString> {
+    CString::new(p.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
@@ -1,10 +1,39 @@
+use std::ffi::OsString;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io;
-use std::os::windows::io::{AsRawHandle, IntoRawHandle, RawHandle};
-use std::path::Path;
+use std::mem;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{
+    AsHandle, AsRawHandle, FromRawHandle, IntoRawHandle, RawHandle,
+};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use winapi_util as winutil;
+use windows_sys::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_CANT_RESOLVE_FILENAME, ERROR_DIRECTORY,
+    ERROR_FILE_NOT_FOUND, ERROR_INVALID_FUNCTION, ERROR_INVALID_PARAMETER,
+    ERROR_LOCK_VIOLATION, ERROR_MORE_DATA, ERROR_NOT_A_REPARSE_POINT,
+    ERROR_NOT_SUPPORTED, ERROR_SHARING_VIOLATION,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    FileAttributeTagInfo, FileCaseSensitiveInfo, FileIdInfo,
+    GetFileInformationByHandleEx, GetFinalPathNameByHandleW, GetLongPathNameW,
+    GetVolumePathNamesForVolumeNameW, FILE_ATTRIBUTE_DIRECTORY,
+    FILE_ATTRIBUTE_REPARSE_POINT, FILE_ATTRIBUTE_SPARSE_FILE,
+    FILE_ATTRIBUTE_TAG_INFO, FILE_FLAG_BACKUP_SEMANTICS,
+    FILE_FLAG_OPEN_REPARSE_POINT, FILE_ID_INFO, FILE_NAME_NORMALIZED,
+    FILE_READ_ATTRIBUTES, MAXIMUM_REPARSE_DATA_BUFFER_SIZE, VOLUME_NAME_DOS,
+    VOLUME_NAME_GUID,
+};
+use windows_sys::Win32::System::Ioctl::{
+    FILE_OBJECTID_BUFFER, FSCTL_GET_OBJECT_ID, FSCTL_GET_REPARSE_POINT,
+};
+use windows_sys::Win32::System::IO::{CancelSynchronousIo, DeviceIoControl};
 
 // For correctness, it is critical that both file handles remain open while
 // their attributes are checked for equality. In particular, the file index
@@ -20,16 +49,15 @@ use winapi_util as winutil;
 // documented here:
 // https://msdn.microsoft.com/en-us/library/windows/desktop/hh802691(v=vs.85).aspx
 //
-// It seems straight-forward enough to modify this code to use
-// `FILE_ID_INFO` when available (minimum Windows Server 2012), but I don't
-// have access to such Windows machines.
+// So this code uses `FILE_ID_INFO` when it is available (minimum Windows
+// Server 2012), and falls back to `nFileIndex{Low,High}` otherwise.
 //
 // Two notes.
 //
-// 1. Java's NIO uses the approach implemented here and appears to ignore
-//    `FILE_ID_INFO` altogether. So Java's NIO and this code are
-//    susceptible to bugs when running on a file system where
-//    `nFileIndex{Low,High}` are not unique.
+// 1. Java's NIO only uses `nFileIndex{Low,High}` and appears to ignore
+//    `FILE_ID_INFO` altogether. So Java's NIO is susceptible to bugs when
+//    running on a file system where `nFileIndex{Low,High}` are not unique,
+//    and so is this code on systems that lack `FILE_ID_INFO`.
 //
 // 2. LLVM has a bug where they fetch the id of a file and continue to use
 //    it even after the handle has been closed, so that uniqueness is no
@@ -47,10 +75,20 @@ use winapi_util as winutil;
 // into the offending directory. As far as failure modes goes, this isn't
 // that bad.
 
+pub(crate) type RawDescriptor = RawHandle;
+
 #[derive(Debug)]
 pub struct Handle {
     kind: HandleKind,
     key: Option<Key>,
+    // The modification time when the handle was constructed, if it has a
+    // key.
+    modified: Option<SystemTime>,
+    // The canonical path the handle was opened with, if any.
+    path: Option<PathBuf>,
+    // The type of the file, which lets comparisons between a directory and
+    // anything else bail early.
+    file_kind: crate::FileKind,
 }
 
 #[derive(Debug)]
@@ -59,12 +97,73 @@ enum HandleKind {
     Owned(winutil::Handle),
     /// Used for stdio.
     Borrowed(winutil::HandleRef),
+    /// Used for placeholders, which have no handle at all. The number is
+    /// only there to spread them out when hashed.
+    Dangling(u64),
+    /// Used for files that could not be opened at all, whose identity is
+    /// their canonical path instead, which is in `Handle::path`.
+    Path,
 }
 
-#[derive(Debug, Eq, PartialEq, Hash)]
-struct Key {
+// The index is 128 bits wide to accommodate ReFS, whose file identifiers
+// don't fit in 64 bits.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialOrd)]
+pub struct Key {
     volume: u64,
-    index: u64,
+    index: u128,
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        // Files on different volumes are never equal, and comparing the
+        // volume first lets us bail before comparing the (wider) index.
+        if self.volume != other.volume {
+            return false;
+        }
+        self.index == other.index
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // All 128 bits of the index go into the hasher, so ReFS identifiers
+        // from `FILE_ID_INFO` (see `Key::from_info`) that only differ in
+        // their high 64 bits don't collide. Without `FILE_ID_INFO`, the high
+        // bits are always zero.
+        self.volume.hash(state);
+        self.index.hash(state);
+    }
+}
+
+impl Key {
+    pub(crate) fn new(volume: u64, index: u128) -> Key {
+        Key { volume, index }
+    }
+
+    /// Build the key of the file with the given handle and information.
+    ///
+    /// The file index in the information is only 64 bits wide, so the full
+    /// identifier is queried from `FILE_ID_INFO` instead. That needs
+    /// Windows 8 (or Server 2012) and a file system that supports it, and
+    /// the file index is used otherwise. On NTFS, both are the same number.
+    ///
+    /// The volume serial number in `FILE_ID_INFO` is 64 bits wide, but only
+    /// the 32 bits that the information has are used, so that keys don't
+    /// depend on which of the two was available.
+    fn from_info(handle: RawHandle, info: &winutil::file::Information) -> Key {
+        let index = file_id(handle).unwrap_or(u128::from(info.file_index()));
+        Key::new(info.volume_serial_number(), index)
+    }
+
+    pub fn parts(&self) -> (u64, u128) {
+        (self.volume, self.index)
+    }
+
+    /// The inverse of `parts`.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(volume: u64, index: u128) -> Option<Key> {
+        Some(Key::new(volume, index))
+    }
 }
 
 impl Eq for Handle {}
@@ -73,29 +172,51 @@ impl PartialEq for Handle {
     fn eq(&self, other: &Handle) -> bool {
         // Need this branch to satisfy `Eq` since `Handle`s with
         // `key.is_none()` wouldn't otherwise.
-        if self as *const Handle == other as *const Handle {
+        if std::ptr::eq(self, other) {
             return true;
+        } else if let (HandleKind::Path, HandleKind::Path) =
+            (&self.kind, &other.kind)
+        {
+            let equal = same_path(&self.path, &other.path);
+            event!(target: "same_file::compare", tracing::Level::TRACE, equal);
+            return equal;
         } else if self.key.is_none() || other.key.is_none() {
+            event!(
+                target: "same_file::compare",
+                tracing::Level::TRACE,
+                equal = false,
+                keyless = true,
+            );
             return false;
         }
-        self.key == other.key
+        let equal = self.file_kind == other.file_kind && self.key == other.key;
+        event!(target: "same_file::compare", tracing::Level::TRACE, equal);
+        equal
     }
 }
 
 impl AsRawHandle for crate::Handle {
     fn as_raw_handle(&self) -> RawHandle {
-        match self.0.kind {
-            HandleKind::Owned(ref h) => h.as_raw_handle(),
-            HandleKind::Borrowed(ref h) => h.as_raw_handle(),
-        }
+        self.0.raw_handle()
     }
 }
 
+impl AsRawHandle for crate::RawDescriptor {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0
+    }
+}
+
+// A raw handle is just a number as far as a `RawDescriptor` is concerned.
+unsafe impl Send for crate::RawDescriptor {}
+unsafe impl Sync for crate::RawDescriptor {}
+
 impl IntoRawHandle for crate::Handle {
     fn into_raw_handle(self) -> RawHandle {
         match self.0.kind {
             HandleKind::Owned(h) => h.into_raw_handle(),
             HandleKind::Borrowed(h) => h.as_raw_handle(),
+            HandleKind::Dangling(_) | HandleKind::Path => ptr::null_mut(),
         }
     }
 }
@@ -103,22 +224,302 @@ impl IntoRawHandle for crate::Handle {
 impl Hash for Handle {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.key.hash(state);
+        match self.kind {
+            HandleKind::Dangling(id) => id.hash(state),
+            // Equal paths may differ in case (see `same_path`).
+            HandleKind::Path => self
+                .path
+                .as_ref()
+                .map(|p| p.as_os_str().to_ascii_lowercase())
+                .hash(state),
+            HandleKind::Owned(_) | HandleKind::Borrowed(_) => {}
+        }
+    }
+}
+
+/// Returns true if the given canonical paths of path-based handles refer to
+/// the same file.
+///
+/// Paths are case insensitive on Windows (with rare exceptions, see
+/// `is_case_sensitive_dir`). Only ASCII letters are folded here, which errs
+/// on the side of calling two paths different. Canonical paths have the case
+/// of the names on disk anyway, except for names that `from_path_or_canonical`
+/// couldn't look up.
+fn same_path(p1: &Option<PathBuf>, p2: &Option<PathBuf>) -> bool {
+    match (p1, p2) {
+        (Some(p1), Some(p2)) => p1.as_os_str().eq_ignore_ascii_case(p2),
+        _ => false,
+    }
+}
+
+/// Returns true if the given path is in the Win32 device namespace, i.e.,
+/// starts with `\\.\`. Either kind of slash is accepted, like Windows does.
+fn is_device_path(p: &Path) -> bool {
+    let slash = |c: u16| c == u16::from(b'\\') || c == u16::from(b'/');
+    let prefix: Vec<u16> = p.as_os_str().encode_wide().take(4).collect();
+    match prefix[..] {
+        [a, b, dot, c] => {
+            slash(a) && slash(b) && dot == u16::from(b'.') && slash(c)
+        }
+        _ => false,
     }
 }
 
 impl Handle {
     pub fn from_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
-        let h = winutil::Handle::from_path_any(p)?;
+        Handle::from_path_detailed(p).map_err(io::Error::from)
+    }
+
+    pub fn from_path_detailed<P: AsRef<Path>>(
+        p: P,
+    ) -> Result<Handle, crate::Error> {
+        if is_device_path(p.as_ref()) {
+            return Handle::from_device_path(p.as_ref());
+        }
+        let h =
+            winutil::Handle::from_path_any(p).map_err(crate::Error::open)?;
+        let info =
+            winutil::file::information(&h).map_err(crate::Error::stat)?;
+        Ok(Handle::from_info(HandleKind::Owned(h), info))
+    }
+
+    /// Open a path in the Win32 device namespace, such as `\\.\C:` or
+    /// `\\.\PhysicalDrive0`.
+    ///
+    /// Reading from a volume or disk requires administrator privileges, but
+    /// reading its attributes doesn't, so only that is asked for. Devices
+    /// without a file system (e.g., `\\.\NUL`) have no file index, and get
+    /// a keyless handle like in `from_file`.
+    fn from_device_path(p: &Path) -> Result<Handle, crate::Error> {
+        use std::fs::OpenOptions;
+        use std::os::windows::fs::OpenOptionsExt;
+
+        let file = OpenOptions::new()
+            .access_mode(FILE_READ_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(p)
+            .map_err(crate::Error::open)?;
+        let h = winutil::Handle::from_file(file);
+        match winutil::file::information(&h) {
+            Ok(info) => Ok(Handle::from_info(HandleKind::Owned(h), info)),
+            Err(ref err) if is_not_a_file(err) => {
+                Ok(Handle::keyless(HandleKind::Owned(h)))
+            }
+            Err(err) => Err(crate::Error::stat(err)),
+        }
+    }
+
+    pub fn from_path_with<P: AsRef<Path>>(
+        p: P,
+        opts: &crate::HandleOptions,
+    ) -> io::Result<Handle> {
+        use std::fs::OpenOptions;
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // This is what `from_path_any` does, except that when not following
+        // symbolic links, the reparse point itself is opened.
+        let mut flags = 0;
+        if opts.backup_semantics {
+            flags |= FILE_FLAG_BACKUP_SEMANTICS;
+        }
+        if !opts.follow_symlinks {
+            flags |= FILE_FLAG_OPEN_REPARSE_POINT;
+        }
+        let file =
+            OpenOptions::new().read(true).custom_flags(flags).open(p)?;
+        let h = winutil::Handle::from_file(file);
         let info = winutil::file::information(&h)?;
+        if !opts.follow_symlinks && is_name_surrogate(&h, &info)? {
+            return Err(io::Error::from_raw_os_error(
+                ERROR_CANT_RESOLVE_FILENAME as i32,
+            ));
+        }
+        if opts.require_directory
+            && info.file_attributes() & FILE_ATTRIBUTE_DIRECTORY as u64 == 0
+        {
+            return Err(io::Error::from_raw_os_error(ERROR_DIRECTORY as i32));
+        }
         Ok(Handle::from_info(HandleKind::Owned(h), info))
     }
 
-    pub fn from_file(file: File) -> io::Result<Handle> {
+    pub fn from_link<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        use std::fs::OpenOptions;
+        use std::os::windows::fs::OpenOptionsExt;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT,
+            )
+            .open(p)?;
         let h = winutil::Handle::from_file(file);
         let info = winutil::file::information(&h)?;
+        if !is_name_surrogate(&h, &info)? {
+            return Err(io::Error::from_raw_os_error(
+                ERROR_NOT_A_REPARSE_POINT as i32,
+            ));
+        }
+        Ok(Handle::from_info(HandleKind::Owned(h), info))
+    }
+
+    pub fn from_dir_entry(entry: &std::fs::DirEntry) -> io::Result<Handle> {
+        Handle::from_path(entry.path())
+    }
+
+    pub fn from_dir_entry_snapshot(
+        entry: &std::fs::DirEntry,
+    ) -> io::Result<Handle> {
+        Handle::from_dir_entry(entry)
+    }
+
+    pub fn from_path_canonical<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        let p = std::fs::canonicalize(p)?;
+        let mut h = Handle::from_path(&p)?;
+        h.path = Some(p);
+        Ok(h)
+    }
+
+    pub fn canonical_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn from_path_or_canonical<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        Handle::path_fallback(p.as_ref(), |p| Handle::from_path(p))
+    }
+
+    /// Construct a handle with the given function, falling back to a
+    /// path-based handle if that is denied access.
+    ///
+    /// This is split out of `from_path_or_canonical` so that tests can
+    /// pretend that opening a file is denied.
+    pub(crate) fn path_fallback<F>(p: &Path, open: F) -> io::Result<Handle>
+    where
+        F: FnOnce(&Path) -> io::Result<Handle>,
+    {
+        let err = match open(p) {
+            Ok(h) => return Ok(h),
+            Err(err) => err,
+        };
+        if err.raw_os_error() != Some(ERROR_ACCESS_DENIED as i32) {
+            return Err(err);
+        }
+        // `canonicalize` opens the file too, but without asking for any
+        // access to it, which may be allowed when opening it for reading
+        // attributes isn't. Failing that, the directory it is in may be
+        // more permissive. Its canonical path has no 8.3 short names in it,
+        // but the file name may still be one, which is only expanded if the
+        // directory can be listed.
+        let path = match std::fs::canonicalize(p) {
+            Ok(path) => path,
+            Err(_) => match (p.parent(), p.file_name()) {
+                (Some(dir), Some(name)) => match std::fs::canonicalize(dir) {
+                    Ok(dir) => {
+                        let path = dir.join(name);
+                        long_path_name(&path).unwrap_or(path)
+                    }
+                    Err(_) => return Err(err),
+                },
+                _ => return Err(err),
+            },
+        };
+        let md = std::fs::metadata(&path).ok();
+        Ok(Handle {
+            kind: HandleKind::Path,
+            key: None,
+            // Like other handles without an identity, this doesn't record
+            // a modification time to go with it.
+            modified: None,
+            file_kind: match md {
+                Some(ref md) if md.is_dir() => crate::FileKind::Dir,
+                Some(ref md) if md.is_file() => crate::FileKind::File,
+                _ => crate::FileKind::Other,
+            },
+            path: Some(path),
+        })
+    }
+
+    pub fn from_path_retry<P: AsRef<Path>>(
+        p: P,
+        attempts: usize,
+        delay: Duration,
+    ) -> io::Result<Handle> {
+        let p = p.as_ref();
+        let mut remaining = attempts.max(1);
+        loop {
+            remaining -= 1;
+            match Handle::from_path(p) {
+                Err(ref err) if remaining > 0 && is_sharing_error(err) => {
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn from_path_timeout<P: AsRef<Path>>(
+        p: P,
+        timeout: Duration,
+    ) -> io::Result<Handle> {
+        let p = p.as_ref().to_path_buf();
+        let (tx, rx) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("same-file-open".to_string())
+            .spawn(move || {
+                // If we already gave up on this open, then nobody is
+                // receiving, and the handle is simply closed.
+                let _ = tx.send(Handle::from_path(p));
+            })?;
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // This is best effort. Not every file system driver supports
+                // cancelling an open, in which case the worker stays blocked
+                // until the open fails on its own, and then exits.
+                unsafe {
+                    CancelSynchronousIo(worker.as_raw_handle() as _);
+                }
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out while opening file",
+                ))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "thread opening file panicked",
+            )),
+        }
+    }
+
+    pub fn from_dir_path<P: AsRef<Path>>(p: P) -> io::Result<Handle> {
+        // `from_path_any` already opens with `FILE_FLAG_BACKUP_SEMANTICS`,
+        // which is what permits opening directories in the first place. All
+        // that's left is to reject anything that isn't one.
+        let h = winutil::Handle::from_path_any(p)?;
+        let info = winutil::file::information(&h)?;
+        if info.file_attributes() & FILE_ATTRIBUTE_DIRECTORY as u64 == 0 {
+            return Err(io::Error::from_raw_os_error(ERROR_DIRECTORY as i32));
+        }
         Ok(Handle::from_info(HandleKind::Owned(h), info))
     }
 
+    pub fn from_as_handle<T: AsHandle>(t: &T) -> io::Result<Handle> {
+        Handle::from_file(File::from(t.as_handle().try_clone_to_owned()?))
+    }
+
+    pub fn from_file(file: File) -> io::Result<Handle> {
+        let h = winutil::Handle::from_file(file);
+        match winutil::file::information(&h) {
+            Ok(info) => Ok(Handle::from_info(HandleKind::Owned(h), info)),
+            // Pipes, character devices and the like aren't files, so they
+            // have no identity to speak of. Treat them like the stdio
+            // handles below rather than refusing to wrap them.
+            Err(ref err) if is_not_a_file(err) => {
+                Ok(Handle::keyless(HandleKind::Owned(h)))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     fn from_std_handle(h: winutil::HandleRef) -> io::Result<Handle> {
         match winutil::file::information(&h) {
             Ok(info) => Ok(Handle::from_info(HandleKind::Borrowed(h), info)),
@@ -127,7 +528,34 @@ impl Handle {
             // We don't really care. The only thing we care about is that
             // this handle is never equivalent to any other handle, which is
             // accomplished by setting key to None.
-            Err(_) => Ok(Handle { kind: HandleKind::Borrowed(h), key: None }),
+            Err(_) => Ok(Handle::keyless(HandleKind::Borrowed(h))),
+        }
+    }
+
+    pub fn dangling() -> Handle {
+        Handle {
+            kind: HandleKind::Dangling(crate::dangling_id()),
+            key: None,
+            modified: None,
+            path: None,
+            file_kind: crate::FileKind::Other,
+        }
+    }
+
+    /// Build a handle without an identity, which is never equivalent to any
+    /// other handle.
+    fn keyless(kind: HandleKind) -> Handle {
+        event!(
+            target: "same_file::information",
+            tracing::Level::TRACE,
+            key = ?None::<Key>,
+        );
+        Handle {
+            kind,
+            key: None,
+            modified: None,
+            path: None,
+            file_kind: crate::FileKind::Other,
         }
     }
 
@@ -135,13 +563,27 @@ impl Handle {
         kind: HandleKind,
         info: winutil::file::Information,
     ) -> Handle {
-        Handle {
-            kind: kind,
-            key: Some(Key {
-                volume: info.volume_serial_number(),
-                index: info.file_index(),
-            }),
-        }
+        let file_kind =
+            if info.file_attributes() & FILE_ATTRIBUTE_DIRECTORY as u64 != 0 {
+                crate::FileKind::Dir
+            } else {
+                crate::FileKind::File
+            };
+        let mut handle = Handle {
+            kind,
+            key: None,
+            modified: info.last_write_time().and_then(filetime_to_system_time),
+            path: None,
+            file_kind,
+        };
+        let key = Key::from_info(handle.raw_handle(), &info);
+        event!(
+            target: "same_file::information",
+            tracing::Level::TRACE,
+            key = ?Some(key),
+        );
+        handle.key = Some(key);
+        handle
     }
 
     pub fn stdin() -> io::Result<Handle> {
@@ -156,17 +598,504 @@ impl Handle {
         Handle::from_std_handle(winutil::HandleRef::stderr())
     }
 
+    pub fn raw(&self) -> RawHandle {
+        self.raw_handle()
+    }
+
     pub fn as_file(&self) -> &File {
-        match self.kind {
-            HandleKind::Owned(ref h) => h.as_file(),
-            HandleKind::Borrowed(ref h) => h.as_file(),
-        }
+        // This only panics for dangling and path-based handles, which is
+        // documented.
+        self.file().expect("dangling or path-based handle has no file")
     }
 
     pub fn as_file_mut(&mut self) -> &mut File {
         match self.kind {
             HandleKind::Owned(ref mut h) => h.as_file_mut(),
             HandleKind::Borrowed(ref mut h) => h.as_file_mut(),
+            // This only panics for dangling and path-based handles, which is
+            // documented.
+            HandleKind::Dangling(_) => panic!("dangling handle has no file"),
+            HandleKind::Path => panic!("path-based handle has no file"),
+        }
+    }
+
+    /// Return the underlying file, or an error for dangling handles.
+    fn file(&self) -> io::Result<&File> {
+        match self.kind {
+            HandleKind::Owned(ref h) => Ok(h.as_file()),
+            HandleKind::Borrowed(ref h) => Ok(h.as_file()),
+            HandleKind::Dangling(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "dangling handle has no open file",
+            )),
+            HandleKind::Path => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "path-based handle has no open file",
+            )),
+        }
+    }
+
+    pub fn into_file(self) -> Result<File, Handle> {
+        match self.kind {
+            HandleKind::Owned(h) => {
+                Ok(unsafe { File::from_raw_handle(h.into_raw_handle()) })
+            }
+            HandleKind::Borrowed(_)
+            | HandleKind::Dangling(_)
+            | HandleKind::Path => Err(self),
+        }
+    }
+
+    pub fn as_borrowed_file(&self) -> Option<&File> {
+        match self.kind {
+            HandleKind::Owned(_)
+            | HandleKind::Dangling(_)
+            | HandleKind::Path => None,
+            HandleKind::Borrowed(ref h) => Some(h.as_file()),
+        }
+    }
+
+    pub fn reopen(&self, options: &std::fs::OpenOptions) -> io::Result<File> {
+        let file = options.open(self.final_path_verbatim()?)?;
+        // The path may have been replaced since it was looked up.
+        let info = winutil::file::information(&file)?;
+        if self.key != Some(Key::from_info(file.as_raw_handle(), &info)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "file was replaced while being reopened",
+            ));
+        }
+        Ok(file)
+    }
+
+    pub fn try_clone(&self) -> io::Result<Handle> {
+        match self.kind {
+            HandleKind::Dangling(_) => return Ok(Handle::dangling()),
+            HandleKind::Path => {
+                return Ok(Handle {
+                    kind: HandleKind::Path,
+                    key: None,
+                    modified: self.modified,
+                    path: self.path.clone(),
+                    file_kind: self.file_kind,
+                })
+            }
+            HandleKind::Owned(_) | HandleKind::Borrowed(_) => {}
+        }
+        let h = winutil::Handle::from_file(self.as_file().try_clone()?);
+        Ok(Handle {
+            kind: HandleKind::Owned(h),
+            key: self.key,
+            modified: self.modified,
+            path: self.path.clone(),
+            file_kind: self.file_kind,
+        })
+    }
+
+    pub fn try_clone_fresh(&self) -> io::Result<Handle> {
+        let mut h = Handle::from_file(self.file()?.try_clone()?)?;
+        h.path = self.path.clone();
+        Ok(h)
+    }
+
+    pub fn modified_at_open(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        false
+    }
+
+    pub fn is_path_based(&self) -> bool {
+        matches!(self.kind, HandleKind::Path)
+    }
+
+    pub fn kind(&self) -> crate::FileKind {
+        self.file_kind
+    }
+
+    pub fn key(&self) -> Option<Key> {
+        self.key
+    }
+
+    /// File indexes have no generation numbers on Windows.
+    pub fn generation(&self) -> Option<u64> {
+        None
+    }
+
+    pub fn link_id(&self) -> io::Result<u64> {
+        Err(crate::unsupported("link identifiers"))
+    }
+
+    pub fn same_as_file(&self, file: &File) -> io::Result<bool> {
+        let info = winutil::file::information(file)?;
+        Ok(self.key == Some(Key::from_info(file.as_raw_handle(), &info)))
+    }
+
+    pub fn same_volume(&self, other: &Handle) -> bool {
+        match (&self.key, &other.key) {
+            (Some(k1), Some(k2)) => k1.volume == k2.volume,
+            _ => false,
+        }
+    }
+
+    pub fn weak_eq(&self, other: &Handle) -> bool {
+        match (&self.key, &other.key) {
+            (Some(_), Some(_)) => self == other,
+            // Dangling handles have no handle to compare.
+            (None, None) => {
+                !self.raw_handle().is_null()
+                    && self.raw_handle() == other.raw_handle()
+            }
+            _ => false,
+        }
+    }
+
+    fn raw_handle(&self) -> RawHandle {
+        match self.kind {
+            HandleKind::Owned(ref h) => h.as_raw_handle(),
+            HandleKind::Borrowed(ref h) => h.as_raw_handle(),
+            HandleKind::Dangling(_) | HandleKind::Path => ptr::null_mut(),
+        }
+    }
+
+    pub fn same_inode(&self, other: &Handle) -> bool {
+        match (&self.key, &other.key) {
+            (Some(k1), Some(k2)) => k1.index == k2.index,
+            _ => false,
+        }
+    }
+
+    pub fn final_path(&self) -> io::Result<PathBuf> {
+        let path = self.final_path_verbatim()?;
+        Ok(strip_verbatim(&path).unwrap_or(path))
+    }
+
+    pub fn final_path_verbatim(&self) -> io::Result<PathBuf> {
+        let path = final_path_name(
+            self.file()?.as_raw_handle(),
+            FILE_NAME_NORMALIZED | VOLUME_NAME_DOS,
+        )?;
+        Ok(PathBuf::from(OsString::from_wide(&path)))
+    }
+
+    pub fn volume_path(&self) -> io::Result<PathBuf> {
+        let path = final_path_name(
+            self.file()?.as_raw_handle(),
+            FILE_NAME_NORMALIZED | VOLUME_NAME_GUID,
+        )?;
+        // The path looks like `\\?\Volume{GUID}\some\file`, so the volume
+        // name is everything up to and including the fourth separator.
+        let sep = u16::from(b'\\');
+        let end =
+            match path.iter().enumerate().filter(|&(_, &c)| c == sep).nth(3) {
+                Some((i, _)) => i + 1,
+                None => path.len(),
+            };
+        let mut volume = path[..end].to_vec();
+        volume.push(0);
+        match volume_mount_path(&volume)? {
+            Some(mount) => Ok(mount),
+            None => Ok(PathBuf::from(OsString::from_wide(&path[..end]))),
+        }
+    }
+
+    pub fn ntfs_object_id(&self) -> io::Result<Option<[u8; 16]>> {
+        let mut buf: FILE_OBJECTID_BUFFER = unsafe { mem::zeroed() };
+        let mut returned = 0;
+        let ok = unsafe {
+            DeviceIoControl(
+                self.file()?.as_raw_handle(),
+                FSCTL_GET_OBJECT_ID,
+                ptr::null(),
+                0,
+                &mut buf as *mut FILE_OBJECTID_BUFFER as *mut _,
+                mem::size_of::<FILE_OBJECTID_BUFFER>() as u32,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if ok != 0 {
+            return Ok(Some(buf.ObjectId));
+        }
+        let err = io::Error::last_os_error();
+        // NTFS reports a file without an object id as "not found", which is
+        // distinct from the handle itself being invalid.
+        if err.raw_os_error() == Some(ERROR_FILE_NOT_FOUND as i32) {
+            return Ok(None);
+        }
+        Err(err)
+    }
+
+    pub fn reparse_tag(&self) -> io::Result<Option<u32>> {
+        // The reparse data starts with the tag, and is followed by data
+        // that's specific to it, which we have no use for. But asking for
+        // less than all of it fails with ERROR_MORE_DATA.
+        let mut buf = vec![0u8; MAXIMUM_REPARSE_DATA_BUFFER_SIZE as usize];
+        let mut returned = 0;
+        let ok = unsafe {
+            DeviceIoControl(
+                self.file()?.as_raw_handle(),
+                FSCTL_GET_REPARSE_POINT,
+                ptr::null(),
+                0,
+                buf.as_mut_ptr() as *mut _,
+                buf.len() as u32,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if ok != 0 {
+            let tag = [buf[0], buf[1], buf[2], buf[3]];
+            return Ok(Some(u32::from_le_bytes(tag)));
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(ERROR_NOT_A_REPARSE_POINT as i32) {
+            return Ok(None);
+        }
+        Err(err)
+    }
+
+    pub fn is_named_pipe(&self) -> io::Result<bool> {
+        match self.kind {
+            HandleKind::Dangling(_) | HandleKind::Path => Ok(false),
+            HandleKind::Owned(_) | HandleKind::Borrowed(_) => {
+                Ok(winutil::file::typ(self.file()?)?.is_pipe())
+            }
+        }
+    }
+
+    pub fn is_case_sensitive_dir(&self) -> io::Result<bool> {
+        // FILE_CASE_SENSITIVE_INFO and FILE_CS_FLAG_CASE_SENSITIVE_DIR from
+        // winbase.h and winnt.h.
+        #[repr(C)]
+        struct CaseSensitiveInfo {
+            flags: u32,
+        }
+        const FILE_CS_FLAG_CASE_SENSITIVE_DIR: u32 = 1;
+
+        if self.file_kind != crate::FileKind::Dir {
+            return Err(io::Error::from_raw_os_error(ERROR_DIRECTORY as i32));
+        }
+        let mut info = CaseSensitiveInfo { flags: 0 };
+        if unsafe {
+            GetFileInformationByHandleEx(
+                self.raw_handle() as _,
+                FileCaseSensitiveInfo,
+                &mut info as *mut CaseSensitiveInfo as *mut _,
+                mem::size_of::<CaseSensitiveInfo>() as u32,
+            )
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info.flags & FILE_CS_FLAG_CASE_SENSITIVE_DIR != 0)
+    }
+
+    pub fn is_sparse(&self) -> io::Result<bool> {
+        let info = winutil::file::information(self.file()?)?;
+        Ok(info.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE as u64 != 0)
+    }
+}
+
+/// Returns true if the error is a transient sharing or lock violation, which
+/// is typically caused by another process (like a virus scanner or an
+/// indexer) briefly holding the file open.
+fn is_sharing_error(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => {
+            code == ERROR_SHARING_VIOLATION as i32
+                || code == ERROR_LOCK_VIOLATION as i32
+        }
+        None => false,
+    }
+}
+
+/// Returns true if the two files are the same file, comparing their
+/// identities directly instead of building a `Handle` for each of them.
+pub(crate) fn files_eq(file1: &File, file2: &File) -> io::Result<bool> {
+    // Like in `Handle::from_file`, things that aren't files have no
+    // identity, and so are never the same as anything.
+    let key = |file: &File| match winutil::file::information(file) {
+        Ok(info) => Ok(Some(Key::from_info(file.as_raw_handle(), &info))),
+        Err(ref err) if is_not_a_file(err) => Ok(None),
+        Err(err) => Err(err),
+    };
+    match (key(file1)?, key(file2)?) {
+        (Some(k1), Some(k2)) => Ok(k1 == k2),
+        _ => Ok(false),
+    }
+}
+
+/// Returns true if the error is how `GetFileInformationByHandle` refuses
+/// handles that don't refer to a file (e.g., pipes or devices), as opposed to
+/// a genuine failure.
+fn is_not_a_file(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => {
+            code == ERROR_INVALID_FUNCTION as i32
+                || code == ERROR_INVALID_PARAMETER as i32
+                || code == ERROR_NOT_SUPPORTED as i32
+        }
+        None => false,
+    }
+}
+
+/// Return the 128-bit identifier of the file with the given handle from
+/// `FILE_ID_INFO`, or `None` if it isn't available.
+fn file_id(handle: RawHandle) -> Option<u128> {
+    let mut info: FILE_ID_INFO = unsafe { mem::zeroed() };
+    if unsafe {
+        GetFileInformationByHandleEx(
+            handle as _,
+            FileIdInfo,
+            &mut info as *mut FILE_ID_INFO as *mut _,
+            mem::size_of::<FILE_ID_INFO>() as u32,
+        )
+    } == 0
+    {
+        return None;
+    }
+    Some(u128::from_le_bytes(info.FileId.Identifier))
+}
+
+/// Convert a `FILETIME`, as a number of 100 nanosecond intervals since
+/// 1601-01-01 UTC, to a `SystemTime`.
+fn filetime_to_system_time(ft: u64) -> Option<SystemTime> {
+    // The number of seconds between 1601-01-01 and 1970-01-01.
+    const EPOCH_DIFFERENCE: u64 = 11_644_473_600;
+
+    let since_1601 =
+        Duration::new(ft / 10_000_000, (ft % 10_000_000) as u32 * 100);
+    (SystemTime::UNIX_EPOCH - Duration::from_secs(EPOCH_DIFFERENCE))
+        .checked_add(since_1601)
+}
+
+/// Returns true if the given handle is of a reparse point that stands in for
+/// another file, such as a symbolic link, a junction or a volume mount point.
+///
+/// Other reparse points (e.g., deduplicated or cloud files) are just files
+/// with special storage, so they don't count.
+fn is_name_surrogate(
+    h: &winutil::Handle,
+    info: &winutil::file::Information,
+) -> io::Result<bool> {
+    // IsReparseTagNameSurrogate from winnt.h.
+    const NAME_SURROGATE_BIT: u32 = 0x2000_0000;
+
+    if info.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT as u64 == 0 {
+        return Ok(false);
+    }
+    let mut tag: FILE_ATTRIBUTE_TAG_INFO = unsafe { mem::zeroed() };
+    if unsafe {
+        GetFileInformationByHandleEx(
+            h.as_raw_handle() as _,
+            FileAttributeTagInfo,
+            &mut tag as *mut FILE_ATTRIBUTE_TAG_INFO as *mut _,
+            mem::size_of::<FILE_ATTRIBUTE_TAG_INFO>() as u32,
+        )
+    } == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(tag.ReparseTag & NAME_SURROGATE_BIT != 0)
+}
+
+/// Return the path of the file referred to by the given handle, formatted
+/// according to the given `GetFinalPathNameByHandleW` flags.
+///
+/// The returned path is not NUL terminated.
+fn final_path_name(h: RawHandle, flags: u32) -> io::Result<Vec<u16>> {
+    let mut buf = vec![0u16; 260];
+    loop {
+        let len = unsafe {
+            GetFinalPathNameByHandleW(
+                h,
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                flags,
+            )
+        } as usize;
+        if len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // On success, the length excludes the NUL terminator. When the
+        // buffer is too small, the length is the size required including
+        // the NUL terminator, so this can't be confused with success.
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+        buf.resize(len, 0);
+    }
+}
+
+/// Expand the 8.3 short names in the given path (e.g., `PROGRA~1`) to the
+/// long names they stand for, with `GetLongPathNameW`.
+///
+/// Unlike canonicalizing the path, this doesn't open the file, only the
+/// directories leading to it, which are listed to find the long names.
+pub(crate) fn long_path_name(p: &Path) -> io::Result<PathBuf> {
+    let wide: Vec<u16> = p.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut buf = vec![0u16; 260];
+    loop {
+        let len = unsafe {
+            GetLongPathNameW(wide.as_ptr(), buf.as_mut_ptr(), buf.len() as u32)
+        } as usize;
+        if len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Like with `GetFinalPathNameByHandleW`, the length includes the
+        // NUL terminator only when the buffer is too small.
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(PathBuf::from(OsString::from_wide(&buf)));
+        }
+        buf.resize(len, 0);
+    }
+}
+
+/// Convert a verbatim path to the equivalent conventional path.
+///
+/// That is, `\\?\C:\foo` becomes `C:\foo` and `\\?\UNC\server\share`
+/// becomes `\\server\share`. If the path isn't a verbatim path, then this
+/// returns `None`.
+fn strip_verbatim(path: &Path) -> Option<PathBuf> {
+    let path = path.to_str()?;
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        return Some(PathBuf::from(format!(r"\\{}", rest)));
+    }
+    path.strip_prefix(r"\\?\").map(PathBuf::from)
+}
+
+/// Return the first path at which the given NUL terminated volume GUID path
+/// is mounted, if it is mounted anywhere.
+fn volume_mount_path(volume: &[u16]) -> io::Result<Option<PathBuf>> {
+    let mut buf = vec![0u16; 260];
+    loop {
+        let mut len = 0;
+        let ok = unsafe {
+            GetVolumePathNamesForVolumeNameW(
+                volume.as_ptr(),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                &mut len,
+            )
+        };
+        if ok == 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(ERROR_MORE_DATA as i32) {
+                buf.resize(len as usize, 0);
+                continue;
+            }
+            return Err(err);
+        }
+        // The buffer is a list of NUL terminated paths that is itself
+        // terminated by an empty path.
+        let first = buf.split(|&c| c == 0).next().unwrap_or(&[]);
+        if first.is_empty() {
+            return Ok(None);
         }
+        return Ok(Some(PathBuf::from(OsString::from_wide(first))));
     }
 }